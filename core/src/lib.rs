@@ -26,6 +26,10 @@
 //! - *Unparking* refers to dequeuing a thread from a queue keyed by some address
 //! and resuming it.
 //!
+//! Asynchronous tasks can wait in the same queues as threads by parking a
+//! `Waker` with `park_waker` instead of suspending the current thread. Tasks
+//! and threads are then unparked by the same functions, in queue order.
+//!
 //! See the documentation of the individual functions for more details.
 //!
 //! # Building custom synchronization primitives
@@ -56,16 +60,29 @@
     feature(thread_local)
 )]
 
+#[cfg(feature = "lock_order_validation")]
+pub mod lock_order;
+#[cfg(feature = "parked_threads")]
+mod parked;
+mod parker;
 mod parking_lot;
 mod spinwait;
+#[cfg(feature = "stall_detection")]
+pub mod stall;
 mod thread_parker;
 mod util;
 mod word_lock;
 
+#[cfg(feature = "parked_threads")]
+pub use self::parked::{dump_parked_threads, ParkedThread};
+pub use self::parker::{ParkTimeoutResult, Parker, Unparker};
 pub use self::parking_lot::deadlock;
+#[cfg(feature = "parked_threads")]
+pub use self::parking_lot::parked_threads;
+pub use self::parking_lot::{cancel_park, park_waker};
 pub use self::parking_lot::{park, unpark_all, unpark_filter, unpark_one, unpark_requeue};
 pub use self::parking_lot::{
-    FilterOp, ParkResult, ParkToken, RequeueOp, UnparkResult, UnparkToken,
+    FilterOp, ParkResult, ParkToken, RequeueOp, UnparkResult, UnparkToken, WakerEntry,
 };
 pub use self::parking_lot::{DEFAULT_PARK_TOKEN, DEFAULT_UNPARK_TOKEN};
 pub use self::spinwait::SpinWait;
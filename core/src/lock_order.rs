@@ -0,0 +1,337 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! \[Experimental\] Lock order validation
+//!
+//! Enabled via the `lock_order_validation` feature flag.
+//!
+//! Every lock acquisition reported through `deadlock::acquire_resource` is
+//! recorded in a global graph: acquiring B while holding A adds the edge
+//! A → B. If a thread later acquires A while holding B, or more generally
+//! acquires a lock which can already reach one of the locks it holds in the
+//! graph, the two threads have used inconsistent lock orders and could
+//! deadlock if they ran concurrently. This is reported the first time it is
+//! observed, even if no deadlock actually occurred.
+//!
+//! Locks are identified by their key, which is usually their address. A lock
+//! which is freed and replaced by another one at the same address shares its
+//! history, which can lead to false positives. A resource acquired with
+//! `deadlock::acquire_resource_as` is identified by the key of the resource
+//! it is reported as, so a lock using several keys is validated as a single
+//! lock.
+
+use backtrace::Backtrace;
+use std::cell::UnsafeCell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+
+/// Description of two locks which have been acquired in inconsistent orders.
+pub struct LockOrderViolation {
+    first: usize,
+    second: usize,
+    thread_name: Option<String>,
+    backtrace: Backtrace,
+    established_backtrace: Backtrace,
+}
+
+impl LockOrderViolation {
+    /// The key of the lock which was previously acquired first.
+    pub fn first(&self) -> usize {
+        self.first
+    }
+
+    /// The key of the lock which was previously acquired after `first`, but
+    /// was held while acquiring `first` by the offending thread.
+    pub fn second(&self) -> usize {
+        self.second
+    }
+
+    /// The name of the thread which acquired the locks in the inverted order.
+    pub fn thread_name(&self) -> Option<&str> {
+        self.thread_name.as_ref().map(|name| &name[..])
+    }
+
+    /// Backtrace of the acquisition of `first` which inverted the order.
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+
+    /// Backtrace of the acquisition which recorded the first step of the
+    /// existing order from `first` to `second`. This is an acquisition of
+    /// either `second` or an intermediate lock while holding `first`.
+    pub fn established_backtrace(&self) -> &Backtrace {
+        &self.established_backtrace
+    }
+}
+
+impl fmt::Debug for LockOrderViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockOrderViolation")
+            .field("first", &self.first)
+            .field("second", &self.second)
+            .field("thread_name", &self.thread_name)
+            .field("backtrace", &self.backtrace)
+            .field("established_backtrace", &self.established_backtrace)
+            .finish()
+    }
+}
+
+impl fmt::Display for LockOrderViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "lock order violation: lock {:#x} acquired while holding lock {:#x}, \
+             but the opposite order was established earlier\n\
+             inverted order acquired at:\n{:?}\n\
+             order established at:\n{:?}",
+            self.first, self.second, self.backtrace, self.established_backtrace
+        )
+    }
+}
+
+struct Graph {
+    // For each lock, the locks which have been acquired while holding it and
+    // the backtrace of the first such acquisition.
+    edges: HashMap<usize, HashMap<usize, Backtrace>>,
+
+    // Pairs of locks which have already been reported.
+    reported: HashSet<(usize, usize)>,
+
+    // Violations not yet returned by `check_lock_order`.
+    violations: Vec<LockOrderViolation>,
+}
+
+impl Graph {
+    // Returns the path from `from` to `to` if there is one.
+    fn find_path(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        // Parent of each visited lock in the search, from which the path is
+        // rebuilt once `to` is found.
+        let mut parents = HashMap::new();
+        parents.insert(from, from);
+        let mut stack = vec![from];
+        while let Some(node) = stack.pop() {
+            if node == to {
+                let mut path = vec![to];
+                let mut node = to;
+                while node != from {
+                    node = parents[&node];
+                    path.push(node);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if let Some(next) = self.edges.get(&node) {
+                for &next in next.keys() {
+                    if let Entry::Vacant(entry) = parents.entry(next) {
+                        entry.insert(node);
+                        stack.push(next);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+static PANIC_ON_VIOLATION: AtomicBool = AtomicBool::new(false);
+
+// The graph is protected by a standard library lock since the locks built on
+// top of this crate are the ones being validated.
+fn graph() -> &'static RwLock<Graph> {
+    static GRAPH: AtomicPtr<RwLock<Graph>> = AtomicPtr::new(ptr::null_mut());
+
+    let mut graph = GRAPH.load(Ordering::Acquire);
+    if graph.is_null() {
+        let new_graph = Box::into_raw(Box::new(RwLock::new(Graph {
+            edges: HashMap::new(),
+            reported: HashSet::new(),
+            violations: Vec::new(),
+        })));
+        match GRAPH.compare_exchange(
+            ptr::null_mut(),
+            new_graph,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => graph = new_graph,
+            Err(old_graph) => {
+                // SAFETY: `new_graph` was created by `Box::into_raw` above and
+                // was never shared.
+                drop(unsafe { Box::from_raw(new_graph) });
+                graph = old_graph;
+            }
+        }
+    }
+
+    // SAFETY: The graph is never freed once it has been published.
+    unsafe { &*graph }
+}
+
+fn read_graph() -> RwLockReadGuard<'static, Graph> {
+    graph().read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write_graph() -> RwLockWriteGuard<'static, Graph> {
+    graph().write().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) struct LockOrderData {
+    // Locks currently held by the thread, in acquisition order
+    held: UnsafeCell<Vec<usize>>,
+
+    // Keys the locks of `held` were acquired with, at the same indices
+    keys: UnsafeCell<Vec<usize>>,
+}
+
+impl LockOrderData {
+    pub(crate) fn new() -> LockOrderData {
+        LockOrderData {
+            held: UnsafeCell::new(Vec::new()),
+            keys: UnsafeCell::new(Vec::new()),
+        }
+    }
+}
+
+// Records the acquisition of `lock` through `key`, which is a different key
+// for the secondary resources of a lock.
+//
+// Must be called from the thread which owns `data`.
+pub(crate) unsafe fn acquire_resource(data: &LockOrderData, key: usize, lock: usize) {
+    let held = &mut *data.held.get();
+
+    // Recursive acquisitions (shared or reentrant locks, or another key of a
+    // lock already held) don't add any ordering constraint.
+    if !held.is_empty() && !held.contains(&lock) {
+        check_and_record(held, lock);
+    }
+    held.push(lock);
+    (*data.keys.get()).push(key);
+}
+
+// Must be called from the thread which owns `data`.
+pub(crate) unsafe fn release_resource(data: &LockOrderData, key: usize) {
+    let keys = &mut *data.keys.get();
+    if let Some(p) = keys.iter().rposition(|&x| x == key) {
+        keys.remove(p);
+        (*data.held.get()).remove(p);
+    }
+}
+
+fn check_and_record(held: &[usize], key: usize) {
+    // Usually every lock held has already been followed by `key`, in which
+    // case there is nothing to check or record and the graph is only read.
+    {
+        let graph = read_graph();
+        let known = |prev: &usize| {
+            graph
+                .edges
+                .get(prev)
+                .and_then(|next| next.get(&key))
+                .is_some()
+        };
+        if held.iter().all(known) {
+            return;
+        }
+    }
+
+    let mut graph = write_graph();
+    let mut violation = None;
+    for &prev in held {
+        match graph.edges.get(&prev) {
+            Some(next) if next.contains_key(&key) => continue,
+            _ => {}
+        }
+
+        // Acquiring `key` after `prev` is an inversion if `key` was
+        // previously acquired before `prev`.
+        if let Some(path) = graph.find_path(key, prev) {
+            let pair = (key, prev);
+            if violation.is_none() && graph.reported.insert(pair) {
+                let mut established_backtrace = graph.edges[&path[0]][&path[1]].clone();
+                established_backtrace.resolve();
+                violation = Some(LockOrderViolation {
+                    first: key,
+                    second: prev,
+                    thread_name: thread::current().name().map(|name| name.to_owned()),
+                    backtrace: Backtrace::new(),
+                    established_backtrace,
+                });
+            }
+            continue;
+        }
+
+        graph
+            .edges
+            .entry(prev)
+            .or_default()
+            .insert(key, Backtrace::new_unresolved());
+    }
+
+    if let Some(violation) = violation {
+        if PANIC_ON_VIOLATION.load(Ordering::Relaxed) && !thread::panicking() {
+            drop(graph);
+            panic!("{}", violation);
+        }
+        graph.violations.push(violation);
+    }
+}
+
+/// Returns all lock order violations detected *since* the last call.
+///
+/// Each pair of locks is only reported once.
+pub fn check_lock_order() -> Vec<LockOrderViolation> {
+    let mut graph = write_graph();
+    graph.violations.drain(..).collect()
+}
+
+/// Sets whether a thread which acquires locks in an inconsistent order should
+/// panic immediately instead of recording the violation for
+/// `check_lock_order`. This is disabled by default.
+///
+/// The panic occurs after the offending lock has been acquired, so that lock
+/// is left locked.
+pub fn set_panic_on_violation(panic: bool) {
+    PANIC_ON_VIOLATION.store(panic, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deadlock::{acquire_resource, release_resource};
+    use std::thread;
+
+    // Nothing else in this crate reports resources, so small integers can be
+    // used as keys without clashing with real locks.
+    #[test]
+    fn panic_on_violation() {
+        unsafe {
+            acquire_resource(1);
+            acquire_resource(2);
+            release_resource(2);
+            release_resource(1);
+        }
+
+        super::set_panic_on_violation(true);
+        let result = thread::spawn(|| unsafe {
+            acquire_resource(2);
+            acquire_resource(1);
+        })
+        .join();
+        super::set_panic_on_violation(false);
+
+        let message = result.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(
+            message.starts_with("lock order violation: lock 0x1 acquired while holding lock 0x2")
+        );
+        assert!(super::check_lock_order().is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::ParkToken;
+use std::fmt;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Description of a thread currently parked, as returned by
+/// `parked_threads`.
+///
+/// Enabled via the `parked_threads` feature flag.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ParkedThread {
+    pub(crate) bucket: usize,
+    pub(crate) key: usize,
+    pub(crate) park_token: ParkToken,
+    pub(crate) timed: bool,
+    pub(crate) parked_for: Duration,
+}
+
+impl ParkedThread {
+    /// The key the thread is parked on
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    /// The park token the thread was parked with
+    pub fn park_token(&self) -> ParkToken {
+        self.park_token
+    }
+
+    /// Whether the thread was parked with a timeout
+    pub fn is_timed(&self) -> bool {
+        self.timed
+    }
+
+    /// How long the thread had been parked when it was collected
+    pub fn parked_for(&self) -> Duration {
+        self.parked_for
+    }
+
+    /// Index of the bucket of the hash table the thread is queued in
+    pub fn bucket(&self) -> usize {
+        self.bucket
+    }
+}
+
+impl fmt::Display for ParkedThread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "parked on {:#x} (bucket {}) for {:?}, park token {:#x}",
+            self.key, self.bucket, self.parked_for, self.park_token.0
+        )?;
+        if self.timed {
+            write!(f, ", with a timeout")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns a human-readable description of every thread currently parked,
+/// one per line.
+///
+/// This has the same limitations as `parked_threads`, which it uses. In
+/// particular it locks the buckets and allocates, so it must not be called
+/// from a signal handler. To dump the parked threads on a signal, the handler
+/// should instead only wake up a dedicated thread, for example by writing to
+/// a pipe, which then calls this function and prints the result.
+///
+/// Enabled via the `parked_threads` feature flag.
+pub fn dump_parked_threads() -> String {
+    let threads = crate::parked_threads();
+    let mut dump = format!("{} parked threads\n", threads.len());
+    for thread in &threads {
+        // Writing to a String can't fail
+        let _ = writeln!(dump, "  {}", thread);
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{park, unpark_all, ParkResult, ParkToken, DEFAULT_UNPARK_TOKEN};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn parked_threads() {
+        static KEY: AtomicBool = AtomicBool::new(false);
+        let key = &KEY as *const _ as usize;
+
+        let parked = Arc::new(AtomicBool::new(false));
+        let parked2 = parked.clone();
+        let t = thread::spawn(move || unsafe {
+            park(
+                key,
+                || true,
+                || parked2.store(true, Ordering::Relaxed),
+                |_, _| {},
+                ParkToken(42),
+                None,
+            )
+        });
+        while !parked.load(Ordering::Relaxed) {
+            thread::yield_now();
+        }
+        thread::sleep(Duration::from_millis(20));
+
+        // Other tests may park threads concurrently
+        let threads: Vec<_> = crate::parked_threads()
+            .into_iter()
+            .filter(|t| t.key() == key)
+            .collect();
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].park_token(), ParkToken(42));
+        assert!(!threads[0].is_timed());
+        assert!(threads[0].parked_for() >= Duration::from_millis(20));
+        assert!(super::dump_parked_threads().contains(&format!(
+            "parked on {:#x} (bucket {})",
+            key,
+            threads[0].bucket()
+        )));
+
+        unsafe { unpark_all(key, DEFAULT_UNPARK_TOKEN) };
+        assert_eq!(
+            t.join().unwrap(),
+            ParkResult::Unparked(DEFAULT_UNPARK_TOKEN)
+        );
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::thread_parker::{ThreadParker, ThreadParkerT, UnparkHandleT};
+use crate::word_lock::WordLock;
+use core::{
+    fmt,
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// No token is available and the thread isn't parked.
+const EMPTY: usize = 0;
+// The thread is parked, or about to be.
+const PARKED: usize = 1;
+// A token is available and the next park will consume it.
+const NOTIFIED: usize = 2;
+
+/// Result of `Parker::park_timeout` and `Parker::park_deadline`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ParkTimeoutResult {
+    /// The token was consumed, either because it was already available or
+    /// because `Unparker::unpark` was called while parked.
+    Unparked,
+
+    /// The timeout expired before the token became available.
+    TimedOut,
+}
+
+impl ParkTimeoutResult {
+    /// Returns true if the timeout expired before the token became available.
+    #[inline]
+    pub fn timed_out(self) -> bool {
+        self == ParkTimeoutResult::TimedOut
+    }
+}
+
+struct Inner {
+    state: AtomicUsize,
+
+    // Protects the transitions from and to `PARKED` as well as the calls to
+    // the thread parker, which has the same requirements as when it is used
+    // with the queue lock of a bucket.
+    lock: WordLock,
+
+    parker: ThreadParker,
+}
+
+// SAFETY: The thread parker is only used while holding `lock`, or by the
+// thread which created the `Parker` while it is parked.
+unsafe impl Send for Inner {}
+unsafe impl Sync for Inner {}
+
+/// A thread parking primitive based on a token, like `std::thread::park`.
+///
+/// Each `Parker` has a token which is initially not available. `park` blocks
+/// until the token becomes available and consumes it, and `Unparker::unpark`
+/// makes the token available. An unpark which happens before the park is
+/// therefore not lost, and several unparks only make one token available.
+///
+/// Unlike `std::thread::park`, parking never returns spuriously: it only
+/// returns once the token was consumed, or the timeout expired for
+/// `park_timeout` and `park_deadline`.
+///
+/// The `Parker` can only be used by the thread which created it, since some
+/// platforms bind the underlying thread parker to the creating thread. It is
+/// therefore neither `Send` nor `Sync`, while any number of threads can
+/// unpark it through `Unparker` handles.
+///
+/// # Examples
+///
+/// ```
+/// use parking_lot_core::Parker;
+/// use std::thread;
+///
+/// let parker = Parker::new();
+/// let unparker = parker.unparker();
+///
+/// thread::spawn(move || {
+///     unparker.unpark();
+/// });
+///
+/// parker.park();
+/// ```
+pub struct Parker {
+    inner: Arc<Inner>,
+
+    // Only the thread which created the parker may park it.
+    _not_send_sync: PhantomData<*const ()>,
+}
+
+impl Parker {
+    /// Creates a new parker, whose token is not available.
+    #[inline]
+    pub fn new() -> Parker {
+        Parker {
+            inner: Arc::new(Inner {
+                state: AtomicUsize::new(EMPTY),
+                lock: WordLock::new(),
+                parker: ThreadParker::new(),
+            }),
+            _not_send_sync: PhantomData,
+        }
+    }
+
+    /// Returns a handle which can be used to unpark this parker.
+    #[inline]
+    pub fn unparker(&self) -> Unparker {
+        Unparker {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Blocks the current thread until the token is available, and consumes
+    /// it.
+    #[inline]
+    pub fn park(&self) {
+        if !self.try_consume() {
+            let result = self.park_slow(None);
+            debug_assert_eq!(result, ParkTimeoutResult::Unparked);
+        }
+    }
+
+    /// Blocks the current thread until the token is available and consumes
+    /// it, or until the timeout has elapsed.
+    #[inline]
+    pub fn park_timeout(&self, timeout: Duration) -> ParkTimeoutResult {
+        if self.try_consume() {
+            return ParkTimeoutResult::Unparked;
+        }
+        // A timeout which can't be represented is the same as no timeout.
+        self.park_slow(Instant::now().checked_add(timeout))
+    }
+
+    /// Blocks the current thread until the token is available and consumes
+    /// it, or until the deadline is reached.
+    #[inline]
+    pub fn park_deadline(&self, deadline: Instant) -> ParkTimeoutResult {
+        if self.try_consume() {
+            return ParkTimeoutResult::Unparked;
+        }
+        self.park_slow(Some(deadline))
+    }
+
+    // Consumes the token if it is available.
+    #[inline]
+    fn try_consume(&self) -> bool {
+        self.inner
+            .state
+            .compare_exchange(NOTIFIED, EMPTY, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    #[cold]
+    fn park_slow(&self, deadline: Option<Instant>) -> ParkTimeoutResult {
+        let inner = &*self.inner;
+        inner.lock.lock();
+        if inner
+            .state
+            .compare_exchange(EMPTY, PARKED, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            // The token became available in the meantime. Only the thread
+            // which created the parker consumes it, so the state can't change
+            // again.
+            inner.state.store(EMPTY, Ordering::Relaxed);
+            // SAFETY: We hold the lock here, as required
+            unsafe { inner.lock.unlock() };
+            return ParkTimeoutResult::Unparked;
+        }
+
+        // SAFETY: The parker is only prepared and parked by the thread which
+        // created it, and it is only unparked while `lock` is held.
+        unsafe {
+            inner.parker.prepare_park();
+            inner.lock.unlock();
+
+            let unparked = match deadline {
+                Some(deadline) => inner.parker.park_until(deadline),
+                None => {
+                    inner.parker.park();
+                    true
+                }
+            };
+            if unparked {
+                return ParkTimeoutResult::Unparked;
+            }
+
+            // We timed out, unless an unparker got to us before we could
+            // take the lock again.
+            inner.lock.lock();
+            let timed_out = inner.parker.timed_out();
+            if timed_out {
+                inner.state.store(EMPTY, Ordering::Relaxed);
+            }
+            inner.lock.unlock();
+            if timed_out {
+                ParkTimeoutResult::TimedOut
+            } else {
+                ParkTimeoutResult::Unparked
+            }
+        }
+    }
+}
+
+impl Default for Parker {
+    #[inline]
+    fn default() -> Parker {
+        Parker::new()
+    }
+}
+
+impl fmt::Debug for Parker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Parker { .. }")
+    }
+}
+
+/// A handle which unparks a `Parker`, created with `Parker::unparker`.
+///
+/// Unparkers can be cloned and sent to other threads.
+#[derive(Clone)]
+pub struct Unparker {
+    inner: Arc<Inner>,
+}
+
+impl Unparker {
+    /// Makes the token of the parker available, waking up its thread if it is
+    /// parked.
+    #[inline]
+    pub fn unpark(&self) {
+        let state = &self.inner.state;
+        if state
+            .compare_exchange(EMPTY, NOTIFIED, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            self.unpark_slow();
+        }
+    }
+
+    #[cold]
+    fn unpark_slow(&self) {
+        let inner = &*self.inner;
+        inner.lock.lock();
+        let handle = match inner.state.load(Ordering::Relaxed) {
+            // Hand the token directly to the parked thread
+            PARKED => {
+                inner.state.store(EMPTY, Ordering::Release);
+                // SAFETY: We hold the lock and the thread is parked.
+                Some(unsafe { inner.parker.unpark_lock() })
+            }
+
+            // The thread timed out or consumed the token in the meantime
+            _ => {
+                inner.state.store(NOTIFIED, Ordering::Release);
+                None
+            }
+        };
+        // SAFETY: We hold the lock here, as required
+        unsafe { inner.lock.unlock() };
+
+        // Wake up the thread after releasing the lock
+        if let Some(handle) = handle {
+            // SAFETY: The parker is kept alive by `self.inner`.
+            unsafe { handle.unpark() };
+        }
+    }
+}
+
+impl fmt::Debug for Unparker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Unparker { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParkTimeoutResult, Parker};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn unpark_before_park() {
+        let parker = Parker::new();
+        let unparker = parker.unparker();
+        unparker.unpark();
+        unparker.unpark();
+        parker.park();
+
+        // Several unparks only make one token available
+        let start = Instant::now();
+        assert_eq!(
+            parker.park_timeout(Duration::from_millis(20)),
+            ParkTimeoutResult::TimedOut
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn unpark_while_parked() {
+        let parker = Parker::new();
+        for _ in 0..100 {
+            let unparker = parker.unparker();
+            let t = thread::spawn(move || unparker.unpark());
+            assert_eq!(
+                parker.park_timeout(Duration::from_secs(10)),
+                ParkTimeoutResult::Unparked
+            );
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn park_deadline() {
+        let parker = Parker::new();
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert!(parker.park_deadline(deadline).timed_out());
+        assert!(Instant::now() >= deadline);
+    }
+}
@@ -12,6 +12,7 @@ use core::{
     cell::{Cell, UnsafeCell},
     ptr,
     sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+    task::{Poll, Waker},
 };
 use smallvec::SmallVec;
 use std::time::{Duration, Instant};
@@ -143,18 +144,50 @@ struct ThreadData {
     // Is the thread parked with a timeout?
     parked_with_timeout: Cell<bool>,
 
+    // Waker to notify instead of unparking a thread. This is only set while
+    // an entry queued by `park_waker` is in the queue.
+    waker: UnsafeCell<Option<Waker>>,
+
     // Extra data for deadlock detection
     #[cfg(feature = "deadlock_detection")]
     deadlock_data: deadlock::DeadlockData,
+
+    // Locks held by this thread, for lock order validation
+    #[cfg(feature = "lock_order_validation")]
+    lock_order_data: crate::lock_order::LockOrderData,
+
+    // When this thread last parked
+    #[cfg(feature = "parked_threads")]
+    parked_at: Cell<Instant>,
+
+    // Where this thread last parked, for stall detection
+    #[cfg(feature = "stall_detection")]
+    stall_data: crate::stall::StallData,
+
+    // Whether this is the queue entry of a task parked with `park_waker`
+    // rather than the data of a thread
+    task: bool,
 }
 
 impl ThreadData {
     fn new() -> ThreadData {
-        // Keep track of the total number of live ThreadData objects and resize
-        // the hash table accordingly.
+        // Keep track of the total number of live threads and resize the hash
+        // table accordingly.
         let num_threads = NUM_THREADS.fetch_add(1, Ordering::Relaxed) + 1;
         grow_hashtable(num_threads);
 
+        ThreadData::new_entry(false)
+    }
+
+    // Creates the queue entry of a task parked with `park_waker`. Tasks are
+    // not threads, so they don't count towards the size of the hash table and
+    // don't record any information about the thread which created them.
+    fn new_task() -> ThreadData {
+        ThreadData::new_entry(true)
+    }
+
+    #[inline]
+    fn new_entry(task: bool) -> ThreadData {
         ThreadData {
             parker: ThreadParker::new(),
             key: AtomicUsize::new(0),
@@ -162,8 +195,45 @@ impl ThreadData {
             unpark_token: Cell::new(DEFAULT_UNPARK_TOKEN),
             park_token: Cell::new(DEFAULT_PARK_TOKEN),
             parked_with_timeout: Cell::new(false),
+            waker: UnsafeCell::new(None),
             #[cfg(feature = "deadlock_detection")]
-            deadlock_data: deadlock::DeadlockData::new(),
+            deadlock_data: deadlock::DeadlockData::new(task),
+            #[cfg(feature = "lock_order_validation")]
+            lock_order_data: crate::lock_order::LockOrderData::new(),
+            #[cfg(feature = "parked_threads")]
+            parked_at: Cell::new(Instant::now()),
+            #[cfg(feature = "stall_detection")]
+            stall_data: crate::stall::StallData::new(task),
+            task,
+        }
+    }
+
+    // Prepares this queue entry to be woken up. This should be called while
+    // holding the queue lock, after the entry has been removed from the queue.
+    #[inline]
+    unsafe fn unpark_lock(&self) -> UnparkHandle {
+        match (*self.waker.get()).take() {
+            Some(waker) => UnparkHandle::Task(waker),
+            None => UnparkHandle::Thread(self.parker.unpark_lock()),
+        }
+    }
+}
+
+// Handle for a queue entry that is about to be woken up, which is either a
+// parked thread or a task parked with `park_waker`.
+enum UnparkHandle {
+    Thread(<ThreadParker as ThreadParkerT>::UnparkHandle),
+    Task(Waker),
+}
+
+impl UnparkHandle {
+    // Wakes up the entry. This should be called after the queue lock is
+    // released.
+    #[inline]
+    unsafe fn unpark(self) {
+        match self {
+            UnparkHandle::Thread(handle) => handle.unpark(),
+            UnparkHandle::Task(waker) => waker.wake(),
         }
     }
 }
@@ -185,7 +255,10 @@ fn with_thread_data<T>(f: impl FnOnce(&ThreadData) -> T) -> T {
 
 impl Drop for ThreadData {
     fn drop(&mut self) {
-        NUM_THREADS.fetch_sub(1, Ordering::Relaxed);
+        if !self.task {
+            deadlock::on_thread_exit(self);
+            NUM_THREADS.fetch_sub(1, Ordering::Relaxed);
+        }
     }
 }
 
@@ -574,11 +647,19 @@ pub unsafe fn park(
 ) -> ParkResult {
     // Grab our thread data, this also ensures that the hash table exists
     with_thread_data(|thread_data| {
+        // Record when and where we park, before locking the bucket since
+        // capturing a backtrace is slow
+        #[cfg(feature = "parked_threads")]
+        thread_data.parked_at.set(Instant::now());
+        #[cfg(feature = "stall_detection")]
+        crate::stall::on_park(&thread_data.stall_data);
+
         // Lock the bucket for the given key
         let bucket = lock_bucket(key);
 
         // If the validation function fails, just return
         if !validate() {
+            deadlock::on_park_invalid(thread_data);
             // SAFETY: We hold the lock here, as required
             bucket.mutex.unlock();
             return ParkResult::Invalid;
@@ -589,6 +670,7 @@ pub unsafe fn park(
         thread_data.next_in_queue.set(ptr::null());
         thread_data.key.store(key, Ordering::Relaxed);
         thread_data.park_token.set(park_token);
+        deadlock::on_park(thread_data);
         thread_data.parker.prepare_park();
         if !bucket.queue_head.get().is_null() {
             (*bucket.queue_tail.get()).next_in_queue.set(thread_data);
@@ -610,7 +692,23 @@ pub unsafe fn park(
             None => {
                 thread_data.parker.park();
                 // call deadlock detection on_unpark hook
-                deadlock::on_unpark(thread_data);
+                if !deadlock::on_unpark(thread_data) {
+                    // This thread was chosen to break a deadlock, so give up
+                    // waiting as if we had timed out, unless we were unparked
+                    // in the meantime.
+                    let (key, bucket) = lock_bucket_checked(&thread_data.key);
+                    let result = if is_queued(bucket, thread_data) {
+                        let was_last_thread = remove_from_queue(bucket, key, thread_data);
+                        timed_out(key, was_last_thread);
+                        ParkResult::TimedOut
+                    } else {
+                        ParkResult::Unparked(thread_data.unpark_token.get())
+                    };
+                    deadlock::on_wait_aborted(thread_data);
+                    // SAFETY: We hold the lock here, as required
+                    bucket.mutex.unlock();
+                    return result;
+                }
                 true
             }
         };
@@ -632,53 +730,141 @@ pub unsafe fn park(
             return ParkResult::Unparked(thread_data.unpark_token.get());
         }
 
-        // We timed out, so we now need to remove our thread from the queue
-        let mut link = &bucket.queue_head;
+        // We timed out, so we now need to remove our thread from the queue.
+        // Then invoke the callback to indicate that we timed out, and whether
+        // we were the last thread on the queue.
+        let was_last_thread = remove_from_queue(bucket, key, thread_data);
+        timed_out(key, was_last_thread);
+
+        // Unlock the bucket, we are done
+        // SAFETY: We hold the lock here, as required
+        bucket.mutex.unlock();
+        ParkResult::TimedOut
+    })
+}
+
+// Calls `f` with the index of the bucket and the thread data of every thread
+// currently parked. Each bucket is locked in turn while `f` is called for the
+// threads queued in it.
+#[cfg(feature = "parked_threads")]
+fn for_each_parked_thread_data(mut f: impl FnMut(usize, &ThreadData)) {
+    let table = get_hashtable();
+    for (index, bucket) in table.entries.iter().enumerate() {
+        bucket.mutex.lock();
         let mut current = bucket.queue_head.get();
-        let mut previous = ptr::null();
-        let mut was_last_thread = true;
         while !current.is_null() {
-            if current == thread_data {
-                let next = (*current).next_in_queue.get();
-                link.set(next);
-                if bucket.queue_tail.get() == current {
-                    bucket.queue_tail.set(previous);
-                } else {
-                    // Scan the rest of the queue to see if there are any other
-                    // entries with the given key.
-                    let mut scan = next;
-                    while !scan.is_null() {
-                        if (*scan).key.load(Ordering::Relaxed) == key {
-                            was_last_thread = false;
-                            break;
-                        }
-                        scan = (*scan).next_in_queue.get();
-                    }
+            // SAFETY: Entries stay valid while they are queued in a bucket
+            // which we hold the lock of.
+            unsafe {
+                // Tasks parked with `park_waker` are not threads.
+                if (*(*current).waker.get()).is_none() {
+                    f(index, &*current);
                 }
+                current = (*current).next_in_queue.get();
+            }
+        }
+        // SAFETY: We hold the lock here, as required
+        unsafe { bucket.mutex.unlock() };
+    }
+}
 
-                // Callback to indicate that we timed out, and whether we were the
-                // last thread on the queue.
-                timed_out(key, was_last_thread);
-                break;
+#[cfg(feature = "parked_threads")]
+fn parked_thread(bucket: usize, thread_data: &ThreadData) -> crate::parked::ParkedThread {
+    crate::parked::ParkedThread {
+        bucket,
+        key: thread_data.key.load(Ordering::Relaxed),
+        park_token: thread_data.park_token.get(),
+        timed: thread_data.parked_with_timeout.get(),
+        // The thread recorded `parked_at` before locking the bucket, so it
+        // can't be later than now.
+        parked_for: Instant::now() - thread_data.parked_at.get(),
+    }
+}
+
+/// Returns every thread currently parked, in bucket order.
+///
+/// Each bucket of the parking lot is locked in turn, so this doesn't give a
+/// consistent snapshot of all the parked threads, and a thread which is
+/// requeued or moved to a new hash table while they are collected can be
+/// missed. Tasks parked with `park_waker` are not threads and are not
+/// included.
+///
+/// Since this locks the buckets, it must not be called from a signal handler
+/// or while the current thread is inside a parking lot callback.
+#[cfg(feature = "parked_threads")]
+pub fn parked_threads() -> Vec<crate::parked::ParkedThread> {
+    let mut threads = Vec::new();
+    for_each_parked_thread_data(|bucket, thread_data| {
+        threads.push(parked_thread(bucket, thread_data));
+    });
+    threads
+}
+
+// Calls `f` with the description and the stall data of every thread currently
+// parked, while its bucket is locked.
+#[cfg(feature = "stall_detection")]
+pub(crate) fn for_each_parked_thread(
+    mut f: impl FnMut(&crate::parked::ParkedThread, &crate::stall::StallData),
+) {
+    for_each_parked_thread_data(|bucket, thread_data| {
+        f(&parked_thread(bucket, thread_data), &thread_data.stall_data);
+    });
+}
+
+// Returns whether the given entry is in the queue of a locked bucket.
+#[inline]
+unsafe fn is_queued(bucket: &Bucket, thread_data: *const ThreadData) -> bool {
+    let mut current = bucket.queue_head.get();
+    while !current.is_null() {
+        if current == thread_data {
+            return true;
+        }
+        current = (*current).next_in_queue.get();
+    }
+    false
+}
+
+// Removes the given entry from the queue of a locked bucket and returns
+// whether it was the last entry in the queue with the given key.
+#[inline]
+unsafe fn remove_from_queue(bucket: &Bucket, key: usize, thread_data: *const ThreadData) -> bool {
+    let mut link = &bucket.queue_head;
+    let mut current = bucket.queue_head.get();
+    let mut previous = ptr::null();
+    let mut was_last_thread = true;
+    while !current.is_null() {
+        if current == thread_data {
+            let next = (*current).next_in_queue.get();
+            link.set(next);
+            if bucket.queue_tail.get() == current {
+                bucket.queue_tail.set(previous);
             } else {
-                if (*current).key.load(Ordering::Relaxed) == key {
-                    was_last_thread = false;
+                // Scan the rest of the queue to see if there are any other
+                // entries with the given key.
+                let mut scan = next;
+                while !scan.is_null() {
+                    if (*scan).key.load(Ordering::Relaxed) == key {
+                        was_last_thread = false;
+                        break;
+                    }
+                    scan = (*scan).next_in_queue.get();
                 }
-                link = &(*current).next_in_queue;
-                previous = current;
-                current = link.get();
             }
+            break;
+        } else {
+            if (*current).key.load(Ordering::Relaxed) == key {
+                was_last_thread = false;
+            }
+            link = &(*current).next_in_queue;
+            previous = current;
+            current = link.get();
         }
+    }
 
-        // There should be no way for our thread to have been removed from the queue
-        // if we timed out.
-        debug_assert!(!current.is_null());
-
-        // Unlock the bucket, we are done
-        // SAFETY: We hold the lock here, as required
-        bucket.mutex.unlock();
-        ParkResult::TimedOut
-    })
+    // There should be no way for the entry to have been removed from the
+    // queue by someone else.
+    debug_assert!(!current.is_null());
+    was_last_thread
 }
 
 /// Unparks one thread from the queue associated with the given key.
@@ -746,7 +932,7 @@ pub unsafe fn unpark_one(
             // times out. Then we unlock the queue since we don't want to keep
             // the queue locked while we perform a system call. Finally we wake
             // up the parked thread.
-            let handle = (*current).parker.unpark_lock();
+            let handle = (*current).unpark_lock();
             // SAFETY: We hold the lock here, as required
             bucket.mutex.unlock();
             handle.unpark();
@@ -802,7 +988,7 @@ pub unsafe fn unpark_all(key: usize, unpark_token: UnparkToken) -> usize {
             // Don't wake up threads while holding the queue lock. See comment
             // in unpark_one. For now just record which threads we need to wake
             // up.
-            threads.push((*current).parker.unpark_lock());
+            threads.push((*current).unpark_lock());
             current = next;
         } else {
             link = &(*current).next_in_queue;
@@ -946,7 +1132,7 @@ pub unsafe fn unpark_requeue(
     // See comment in unpark_one for why we mess with the locking
     if let Some(wakeup_thread) = wakeup_thread {
         (*wakeup_thread).unpark_token.set(token);
-        let handle = (*wakeup_thread).parker.unpark_lock();
+        let handle = (*wakeup_thread).unpark_lock();
         // SAFETY: Both buckets are locked, as required.
         unlock_bucket_pair(bucket_from, bucket_to);
         handle.unpark();
@@ -1045,7 +1231,7 @@ pub unsafe fn unpark_filter(
     // them for unparking.
     for t in threads.iter_mut() {
         (*t.0).unpark_token.set(token);
-        t.1 = Some((*t.0).parker.unpark_lock());
+        t.1 = Some((*t.0).unpark_lock());
     }
 
     // SAFETY: We hold the lock here, as required
@@ -1060,6 +1246,217 @@ pub unsafe fn unpark_filter(
     result
 }
 
+/// A queue entry which allows an asynchronous task to wait in a parking lot
+/// queue using `park_waker`.
+///
+/// Each task that waits in a queue needs its own entry. An entry is typically
+/// stored in the future that is waiting and is reused across polls. Its queue
+/// storage is heap-allocated, so the entry may be moved freely, even while the
+/// task is parked.
+///
+/// Creating an entry costs one heap allocation of roughly the size of the
+/// per-thread data used by `park`, but entries are not counted as threads:
+/// they don't make the parking lot's hash table grow. Many pending tasks are
+/// therefore cheap to create, although they make the queues they wait in
+/// longer.
+///
+/// Dropping an entry while the task is still parked removes it from the queue
+/// without notifying anyone. Use `cancel_park` instead to be notified of
+/// whether the task was the last one in the queue, or whether it was unparked
+/// before it could be removed.
+pub struct WakerEntry {
+    data: Box<ThreadData>,
+
+    // Whether this entry was queued by `park_waker` and the result of that park
+    // has not been observed yet.
+    parked: bool,
+}
+
+// The `ThreadData` of an entry is only ever accessed by its owner or while
+// holding the lock of the bucket it is queued in.
+unsafe impl Send for WakerEntry {}
+unsafe impl Sync for WakerEntry {}
+
+impl WakerEntry {
+    /// Creates a new queue entry which is not parked.
+    #[inline]
+    pub fn new() -> WakerEntry {
+        WakerEntry {
+            data: Box::new(ThreadData::new_task()),
+            parked: false,
+        }
+    }
+
+    /// Returns true if the entry was parked with `park_waker` and the result
+    /// of that park has not been observed yet through `park_waker` or
+    /// `cancel_park`.
+    #[inline]
+    pub fn is_parked(&self) -> bool {
+        self.parked
+    }
+}
+
+impl Default for WakerEntry {
+    #[inline]
+    fn default() -> WakerEntry {
+        WakerEntry::new()
+    }
+}
+
+impl Drop for WakerEntry {
+    #[inline]
+    fn drop(&mut self) {
+        // SAFETY: the callback does nothing
+        unsafe {
+            cancel_park(self, |_, _| {});
+        }
+    }
+}
+
+/// Parks an asynchronous task in the queue associated with the given key, or
+/// checks whether a task which was previously parked has been unparked.
+///
+/// This is the asynchronous counterpart of `park`: instead of suspending the
+/// current thread, a clone of `waker` is added to the queue together with the
+/// given `WakerEntry`. Tasks and threads share the same queues, so
+/// `unpark_one`, `unpark_all`, `unpark_requeue` and `unpark_filter` unpark
+/// tasks and threads alike, in the order in which they were parked. Unparking
+/// a task wakes its waker, after which the task is expected to call
+/// `park_waker` again with the same entry to retrieve the result.
+///
+/// If `entry` is not parked, the `validate` function is called while the queue
+/// is locked and can abort the operation by returning false, in which case
+/// `Poll::Ready(ParkResult::Invalid)` is returned. If `validate` returns true
+/// then the task is appended to the queue and `Poll::Pending` is returned.
+///
+/// If `entry` is already parked, `key`, `validate` and `park_token` are
+/// ignored. If the task has been unparked since then
+/// `Poll::Ready(ParkResult::Unparked(token))` is returned with the token passed
+/// by the unparker. Otherwise `waker` replaces the waker that was previously
+/// registered and `Poll::Pending` is returned.
+///
+/// There is no timeout support: a task which wants to stop waiting should call
+/// `cancel_park`.
+///
+/// # Safety
+///
+/// You should only call this function with an address that you control, since
+/// you could otherwise interfere with the operation of other synchronization
+/// primitives.
+///
+/// The `validate` function is called while the queue is locked and must not
+/// panic or call into any function in `parking_lot`.
+#[inline]
+pub unsafe fn park_waker(
+    key: usize,
+    validate: impl FnOnce() -> bool,
+    entry: &mut WakerEntry,
+    waker: &Waker,
+    park_token: ParkToken,
+) -> Poll<ParkResult> {
+    // Clone the waker before locking the bucket since cloning or dropping a
+    // waker can run arbitrary code.
+    let waker = waker.clone();
+    let thread_data = &*entry.data;
+
+    if entry.parked {
+        // Lock our bucket. Our key may have changed if we were requeued.
+        let (_, bucket) = lock_bucket_checked(&thread_data.key);
+
+        // If our waker has been taken then we were unparked, otherwise just
+        // register the new waker.
+        let old_waker = (*thread_data.waker.get()).take();
+        if old_waker.is_none() {
+            // SAFETY: We hold the lock here, as required
+            bucket.mutex.unlock();
+            entry.parked = false;
+            return Poll::Ready(ParkResult::Unparked(thread_data.unpark_token.get()));
+        }
+        *thread_data.waker.get() = Some(waker);
+        // SAFETY: We hold the lock here, as required
+        bucket.mutex.unlock();
+        drop(old_waker);
+        return Poll::Pending;
+    }
+
+    // Lock the bucket for the given key
+    let bucket = lock_bucket(key);
+
+    // If the validation function fails, just return
+    if !validate() {
+        // SAFETY: We hold the lock here, as required
+        bucket.mutex.unlock();
+        return Poll::Ready(ParkResult::Invalid);
+    }
+
+    // Append our entry to the queue and unlock the bucket
+    thread_data.parked_with_timeout.set(false);
+    thread_data.next_in_queue.set(ptr::null());
+    thread_data.key.store(key, Ordering::Relaxed);
+    thread_data.park_token.set(park_token);
+    *thread_data.waker.get() = Some(waker);
+    if !bucket.queue_head.get().is_null() {
+        (*bucket.queue_tail.get()).next_in_queue.set(thread_data);
+    } else {
+        bucket.queue_head.set(thread_data);
+    }
+    bucket.queue_tail.set(thread_data);
+    // SAFETY: We hold the lock here, as required
+    bucket.mutex.unlock();
+
+    entry.parked = true;
+    Poll::Pending
+}
+
+/// Removes a task which was parked with `park_waker` from its queue.
+///
+/// If the task is still in the queue, it is removed and the `cancelled`
+/// function is called while the queue is locked. It is passed the key of the
+/// queue the task was in, which may be different from the original key if
+/// `unpark_requeue` was called, and a bool which indicates whether it was the
+/// last task or thread in the queue. `None` is returned in this case.
+///
+/// If the task was unparked before it could be removed, `cancelled` is not
+/// called and the token passed by the unparker is returned instead. This
+/// allows the caller to release any resource that was handed off to the task.
+///
+/// This function does nothing and returns `None` if `entry` is not parked.
+///
+/// # Safety
+///
+/// The `cancelled` function is called while the queue is locked and must not
+/// panic or call into any function in `parking_lot`.
+#[inline]
+pub unsafe fn cancel_park(
+    entry: &mut WakerEntry,
+    cancelled: impl FnOnce(usize, bool),
+) -> Option<UnparkToken> {
+    if !entry.parked {
+        return None;
+    }
+    entry.parked = false;
+    let thread_data = &*entry.data;
+
+    // Lock our bucket. Our key may have changed if we were requeued.
+    let (key, bucket) = lock_bucket_checked(&thread_data.key);
+
+    // If our waker has been taken then we were already unparked
+    let waker = (*thread_data.waker.get()).take();
+    if waker.is_none() {
+        // SAFETY: We hold the lock here, as required
+        bucket.mutex.unlock();
+        return Some(thread_data.unpark_token.get());
+    }
+
+    let was_last_thread = remove_from_queue(bucket, key, thread_data);
+    cancelled(key, was_last_thread);
+
+    // SAFETY: We hold the lock here, as required
+    bucket.mutex.unlock();
+    drop(waker);
+    None
+}
+
 /// \[Experimental\] Deadlock detection
 ///
 /// Enabled via the `deadlock_detection` feature flag.
@@ -1069,21 +1466,127 @@ pub mod deadlock {
 
     #[cfg(feature = "deadlock_detection")]
     pub(super) use super::deadlock_impl::DeadlockData;
+    #[cfg(feature = "deadlock_detection")]
+    pub use super::deadlock_impl::{
+        DeadlockReport, DeadlockedThread, ExitedThread, HeldResource, ThreadReport, WaitEdge,
+    };
+    use core::fmt;
+
+    /// The kind of lock a resource belongs to, and how it is held or waited
+    /// for. This is only used to describe resources in reports.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum ResourceKind {
+        /// An exclusive lock. A lock which can be acquired recursively by the
+        /// same thread, such as `ReentrantMutex`, is reported as a mutex too.
+        Mutex,
+        /// A reader-writer lock in shared mode.
+        RwLockShared,
+        /// A reader-writer lock in exclusive mode.
+        RwLockExclusive,
+        /// A reader-writer lock in upgradable mode.
+        RwLockUpgradable,
+        /// A condition variable.
+        Condvar,
+        /// A one-time initialization, held by the thread running the
+        /// initializer.
+        Once,
+        /// A resource which was acquired or waited for without specifying
+        /// its kind.
+        Other,
+    }
+
+    impl fmt::Display for ResourceKind {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match *self {
+                ResourceKind::Mutex => "mutex",
+                ResourceKind::RwLockShared => "rwlock shared",
+                ResourceKind::RwLockExclusive => "rwlock exclusive",
+                ResourceKind::RwLockUpgradable => "rwlock upgradable",
+                ResourceKind::Condvar => "condvar",
+                ResourceKind::Once => "once",
+                ResourceKind::Other => "other",
+            })
+        }
+    }
+
+    /// A resource held or waited for by a thread.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct Resource {
+        /// The key identifying the resource, usually the address of the lock.
+        pub key: usize,
+        /// The kind of the resource.
+        pub kind: ResourceKind,
+    }
+
+    impl fmt::Display for Resource {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:#x} ({})", self.key, self.kind)
+        }
+    }
 
     /// Acquire a resource identified by key in the deadlock detector
-    /// Noop if deadlock_detection feature isn't enabled.
+    /// Noop if neither the deadlock_detection nor the lock_order_validation
+    /// feature is enabled.
     ///
     /// # Safety
     ///
     /// Call after the resource is acquired
     #[inline]
-    pub unsafe fn acquire_resource(_key: usize) {
+    pub unsafe fn acquire_resource(key: usize) {
+        acquire_resource_with_kind(key, ResourceKind::Other);
+    }
+
+    /// Acquire a resource identified by key in the deadlock detector, with
+    /// the given kind reported in deadlock reports.
+    /// Noop if neither the deadlock_detection nor the lock_order_validation
+    /// feature is enabled.
+    ///
+    /// # Safety
+    ///
+    /// Call after the resource is acquired
+    #[inline]
+    pub unsafe fn acquire_resource_with_kind(key: usize, kind: ResourceKind) {
+        acquire_resource_as(key, Resource { key, kind });
+    }
+
+    /// Acquire a resource identified by key in the deadlock detector, which
+    /// is reported as `resource` in deadlock reports. This allows a lock
+    /// which parks threads on several keys to be reported as the lock itself.
+    /// The lock order validator only sees `resource.key`, and ignores this
+    /// acquisition if the thread already holds it through another key.
+    /// Noop if neither the deadlock_detection nor the lock_order_validation
+    /// feature is enabled.
+    ///
+    /// # Safety
+    ///
+    /// Call after the resource is acquired
+    #[inline]
+    pub unsafe fn acquire_resource_as(_key: usize, _resource: Resource) {
         #[cfg(feature = "deadlock_detection")]
-        deadlock_impl::acquire_resource(_key);
+        deadlock_impl::acquire_resource(_key, _resource);
+        #[cfg(feature = "lock_order_validation")]
+        super::with_thread_data(|thread_data| {
+            crate::lock_order::acquire_resource(&thread_data.lock_order_data, _key, _resource.key)
+        });
+    }
+
+    /// Changes the kind of a resource held by the current thread, such as
+    /// when a reader-writer lock is upgraded or downgraded. The resource stays
+    /// held, so this doesn't affect the lock order.
+    /// Noop if the deadlock_detection feature isn't enabled.
+    ///
+    /// # Safety
+    ///
+    /// The resource must have been acquired by the current thread
+    #[inline]
+    pub unsafe fn change_resource_kind(_key: usize, _kind: ResourceKind) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock_impl::change_resource_kind(_key, _kind);
     }
 
     /// Release a resource identified by key in the deadlock detector.
-    /// Noop if deadlock_detection feature isn't enabled.
+    /// Noop if neither the deadlock_detection nor the lock_order_validation
+    /// feature is enabled.
     ///
     /// # Panics
     ///
@@ -1096,26 +1599,146 @@ pub mod deadlock {
     pub unsafe fn release_resource(_key: usize) {
         #[cfg(feature = "deadlock_detection")]
         deadlock_impl::release_resource(_key);
+        #[cfg(feature = "lock_order_validation")]
+        super::with_thread_data(|thread_data| {
+            crate::lock_order::release_resource(&thread_data.lock_order_data, _key)
+        });
+    }
+
+    /// Sets the kind of the resource the current thread is about to wait
+    /// for. This only applies to the next call to `park` on this thread, even
+    /// if its validation fails, and is only used in deadlock reports.
+    /// Noop if the deadlock_detection feature isn't enabled.
+    #[inline]
+    pub fn set_wait_kind(_kind: ResourceKind) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock_impl::set_wait_kind(None, _kind);
+    }
+
+    /// Like `set_wait_kind`, but the resource is also reported with the
+    /// given key instead of the one passed to `park`, like a resource
+    /// acquired with `acquire_resource_as`.
+    /// Noop if the deadlock_detection feature isn't enabled.
+    #[inline]
+    pub fn set_wait_resource(_resource: Resource) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock_impl::set_wait_kind(Some(_resource.key), _resource.kind);
+    }
+
+    /// Sets a resource which the current thread has to acquire after the
+    /// next call to `park` returns, such as the mutex associated with a
+    /// condition variable. This only applies to the next call to `park` on
+    /// this thread, even if its validation fails. The thread can then only
+    /// make progress once that resource is available, so it is treated as
+    /// waiting for it by the deadlock detector.
+    /// Noop if the deadlock_detection feature isn't enabled.
+    #[inline]
+    pub fn set_reacquire_resource(_key: usize, _kind: ResourceKind) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock_impl::set_reacquire_resource(Resource {
+            key: _key,
+            kind: _kind,
+        });
+    }
+
+    /// Sets whether the current thread may be chosen to break a deadlock it
+    /// is involved in. This applies to every following call to `park`
+    /// without a timeout until it is disabled again.
+    ///
+    /// When a deadlock is detected, one of the threads of the cycle which
+    /// enabled this is chosen as the victim: its `park` stops waiting and
+    /// returns `ParkResult::TimedOut` after calling `timed_out`, as if it
+    /// had been given a timeout. The other threads of the cycle keep
+    /// waiting. If no thread of the cycle enabled this, all of them stay
+    /// parked forever.
+    /// Noop if the deadlock_detection feature isn't enabled.
+    #[inline]
+    pub fn set_wait_recoverable(_recoverable: bool) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock_impl::set_wait_recoverable(_recoverable);
     }
 
     /// Returns all deadlocks detected *since* the last call.
     /// Each cycle consist of a vector of `DeadlockedThread`.
     #[cfg(feature = "deadlock_detection")]
     #[inline]
-    pub fn check_deadlock() -> Vec<Vec<deadlock_impl::DeadlockedThread>> {
+    pub fn check_deadlock() -> Vec<Vec<DeadlockedThread>> {
         deadlock_impl::check_deadlock()
     }
 
+    /// Sets whether a backtrace should be captured every time a resource is
+    /// acquired, so that it can be included in the report of a thread which
+    /// exits while still holding resources. This is disabled by default since
+    /// it makes locking much more expensive.
+    #[cfg(feature = "deadlock_detection")]
     #[inline]
-    pub(super) unsafe fn on_unpark(_td: &super::ThreadData) {
+    pub fn set_capture_backtraces(capture: bool) {
+        deadlock_impl::set_capture_backtraces(capture);
+    }
+
+    /// Replaces the hook called when a thread exits while still holding
+    /// resources, which would otherwise make any other thread waiting for
+    /// them hang later on. The default hook prints the report to standard
+    /// error.
+    ///
+    /// The hook is called on the exiting thread while its thread-local
+    /// variables are being destroyed, so it shouldn't use any of them.
+    #[cfg(feature = "deadlock_detection")]
+    #[inline]
+    pub fn set_exit_hook(hook: Box<dyn Fn(&ExitedThread) + Send + Sync>) {
+        deadlock_impl::set_exit_hook(hook);
+    }
+
+    #[inline]
+    pub(super) unsafe fn on_park(_td: &super::ThreadData) {
         #[cfg(feature = "deadlock_detection")]
-        deadlock_impl::on_unpark(_td);
+        deadlock_impl::on_park(_td);
+    }
+
+    // Must be called when `park` returns without parking because the
+    // validation failed.
+    #[inline]
+    pub(super) unsafe fn on_park_invalid(_td: &super::ThreadData) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock_impl::on_park_invalid(_td);
+    }
+
+    // Returns false if the thread was chosen to break a deadlock and must
+    // stop waiting.
+    #[cfg(feature = "deadlock_detection")]
+    #[inline]
+    pub(super) unsafe fn on_unpark(td: &super::ThreadData) -> bool {
+        deadlock_impl::on_unpark(td)
+    }
+
+    #[cfg(not(feature = "deadlock_detection"))]
+    #[inline]
+    pub(super) unsafe fn on_unpark(_td: &super::ThreadData) -> bool {
+        true
+    }
+
+    #[inline]
+    pub(super) fn on_thread_exit(_td: &super::ThreadData) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock_impl::on_thread_exit(_td);
+    }
+
+    // Must be called with the bucket of the thread locked, once it was
+    // removed from the queue after `on_unpark` returned false.
+    #[inline]
+    pub(super) unsafe fn on_wait_aborted(_td: &super::ThreadData) {
+        #[cfg(feature = "deadlock_detection")]
+        deadlock_impl::on_wait_aborted(_td);
     }
 }
 
 #[cfg(feature = "deadlock_detection")]
 mod deadlock_impl {
-    use super::{get_hashtable, lock_bucket, with_thread_data, ThreadData, NUM_THREADS};
+    use super::deadlock::{Resource, ResourceKind};
+    use super::{
+        get_hashtable, is_queued, lock_bucket, lock_bucket_checked, with_thread_data, ThreadData,
+        NUM_THREADS,
+    };
     use crate::thread_parker::{ThreadParkerT, UnparkHandleT};
     use crate::word_lock::WordLock;
     use backtrace::Backtrace;
@@ -1123,13 +1746,23 @@ mod deadlock_impl {
     use petgraph::graphmap::DiGraphMap;
     use std::cell::{Cell, UnsafeCell};
     use std::collections::HashSet;
+    use std::fmt;
+    use std::ptr;
     use std::sync::atomic::Ordering;
+    use std::sync::atomic::{AtomicBool, AtomicPtr};
     use std::sync::mpsc;
+    use std::sync::{Arc, PoisonError, RwLock};
+    use std::thread;
     use thread_id;
 
     /// Representation of a deadlocked thread
     pub struct DeadlockedThread {
         thread_id: usize,
+        thread_name: Option<String>,
+        blocked_on: Resource,
+        reacquires: Option<Resource>,
+        held_resources: Vec<Resource>,
+        victim: bool,
         backtrace: Backtrace,
     }
 
@@ -1139,72 +1772,568 @@ mod deadlock_impl {
             self.thread_id
         }
 
+        /// The thread name, if it has one
+        pub fn thread_name(&self) -> Option<&str> {
+            self.thread_name.as_ref().map(|name| &name[..])
+        }
+
+        /// The resource the thread is blocked on
+        pub fn blocked_on(&self) -> Resource {
+            self.blocked_on
+        }
+
+        /// The resource the thread has to acquire once it is unparked from
+        /// `blocked_on`, such as the mutex of a condition variable
+        pub fn reacquires(&self) -> Option<Resource> {
+            self.reacquires
+        }
+
+        /// The resources held by the thread, in acquisition order
+        pub fn held_resources(&self) -> &[Resource] {
+            &self.held_resources
+        }
+
+        /// Whether the thread was chosen to break the deadlock, in which case
+        /// its wait is aborted instead of blocking forever
+        pub fn is_victim(&self) -> bool {
+            self.victim
+        }
+
         /// The thread backtrace
         pub fn backtrace(&self) -> &Backtrace {
             &self.backtrace
         }
     }
 
+    impl fmt::Debug for DeadlockedThread {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("DeadlockedThread")
+                .field("thread_id", &self.thread_id)
+                .field("thread_name", &self.thread_name)
+                .field("blocked_on", &self.blocked_on)
+                .field("reacquires", &self.reacquires)
+                .field("held_resources", &self.held_resources)
+                .field("victim", &self.victim)
+                .field("backtrace", &self.backtrace)
+                .finish()
+        }
+    }
+
+    /// An edge of a deadlock cycle: a thread blocked on a resource which is
+    /// held by another thread of the cycle.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct WaitEdge {
+        /// The system thread id of the blocked thread
+        pub waiting_thread_id: usize,
+        /// The key of the resource the thread is blocked on
+        pub key: usize,
+        /// The system thread id of the thread holding the resource
+        pub holding_thread_id: usize,
+    }
+
+    /// Description of a deadlocked thread made only of plain data, suitable
+    /// for logging or serialization.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct ThreadReport {
+        /// The system thread id
+        pub thread_id: usize,
+        /// The thread name, if it has one
+        pub thread_name: Option<String>,
+        /// The resource the thread is blocked on
+        pub blocked_on: Resource,
+        /// The resource the thread has to acquire once it is unparked from
+        /// `blocked_on`
+        pub reacquires: Option<Resource>,
+        /// The resources held by the thread, in acquisition order
+        pub held_resources: Vec<Resource>,
+        /// Whether the thread was chosen to break the deadlock
+        pub victim: bool,
+        /// The resolved thread backtrace
+        pub backtrace: String,
+    }
+
+    /// Description of a deadlock cycle made only of plain data, suitable for
+    /// logging or serialization.
+    ///
+    /// The `Display` implementation formats the whole report over multiple
+    /// lines.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct DeadlockReport {
+        /// The threads of the cycle
+        pub threads: Vec<ThreadReport>,
+        /// Which thread is waiting for which other thread
+        pub edges: Vec<WaitEdge>,
+    }
+
+    impl DeadlockReport {
+        /// Builds a report from a cycle returned by `check_deadlock`.
+        pub fn new(cycle: &[DeadlockedThread]) -> DeadlockReport {
+            let mut edges = Vec::new();
+            for waiting in cycle {
+                let wanted = Some(waiting.blocked_on)
+                    .into_iter()
+                    .chain(waiting.reacquires);
+                for wanted in wanted {
+                    for holding in cycle {
+                        if !ptr::eq(holding, waiting)
+                            && holding
+                                .held_resources
+                                .iter()
+                                .any(|held| held.key == wanted.key && conflicts(wanted, *held))
+                        {
+                            edges.push(WaitEdge {
+                                waiting_thread_id: waiting.thread_id,
+                                key: wanted.key,
+                                holding_thread_id: holding.thread_id,
+                            });
+                        }
+                    }
+                }
+            }
+            let threads = cycle
+                .iter()
+                .map(|t| ThreadReport {
+                    thread_id: t.thread_id,
+                    thread_name: t.thread_name.clone(),
+                    blocked_on: t.blocked_on,
+                    reacquires: t.reacquires,
+                    held_resources: t.held_resources.clone(),
+                    victim: t.victim,
+                    backtrace: format!("{:?}", t.backtrace),
+                })
+                .collect();
+            DeadlockReport { threads, edges }
+        }
+    }
+
+    // Whether a thread waiting for `wanted` can be blocked by a thread holding
+    // `held` with the same key. Readers of a reader-writer lock don't block
+    // each other, nor an upgradable reader.
+    fn conflicts(wanted: Resource, held: Resource) -> bool {
+        let shares =
+            |kind| kind == ResourceKind::RwLockShared || kind == ResourceKind::RwLockUpgradable;
+        let shared = |kind| kind == ResourceKind::RwLockShared;
+        !(shared(wanted.kind) && shares(held.kind) || shares(wanted.kind) && shared(held.kind))
+    }
+
+    impl fmt::Display for DeadlockReport {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            writeln!(f, "deadlock between {} threads", self.threads.len())?;
+            for t in &self.threads {
+                write!(f, "thread {}", t.thread_id)?;
+                if let Some(ref name) = t.thread_name {
+                    write!(f, " '{}'", name)?;
+                }
+                write!(f, " blocked on {}", t.blocked_on)?;
+                if let Some(r) = t.reacquires {
+                    write!(f, " then {}", r)?;
+                }
+                if t.victim {
+                    write!(f, ", wait aborted to break the deadlock")?;
+                }
+                writeln!(f)?;
+                for r in &t.held_resources {
+                    writeln!(f, "  holding {}", r)?;
+                }
+                writeln!(f, "{}", t.backtrace)?;
+            }
+            for e in &self.edges {
+                writeln!(
+                    f,
+                    "thread {} waits for {:#x} held by thread {}",
+                    e.waiting_thread_id, e.key, e.holding_thread_id
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    /// A resource which was still held by a thread when it exited.
+    pub struct HeldResource {
+        resource: Resource,
+        backtrace: Option<Backtrace>,
+    }
+
+    impl HeldResource {
+        /// The resource
+        pub fn resource(&self) -> Resource {
+            self.resource
+        }
+
+        /// Backtrace of the acquisition of the resource, if backtraces were
+        /// enabled with `set_capture_backtraces` when it was acquired
+        pub fn backtrace(&self) -> Option<&Backtrace> {
+            self.backtrace.as_ref()
+        }
+    }
+
+    impl fmt::Debug for HeldResource {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("HeldResource")
+                .field("resource", &self.resource)
+                .field("backtrace", &self.backtrace)
+                .finish()
+        }
+    }
+
+    /// Representation of a thread which exited while still holding resources
+    pub struct ExitedThread {
+        thread_id: usize,
+        thread_name: Option<String>,
+        held_resources: Vec<HeldResource>,
+    }
+
+    impl ExitedThread {
+        /// The system thread id
+        pub fn thread_id(&self) -> usize {
+            self.thread_id
+        }
+
+        /// The thread name, if it has one
+        pub fn thread_name(&self) -> Option<&str> {
+            self.thread_name.as_ref().map(|name| &name[..])
+        }
+
+        /// The resources the thread still held
+        pub fn held_resources(&self) -> &[HeldResource] {
+            &self.held_resources
+        }
+    }
+
+    impl fmt::Debug for ExitedThread {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("ExitedThread")
+                .field("thread_id", &self.thread_id)
+                .field("thread_name", &self.thread_name)
+                .field("held_resources", &self.held_resources)
+                .finish()
+        }
+    }
+
+    impl fmt::Display for ExitedThread {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "thread {}", self.thread_id)?;
+            if let Some(ref name) = self.thread_name {
+                write!(f, " '{}'", name)?;
+            }
+            writeln!(
+                f,
+                " exited while holding {} resources",
+                self.held_resources.len()
+            )?;
+            for held in &self.held_resources {
+                writeln!(f, "  holding {}", held.resource)?;
+                if let Some(ref backtrace) = held.backtrace {
+                    writeln!(f, "{:?}", backtrace)?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    type ExitHook = Arc<dyn Fn(&ExitedThread) + Send + Sync>;
+
+    static CAPTURE_BACKTRACES: AtomicBool = AtomicBool::new(false);
+
+    // The hook is stored behind a standard library lock since the locks built
+    // on top of this crate are the ones being checked. `None` means the
+    // default hook.
+    fn exit_hook() -> &'static RwLock<Option<ExitHook>> {
+        static EXIT_HOOK: AtomicPtr<RwLock<Option<ExitHook>>> = AtomicPtr::new(ptr::null_mut());
+
+        let mut hook = EXIT_HOOK.load(Ordering::Acquire);
+        if hook.is_null() {
+            let new_hook = Box::into_raw(Box::new(RwLock::new(None)));
+            match EXIT_HOOK.compare_exchange(
+                ptr::null_mut(),
+                new_hook,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => hook = new_hook,
+                Err(old_hook) => {
+                    // SAFETY: `new_hook` was created by `Box::into_raw` above
+                    // and was never shared.
+                    drop(unsafe { Box::from_raw(new_hook) });
+                    hook = old_hook;
+                }
+            }
+        }
+
+        // SAFETY: The hook is never freed once it has been published.
+        unsafe { &*hook }
+    }
+
     pub struct DeadlockData {
-        // Currently owned resources (keys)
-        resources: UnsafeCell<Vec<usize>>,
+        // Currently owned resources, as they are reported
+        resources: UnsafeCell<Vec<Resource>>,
+
+        // Keys of `resources` in the wait graph, at the same indices
+        keys: UnsafeCell<Vec<usize>>,
+
+        // Backtraces of the acquisitions of `resources`, at the same indices
+        backtraces: UnsafeCell<Vec<Option<Backtrace>>>,
+
+        // Kind of the resource the next park will wait for
+        next_wait_kind: Cell<ResourceKind>,
+
+        // Key reported instead of the key of the next park
+        next_wait_key: Cell<Option<usize>>,
+
+        // Kind of the resource the thread is currently parked on
+        wait_kind: Cell<ResourceKind>,
+
+        // Key reported instead of the key the thread is currently parked on
+        wait_key: Cell<Option<usize>>,
+
+        // Resource to acquire after the next park
+        next_reacquire: Cell<Option<Resource>>,
+
+        // Resource to acquire after the current park
+        reacquire: Cell<Option<Resource>>,
 
         // Set when there's a pending callstack request
         deadlocked: Cell<bool>,
 
+        // Whether the thread may be chosen to break a deadlock
+        recoverable: Cell<bool>,
+
+        // Set along with `deadlocked` when the thread must stop waiting to
+        // break the deadlock
+        victim: Cell<bool>,
+
+        // Set along with `deadlocked` when another thread of the cycle was
+        // chosen as the victim, so this one must keep waiting
+        recovering: Cell<bool>,
+
         // Sender used to report the backtrace
         backtrace_sender: UnsafeCell<Option<mpsc::Sender<DeadlockedThread>>>,
 
         // System thread id
         thread_id: usize,
+
+        // Thread name, reported if the thread exits while holding resources
+        thread_name: Option<String>,
     }
 
     impl DeadlockData {
-        pub fn new() -> Self {
+        // The data of a task doesn't describe any thread.
+        pub fn new(task: bool) -> Self {
             DeadlockData {
                 resources: UnsafeCell::new(Vec::new()),
+                keys: UnsafeCell::new(Vec::new()),
+                backtraces: UnsafeCell::new(Vec::new()),
+                next_wait_kind: Cell::new(ResourceKind::Other),
+                next_wait_key: Cell::new(None),
+                wait_kind: Cell::new(ResourceKind::Other),
+                wait_key: Cell::new(None),
+                next_reacquire: Cell::new(None),
+                reacquire: Cell::new(None),
                 deadlocked: Cell::new(false),
+                recoverable: Cell::new(false),
+                victim: Cell::new(false),
+                recovering: Cell::new(false),
                 backtrace_sender: UnsafeCell::new(None),
-                thread_id: thread_id::get(),
+                thread_id: if task { 0 } else { thread_id::get() },
+                thread_name: if task {
+                    None
+                } else {
+                    thread::current().name().map(|name| name.to_owned())
+                },
             }
         }
     }
 
-    pub(super) unsafe fn on_unpark(td: &ThreadData) {
-        if td.deadlock_data.deadlocked.get() {
+    pub(super) unsafe fn on_park(td: &ThreadData) {
+        let kind = td.deadlock_data.next_wait_kind.replace(ResourceKind::Other);
+        td.deadlock_data.wait_kind.set(kind);
+        let key = td.deadlock_data.next_wait_key.replace(None);
+        td.deadlock_data.wait_key.set(key);
+        let reacquire = td.deadlock_data.next_reacquire.replace(None);
+        td.deadlock_data.reacquire.set(reacquire);
+    }
+
+    // What was set for this park must not leak into the next one.
+    pub(super) unsafe fn on_park_invalid(td: &ThreadData) {
+        td.deadlock_data.next_wait_kind.set(ResourceKind::Other);
+        td.deadlock_data.next_wait_key.set(None);
+        td.deadlock_data.next_reacquire.set(None);
+    }
+
+    pub(super) unsafe fn on_unpark(td: &ThreadData) -> bool {
+        while td.deadlock_data.deadlocked.get() {
             let sender = (*td.deadlock_data.backtrace_sender.get()).take().unwrap();
             sender
                 .send(DeadlockedThread {
                     thread_id: td.deadlock_data.thread_id,
+                    thread_name: thread::current().name().map(|name| name.to_owned()),
+                    blocked_on: Resource {
+                        key: match td.deadlock_data.wait_key.get() {
+                            Some(key) => key,
+                            None => td.key.load(Ordering::Relaxed),
+                        },
+                        kind: td.deadlock_data.wait_kind.get(),
+                    },
+                    reacquires: td.deadlock_data.reacquire.get(),
+                    held_resources: distinct(&*td.deadlock_data.resources.get()),
+                    victim: td.deadlock_data.victim.get(),
                     backtrace: Backtrace::new(),
                 })
                 .unwrap();
             // make sure to close this sender
             drop(sender);
 
-            // park until the end of the time
+            // the caller stops waiting, `on_wait_aborted` clears the flags
+            if td.deadlock_data.victim.get() {
+                return false;
+            }
+
+            if !td.deadlock_data.recovering.get() {
+                // park until the end of the time
+                td.parker.prepare_park();
+                td.parker.park();
+                unreachable!("unparked deadlocked thread!");
+            }
+
+            // Another thread of the cycle stops waiting, so go back to sleep
+            // until we are actually unparked. The cycle may be detected
+            // again if it wasn't broken after all.
+            let (_, bucket) = lock_bucket_checked(&td.key);
+            td.deadlock_data.deadlocked.set(false);
+            td.deadlock_data.recovering.set(false);
+            if !is_queued(bucket, td) {
+                // SAFETY: We hold the lock here, as required
+                bucket.mutex.unlock();
+                break;
+            }
             td.parker.prepare_park();
+            // SAFETY: We hold the lock here, as required
+            bucket.mutex.unlock();
             td.parker.park();
-            unreachable!("unparked deadlocked thread!");
         }
+        true
     }
 
-    pub unsafe fn acquire_resource(key: usize) {
+    pub(super) unsafe fn on_wait_aborted(td: &ThreadData) {
+        td.deadlock_data.deadlocked.set(false);
+        td.deadlock_data.victim.set(false);
+    }
+
+    // A lock which is held through several keys is only reported once.
+    fn distinct(resources: &[Resource]) -> Vec<Resource> {
+        let mut distinct = Vec::with_capacity(resources.len());
+        for resource in resources {
+            if !distinct.contains(resource) {
+                distinct.push(*resource);
+            }
+        }
+        distinct
+    }
+
+    pub unsafe fn acquire_resource(key: usize, resource: Resource) {
+        let backtrace = if CAPTURE_BACKTRACES.load(Ordering::Relaxed) {
+            Some(Backtrace::new_unresolved())
+        } else {
+            None
+        };
         with_thread_data(|thread_data| {
-            (*thread_data.deadlock_data.resources.get()).push(key);
+            (*thread_data.deadlock_data.resources.get()).push(resource);
+            (*thread_data.deadlock_data.keys.get()).push(key);
+            (*thread_data.deadlock_data.backtraces.get()).push(backtrace);
+        });
+    }
+
+    pub unsafe fn change_resource_kind(key: usize, kind: ResourceKind) {
+        with_thread_data(|thread_data| {
+            let keys = &(*thread_data.deadlock_data.keys.get());
+            match keys.iter().rposition(|&x| x == key) {
+                Some(p) => (&mut *thread_data.deadlock_data.resources.get())[p].kind = kind,
+                None => panic!("key {} not found in thread resources", key),
+            };
         });
     }
 
     pub unsafe fn release_resource(key: usize) {
         with_thread_data(|thread_data| {
-            let resources = &mut (*thread_data.deadlock_data.resources.get());
-            match resources.iter().rposition(|x| *x == key) {
-                Some(p) => resources.swap_remove(p),
+            let keys = &mut (*thread_data.deadlock_data.keys.get());
+            match keys.iter().rposition(|&x| x == key) {
+                Some(p) => {
+                    keys.swap_remove(p);
+                    (*thread_data.deadlock_data.resources.get()).swap_remove(p);
+                    (*thread_data.deadlock_data.backtraces.get()).swap_remove(p);
+                }
                 None => panic!("key {} not found in thread resources", key),
             };
         });
     }
 
+    pub fn set_capture_backtraces(capture: bool) {
+        CAPTURE_BACKTRACES.store(capture, Ordering::Relaxed);
+    }
+
+    pub fn set_exit_hook(hook: Box<dyn Fn(&ExitedThread) + Send + Sync>) {
+        *exit_hook().write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::from(hook));
+    }
+
+    pub(super) fn on_thread_exit(td: &ThreadData) {
+        // SAFETY: The thread data is being dropped, so nothing else can use
+        // it anymore.
+        let (resources, backtraces) = unsafe {
+            (
+                &mut *td.deadlock_data.resources.get(),
+                &mut *td.deadlock_data.backtraces.get(),
+            )
+        };
+        if resources.is_empty() {
+            return;
+        }
+
+        let mut held_resources: Vec<HeldResource> = Vec::with_capacity(resources.len());
+        for (resource, mut backtrace) in resources.drain(..).zip(backtraces.drain(..)) {
+            // A lock which is held through several keys is only reported once.
+            if held_resources.iter().any(|held| held.resource == resource) {
+                continue;
+            }
+            if let Some(ref mut backtrace) = backtrace {
+                backtrace.resolve();
+            }
+            held_resources.push(HeldResource {
+                resource,
+                backtrace,
+            });
+        }
+        let exited = ExitedThread {
+            thread_id: td.deadlock_data.thread_id,
+            thread_name: td.deadlock_data.thread_name.clone(),
+            held_resources,
+        };
+        let hook = exit_hook()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        match hook {
+            Some(hook) => hook(&exited),
+            None => eprintln!("{}", exited),
+        }
+    }
+
+    pub fn set_wait_kind(key: Option<usize>, kind: ResourceKind) {
+        with_thread_data(|thread_data| {
+            thread_data.deadlock_data.next_wait_kind.set(kind);
+            thread_data.deadlock_data.next_wait_key.set(key);
+        });
+    }
+
+    pub fn set_wait_recoverable(recoverable: bool) {
+        with_thread_data(|thread_data| thread_data.deadlock_data.recoverable.set(recoverable));
+    }
+
+    pub fn set_reacquire_resource(resource: Resource) {
+        with_thread_data(|thread_data| {
+            thread_data.deadlock_data.next_reacquire.set(Some(resource))
+        });
+    }
+
     pub fn check_deadlock() -> Vec<Vec<DeadlockedThread>> {
         unsafe {
             // fast pass
@@ -1229,15 +2358,25 @@ mod deadlock_impl {
             b.mutex.lock();
             let mut current = b.queue_head.get();
             while !current.is_null() {
+                // Tasks parked with `park_waker` are not threads and can't
+                // take part in a deadlock cycle.
                 if !(*current).parked_with_timeout.get()
                     && !(*current).deadlock_data.deadlocked.get()
+                    && (*(*current).waker.get()).is_none()
                 {
                     // .resources are waiting for their owner
-                    for &resource in &(*(*current).deadlock_data.resources.get()) {
-                        graph.add_edge(resource, current as usize, ());
+                    for &key in &(*(*current).deadlock_data.keys.get()) {
+                        graph.add_edge(key, current as usize, ());
                     }
                     // owner waits for resource .key
-                    graph.add_edge(current as usize, (*current).key.load(Ordering::Relaxed), ());
+                    let key = (*current).key.load(Ordering::Relaxed);
+                    graph.add_edge(current as usize, key, ());
+                    // resource .key can only be acquired after .reacquire
+                    if let Some(reacquire) = (*current).deadlock_data.reacquire.get() {
+                        if reacquire.key != key {
+                            graph.add_edge(key, reacquire.key, ());
+                        }
+                    }
                 }
                 current = (*current).next_in_queue.get();
             }
@@ -1254,7 +2393,7 @@ mod deadlock_impl {
         Resource(usize),
     }
 
-    use self::WaitGraphNode::*;
+    use self::WaitGraphNode::Thread;
 
     // Contrary to the _fast variant this locks the entries table before looking for cycles.
     // Returns all detected thread wait cycles.
@@ -1289,23 +2428,37 @@ mod deadlock_impl {
         let thread_count = NUM_THREADS.load(Ordering::Relaxed);
         let mut graph =
             DiGraphMap::<WaitGraphNode, ()>::with_capacity(thread_count * 2, thread_count * 2);
+        let mut recoverable = HashSet::new();
 
         for b in &table.entries[..] {
             let mut current = b.queue_head.get();
             while !current.is_null() {
+                // Tasks parked with `park_waker` are not threads and can't
+                // take part in a deadlock cycle.
                 if !(*current).parked_with_timeout.get()
                     && !(*current).deadlock_data.deadlocked.get()
+                    && (*(*current).waker.get()).is_none()
                 {
+                    if (*current).deadlock_data.recoverable.get() {
+                        recoverable.insert(current);
+                    }
                     // .resources are waiting for their owner
-                    for &resource in &(*(*current).deadlock_data.resources.get()) {
-                        graph.add_edge(Resource(resource), Thread(current), ());
+                    for &key in &(*(*current).deadlock_data.keys.get()) {
+                        graph.add_edge(WaitGraphNode::Resource(key), Thread(current), ());
                     }
                     // owner waits for resource .key
-                    graph.add_edge(
-                        Thread(current),
-                        Resource((*current).key.load(Ordering::Relaxed)),
-                        (),
-                    );
+                    let key = (*current).key.load(Ordering::Relaxed);
+                    graph.add_edge(Thread(current), WaitGraphNode::Resource(key), ());
+                    // resource .key can only be acquired after .reacquire
+                    if let Some(reacquire) = (*current).deadlock_data.reacquire.get() {
+                        if reacquire.key != key {
+                            graph.add_edge(
+                                WaitGraphNode::Resource(key),
+                                WaitGraphNode::Resource(reacquire.key),
+                                (),
+                            );
+                        }
+                    }
                 }
                 current = (*current).next_in_queue.get();
             }
@@ -1321,11 +2474,40 @@ mod deadlock_impl {
 
         let mut results = Vec::with_capacity(cycles.len());
 
+        let mut victims = HashSet::new();
+        let mut reported = HashSet::new();
+
         for cycle in cycles {
+            // Pick a victim among the threads which accept to stop waiting,
+            // unless a victim from a previous cycle already breaks this one.
+            // A thread reported with a previous cycle isn't unparked again,
+            // so it can't be told that it was chosen.
+            let victim = if cycle.iter().any(|td| victims.contains(td)) {
+                None
+            } else {
+                cycle
+                    .iter()
+                    .cloned()
+                    .find(|td| recoverable.contains(td) && !reported.contains(td))
+            };
+            if let Some(victim) = victim {
+                victims.insert(victim);
+            }
+
             let (sender, receiver) = mpsc::channel();
             for td in cycle {
+                // A thread shared with a previous cycle has already been
+                // reported and can't be unparked again.
+                if !reported.insert(td) {
+                    continue;
+                }
                 let bucket = lock_bucket((*td).key.load(Ordering::Relaxed));
                 (*td).deadlock_data.deadlocked.set(true);
+                if victims.contains(&td) {
+                    (*td).deadlock_data.victim.set(true);
+                } else if victim.is_some() {
+                    (*td).deadlock_data.recovering.set(true);
+                }
                 *(*td).deadlock_data.backtrace_sender.get() = Some(sender.clone());
                 let handle = (*td).parker.unpark_lock();
                 // SAFETY: We hold the lock here, as required
@@ -1661,4 +2843,231 @@ mod tests {
             &self.semaphore as *const _ as usize
         }
     }
+
+    mod waker {
+        use super::super::{
+            cancel_park, park_waker, unpark_all, unpark_filter, unpark_one, unpark_requeue,
+            FilterOp, ParkResult, ParkToken, RequeueOp, UnparkToken, WakerEntry,
+        };
+        use super::{DEFAULT_PARK_TOKEN, DEFAULT_UNPARK_TOKEN};
+        use std::{
+            sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc,
+            },
+            task::{Poll, RawWaker, RawWakerVTable, Waker},
+        };
+
+        /// Creates a waker which counts how many times it has been woken
+        fn counting_waker() -> (Waker, Arc<AtomicUsize>) {
+            unsafe fn clone(data: *const ()) -> RawWaker {
+                let arc = Arc::from_raw(data as *const AtomicUsize);
+                let cloned = arc.clone();
+                std::mem::forget(arc);
+                RawWaker::new(Arc::into_raw(cloned) as *const (), &VTABLE)
+            }
+            unsafe fn wake(data: *const ()) {
+                wake_by_ref(data);
+                drop_waker(data);
+            }
+            unsafe fn wake_by_ref(data: *const ()) {
+                (*(data as *const AtomicUsize)).fetch_add(1, Ordering::SeqCst);
+            }
+            unsafe fn drop_waker(data: *const ()) {
+                drop(Arc::from_raw(data as *const AtomicUsize));
+            }
+            static VTABLE: RawWakerVTable =
+                RawWakerVTable::new(clone, wake, wake_by_ref, drop_waker);
+
+            let count = Arc::new(AtomicUsize::new(0));
+            let data = Arc::into_raw(count.clone()) as *const ();
+            (
+                unsafe { Waker::from_raw(RawWaker::new(data, &VTABLE)) },
+                count,
+            )
+        }
+
+        fn park(key: usize, entry: &mut WakerEntry, waker: &Waker) -> Poll<ParkResult> {
+            unsafe { park_waker(key, || true, entry, waker, DEFAULT_PARK_TOKEN) }
+        }
+
+        #[test]
+        fn unpark_one_wakes_task() {
+            let lock = 0u8;
+            let key = &lock as *const _ as usize;
+            let (waker, count) = counting_waker();
+            let mut entry = WakerEntry::new();
+
+            assert_eq!(park(key, &mut entry, &waker), Poll::Pending);
+            assert!(entry.is_parked());
+            assert_eq!(park(key, &mut entry, &waker), Poll::Pending);
+            assert_eq!(count.load(Ordering::SeqCst), 0);
+
+            let result = unsafe { unpark_one(key, |_| UnparkToken(7)) };
+            assert_eq!(result.unparked_threads, 1);
+            assert!(!result.have_more_threads);
+            assert_eq!(count.load(Ordering::SeqCst), 1);
+
+            assert_eq!(
+                park(key, &mut entry, &waker),
+                Poll::Ready(ParkResult::Unparked(UnparkToken(7)))
+            );
+            assert!(!entry.is_parked());
+        }
+
+        #[test]
+        fn unpark_all_wakes_tasks() {
+            let lock = 0u8;
+            let key = &lock as *const _ as usize;
+            let (waker, count) = counting_waker();
+            let mut entries = [WakerEntry::new(), WakerEntry::new(), WakerEntry::new()];
+
+            for entry in &mut entries {
+                assert_eq!(park(key, entry, &waker), Poll::Pending);
+            }
+            assert_eq!(unsafe { unpark_all(key, UnparkToken(3)) }, 3);
+            assert_eq!(count.load(Ordering::SeqCst), 3);
+            for entry in &mut entries {
+                assert_eq!(
+                    park(key, entry, &waker),
+                    Poll::Ready(ParkResult::Unparked(UnparkToken(3)))
+                );
+            }
+        }
+
+        #[test]
+        fn unpark_requeue_moves_tasks() {
+            let locks = [0u8; 2];
+            let key_from = &locks[0] as *const _ as usize;
+            let key_to = &locks[1] as *const _ as usize;
+            let (waker, count) = counting_waker();
+            let mut entries = [WakerEntry::new(), WakerEntry::new()];
+
+            for entry in &mut entries {
+                assert_eq!(park(key_from, entry, &waker), Poll::Pending);
+            }
+            let result = unsafe {
+                unpark_requeue(
+                    key_from,
+                    key_to,
+                    || RequeueOp::UnparkOneRequeueRest,
+                    |_, _| DEFAULT_UNPARK_TOKEN,
+                )
+            };
+            assert_eq!(result.unparked_threads, 1);
+            assert_eq!(result.requeued_threads, 1);
+            assert_eq!(count.load(Ordering::SeqCst), 1);
+
+            // The requeued task is now woken through its new key.
+            assert_eq!(unsafe { unpark_all(key_from, DEFAULT_UNPARK_TOKEN) }, 0);
+            assert_eq!(unsafe { unpark_all(key_to, DEFAULT_UNPARK_TOKEN) }, 1);
+            assert_eq!(count.load(Ordering::SeqCst), 2);
+            for entry in &mut entries {
+                assert_eq!(
+                    park(key_from, entry, &waker),
+                    Poll::Ready(ParkResult::Unparked(DEFAULT_UNPARK_TOKEN))
+                );
+            }
+        }
+
+        #[test]
+        fn unpark_filter_sees_park_tokens() {
+            let lock = 0u8;
+            let key = &lock as *const _ as usize;
+            let (waker, count) = counting_waker();
+            let mut entries = [WakerEntry::new(), WakerEntry::new(), WakerEntry::new()];
+
+            for (i, entry) in entries.iter_mut().enumerate() {
+                let poll = unsafe { park_waker(key, || true, entry, &waker, ParkToken(i)) };
+                assert_eq!(poll, Poll::Pending);
+            }
+            let result = unsafe {
+                unpark_filter(
+                    key,
+                    |ParkToken(token)| {
+                        if token % 2 == 0 {
+                            FilterOp::Unpark
+                        } else {
+                            FilterOp::Skip
+                        }
+                    },
+                    |_| DEFAULT_UNPARK_TOKEN,
+                )
+            };
+            assert_eq!(result.unparked_threads, 2);
+            assert!(result.have_more_threads);
+            assert_eq!(count.load(Ordering::SeqCst), 2);
+
+            assert_eq!(park(key, &mut entries[1], &waker), Poll::Pending);
+            assert_eq!(unsafe { cancel_park(&mut entries[1], |_, _| {}) }, None);
+        }
+
+        #[test]
+        fn cancel_park_removes_task() {
+            let lock = 0u8;
+            let key = &lock as *const _ as usize;
+            let (waker, count) = counting_waker();
+            let mut first = WakerEntry::new();
+            let mut second = WakerEntry::new();
+
+            assert_eq!(park(key, &mut first, &waker), Poll::Pending);
+            assert_eq!(park(key, &mut second, &waker), Poll::Pending);
+
+            let mut calls = Vec::new();
+            let token = unsafe { cancel_park(&mut first, |k, last| calls.push((k, last))) };
+            assert_eq!(token, None);
+            assert!(!first.is_parked());
+            let token = unsafe { cancel_park(&mut second, |k, last| calls.push((k, last))) };
+            assert_eq!(token, None);
+            assert_eq!(calls, [(key, false), (key, true)]);
+
+            // Cancelling an entry which is not parked does nothing.
+            assert_eq!(unsafe { cancel_park(&mut first, |_, _| panic!()) }, None);
+            assert_eq!(unsafe { unpark_all(key, DEFAULT_UNPARK_TOKEN) }, 0);
+            assert_eq!(count.load(Ordering::SeqCst), 0);
+        }
+
+        #[test]
+        fn cancel_park_after_unpark() {
+            let lock = 0u8;
+            let key = &lock as *const _ as usize;
+            let (waker, _) = counting_waker();
+            let mut entry = WakerEntry::new();
+
+            assert_eq!(park(key, &mut entry, &waker), Poll::Pending);
+            unsafe { unpark_one(key, |_| UnparkToken(5)) };
+            let token = unsafe { cancel_park(&mut entry, |_, _| panic!()) };
+            assert_eq!(token, Some(UnparkToken(5)));
+            assert!(!entry.is_parked());
+        }
+
+        #[test]
+        fn park_waker_invalid() {
+            let lock = 0u8;
+            let key = &lock as *const _ as usize;
+            let (waker, _) = counting_waker();
+            let mut entry = WakerEntry::new();
+
+            let poll = unsafe { park_waker(key, || false, &mut entry, &waker, DEFAULT_PARK_TOKEN) };
+            assert_eq!(poll, Poll::Ready(ParkResult::Invalid));
+            assert!(!entry.is_parked());
+            assert_eq!(unsafe { unpark_all(key, DEFAULT_UNPARK_TOKEN) }, 0);
+        }
+
+        #[test]
+        fn drop_parked_entry() {
+            let lock = 0u8;
+            let key = &lock as *const _ as usize;
+            let (waker, count) = counting_waker();
+            let mut entry = WakerEntry::new();
+
+            assert_eq!(park(key, &mut entry, &waker), Poll::Pending);
+            drop(entry);
+            assert_eq!(unsafe { unpark_all(key, DEFAULT_UNPARK_TOKEN) }, 0);
+            assert_eq!(count.load(Ordering::SeqCst), 0);
+            // All clones of the waker were dropped along with the entry.
+            drop(waker);
+            assert_eq!(Arc::strong_count(&count), 1);
+        }
+    }
 }
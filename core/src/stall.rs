@@ -0,0 +1,220 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! \[Experimental\] Stall detection
+//!
+//! Enabled via the `stall_detection` feature flag.
+//!
+//! Every thread records when it parks. `check_stalls` then reports the
+//! threads which have been parked on the same key for longer than a given
+//! threshold, whether or not they were parked with a timeout. Unlike the
+//! deadlock detector this doesn't require a cycle, so it also finds threads
+//! stuck behind a lock which is held for too long or a long convoy of
+//! waiters.
+//!
+//! Tasks parked with `park_waker` are not threads and are not reported.
+
+use crate::parking_lot::for_each_parked_thread;
+use crate::ParkToken;
+use backtrace::Backtrace;
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use thread_id;
+
+/// Description of a thread which has been parked for longer than the
+/// threshold passed to `check_stalls`.
+pub struct StalledThread {
+    thread_id: usize,
+    key: usize,
+    park_token: ParkToken,
+    parked_for: Duration,
+    timed: bool,
+    backtrace: Option<Backtrace>,
+}
+
+impl StalledThread {
+    /// The system thread id
+    pub fn thread_id(&self) -> usize {
+        self.thread_id
+    }
+
+    /// The key the thread is parked on
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    /// The park token the thread was parked with
+    pub fn park_token(&self) -> ParkToken {
+        self.park_token
+    }
+
+    /// How long the thread had been parked when it was reported
+    pub fn parked_for(&self) -> Duration {
+        self.parked_for
+    }
+
+    /// Whether the thread was parked with a timeout
+    pub fn is_timed(&self) -> bool {
+        self.timed
+    }
+
+    /// Backtrace of the call to `park`, if backtraces were enabled with
+    /// `set_capture_backtraces` when the thread parked
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_ref()
+    }
+}
+
+impl fmt::Debug for StalledThread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StalledThread")
+            .field("thread_id", &self.thread_id)
+            .field("key", &self.key)
+            .field("park_token", &self.park_token)
+            .field("parked_for", &self.parked_for)
+            .field("timed", &self.timed)
+            .field("backtrace", &self.backtrace)
+            .finish()
+    }
+}
+
+impl fmt::Display for StalledThread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "thread {} parked on {:#x} for {:?}",
+            self.thread_id, self.key, self.parked_for
+        )?;
+        if self.timed {
+            write!(f, " with a timeout")?;
+        }
+        if let Some(ref backtrace) = self.backtrace {
+            write!(f, "\n{:?}", backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+static CAPTURE_BACKTRACES: AtomicBool = AtomicBool::new(false);
+
+pub(crate) struct StallData {
+    // Where the thread last parked, if backtraces are captured
+    backtrace: UnsafeCell<Option<Backtrace>>,
+
+    // System thread id
+    thread_id: usize,
+}
+
+impl StallData {
+    // The data of a task doesn't describe any thread.
+    pub(crate) fn new(task: bool) -> StallData {
+        StallData {
+            backtrace: UnsafeCell::new(None),
+            thread_id: if task { 0 } else { thread_id::get() },
+        }
+    }
+}
+
+// Must be called from the thread which owns `data`, before it is queued.
+pub(crate) unsafe fn on_park(data: &StallData) {
+    *data.backtrace.get() = if CAPTURE_BACKTRACES.load(Ordering::Relaxed) {
+        Some(Backtrace::new_unresolved())
+    } else {
+        None
+    };
+}
+
+/// Returns the threads which have been parked on the same key for at least
+/// `threshold`.
+///
+/// Each bucket of the parking lot is locked in turn, so this doesn't give a
+/// consistent snapshot of all the parked threads, and a thread which is
+/// requeued or moved to a new hash table during the check can be missed.
+pub fn check_stalls(threshold: Duration) -> Vec<StalledThread> {
+    let mut stalled = Vec::new();
+    for_each_parked_thread(|thread, data| {
+        if thread.parked_for() >= threshold {
+            stalled.push(StalledThread {
+                thread_id: data.thread_id,
+                key: thread.key(),
+                park_token: thread.park_token(),
+                parked_for: thread.parked_for(),
+                timed: thread.is_timed(),
+                // SAFETY: The owner only writes this while it isn't queued.
+                backtrace: unsafe { (*data.backtrace.get()).clone() },
+            });
+        }
+    });
+    for thread in &mut stalled {
+        if let Some(ref mut backtrace) = thread.backtrace {
+            backtrace.resolve();
+        }
+    }
+    stalled
+}
+
+/// Sets whether threads should capture a backtrace every time they park, so
+/// that it can be included in the threads reported by `check_stalls`. This is
+/// disabled by default since it makes parking much more expensive.
+pub fn set_capture_backtraces(capture: bool) {
+    CAPTURE_BACKTRACES.store(capture, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{park, unpark_all, ParkResult, ParkToken, DEFAULT_UNPARK_TOKEN};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn check_stalls() {
+        static KEY: AtomicBool = AtomicBool::new(false);
+        let key = &KEY as *const _ as usize;
+        super::set_capture_backtraces(true);
+
+        let parked = Arc::new(AtomicBool::new(false));
+        let parked2 = parked.clone();
+        let t = thread::spawn(move || unsafe {
+            park(
+                key,
+                || true,
+                || parked2.store(true, Ordering::Relaxed),
+                |_, _| {},
+                ParkToken(42),
+                Some(Instant::now() + Duration::from_secs(10)),
+            )
+        });
+        while !parked.load(Ordering::Relaxed) {
+            thread::yield_now();
+        }
+        thread::sleep(Duration::from_millis(50));
+
+        let stalled: Vec<_> = super::check_stalls(Duration::from_millis(40))
+            .into_iter()
+            .filter(|t| t.key() == key)
+            .collect();
+        assert_eq!(stalled.len(), 1);
+        assert_eq!(stalled[0].park_token(), ParkToken(42));
+        assert!(stalled[0].is_timed());
+        assert!(stalled[0].parked_for() >= Duration::from_millis(40));
+        assert!(stalled[0].backtrace().is_some());
+        assert!(super::check_stalls(Duration::from_secs(10))
+            .iter()
+            .all(|t| t.key() != key));
+        super::set_capture_backtraces(false);
+
+        unsafe { unpark_all(key, DEFAULT_UNPARK_TOKEN) };
+        assert_eq!(
+            t.join().unwrap(),
+            ParkResult::Unparked(DEFAULT_UNPARK_TOKEN)
+        );
+    }
+}
@@ -77,11 +77,16 @@
 //!
 //! # Cargo features
 //!
-//! This crate supports two cargo features:
+//! This crate supports the following cargo features:
 //!
 //! - `owning_ref`: Allows your lock types to be used with the `owning_ref` crate.
 //! - `nightly`: Enables nightly-only features. At the moment the only such
 //!   feature is `const fn` constructors for lock types.
+//! - `arc_lock`: Enables locking from an `Arc`. This enables types such as `ArcMutexGuard`.
+//!   Note that this requires the `alloc` crate to be present.
+//! - `poison`: Enables the `PoisonMutex` and `PoisonRwLock` wrappers, which
+//!   implement lock poisoning like the standard library locks. Note that this
+//!   requires the `std` crate to be present.
 
 #![no_std]
 #![warn(missing_docs)]
@@ -91,6 +96,12 @@
 #[macro_use]
 extern crate scopeguard;
 
+#[cfg(feature = "arc_lock")]
+extern crate alloc;
+
+#[cfg(feature = "poison")]
+extern crate std;
+
 /// Marker type which indicates that the Guard type for a lock is `Send`.
 pub struct GuardSend(());
 
@@ -105,3 +116,8 @@ pub use crate::remutex::*;
 
 mod rwlock;
 pub use crate::rwlock::*;
+
+#[cfg(feature = "poison")]
+mod poison;
+#[cfg(feature = "poison")]
+pub use crate::poison::*;
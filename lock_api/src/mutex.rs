@@ -7,9 +7,19 @@
 
 use core::cell::UnsafeCell;
 use core::fmt;
+use core::future::Future;
 use core::marker::PhantomData;
 use core::mem;
 use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+#[cfg(feature = "arc_lock")]
+use alloc::sync::Arc;
+#[cfg(feature = "arc_lock")]
+use core::mem::ManuallyDrop;
+#[cfg(feature = "arc_lock")]
+use core::ptr;
 
 #[cfg(feature = "owning_ref")]
 use owning_ref::StableAddress;
@@ -86,6 +96,57 @@ pub unsafe trait RawMutexTimed: RawMutex {
     fn try_lock_until(&self, timeout: Self::Instant) -> bool;
 }
 
+/// Additional methods for mutexes which support failing a lock acquisition
+/// which would otherwise deadlock.
+///
+/// The error type is specified as an associated type so that this trait is
+/// usable even in `no_std` environments.
+///
+/// # Safety
+///
+/// Implementations of this trait must ensure that the mutex is locked when
+/// `lock_or_deadlock` returns `Ok`, and that it isn't when it returns `Err`.
+pub unsafe trait RawMutexDeadlock: RawMutex {
+    /// Error returned when the acquisition was aborted to break a deadlock.
+    type DeadlockError;
+
+    /// Acquires this mutex, blocking the current thread until it is able to do
+    /// so, unless the thread is chosen to break a deadlock it is involved in.
+    fn lock_or_deadlock(&self) -> Result<(), Self::DeadlockError>;
+}
+
+/// Additional methods for mutexes which support being locked from
+/// asynchronous code.
+///
+/// Instead of blocking the current thread, a task waiting for the mutex
+/// registers its `Waker` and is woken up once it should poll again.
+///
+/// # Safety
+///
+/// Implementations of this trait must ensure that a task is only woken up
+/// with the mutex locked if `poll_lock` will return `Poll::Ready`, and that
+/// `cancel_lock` releases the mutex if it was handed over to the task.
+pub unsafe trait RawMutexAsync: RawMutex {
+    /// State kept by a task between polls while it is waiting for the mutex.
+    type Waiter: Default + Unpin;
+
+    /// Attempts to acquire this mutex, registering the waker of `cx` to be
+    /// woken up if the mutex could not be acquired yet.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must only be used with this mutex.
+    unsafe fn poll_lock(&self, waiter: &mut Self::Waiter, cx: &mut Context<'_>) -> Poll<()>;
+
+    /// Gives up on acquiring this mutex after `poll_lock` returned
+    /// `Poll::Pending`.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must only be used with this mutex.
+    unsafe fn cancel_lock(&self, waiter: &mut Self::Waiter);
+}
+
 /// A mutual exclusion primitive useful for protecting shared data
 ///
 /// This mutex will block threads waiting for the lock to become available. The
@@ -142,6 +203,18 @@ impl<R: RawMutex, T: ?Sized> Mutex<R, T> {
         }
     }
 
+    /// # Safety
+    ///
+    /// The lock must be held when calling this method.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    unsafe fn guard_arc(self: &Arc<Self>) -> ArcMutexGuard<R, T> {
+        ArcMutexGuard {
+            mutex: self.clone(),
+            marker: PhantomData,
+        }
+    }
+
     /// Acquires a mutex, blocking the current thread until it is able to do so.
     ///
     /// This function will block the local thread until it is available to acquire
@@ -175,6 +248,36 @@ impl<R: RawMutex, T: ?Sized> Mutex<R, T> {
         }
     }
 
+    /// Acquires a mutex through an `Arc`, blocking the current thread until it
+    /// is able to do so.
+    ///
+    /// This method is similar to the `lock` method; however, it requires the
+    /// `Mutex` to be inside of an `Arc` and the resulting mutex guard has no
+    /// lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn lock_arc(self: &Arc<Self>) -> ArcMutexGuard<R, T> {
+        self.raw.lock();
+        // SAFETY: The lock is held, as required.
+        unsafe { self.guard_arc() }
+    }
+
+    /// Attempts to acquire a lock through an `Arc`.
+    ///
+    /// This method is similar to the `try_lock` method; however, it requires
+    /// the `Mutex` to be inside of an `Arc` and the resulting mutex guard has
+    /// no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_lock_arc(self: &Arc<Self>) -> Option<ArcMutexGuard<R, T>> {
+        if self.raw.try_lock() {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.guard_arc() })
+        } else {
+            None
+        }
+    }
+
     /// Returns a mutable reference to the underlying data.
     ///
     /// Since this call borrows the `Mutex` mutably, no actual locking needs to
@@ -263,6 +366,86 @@ impl<R: RawMutexTimed, T: ?Sized> Mutex<R, T> {
             None
         }
     }
+
+    /// Attempts to acquire this lock through an `Arc` until a timeout is
+    /// reached.
+    ///
+    /// This method is similar to the `try_lock_for` method; however, it
+    /// requires the `Mutex` to be inside of an `Arc` and the resulting mutex
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_lock_arc_for(self: &Arc<Self>, timeout: R::Duration) -> Option<ArcMutexGuard<R, T>> {
+        if self.raw.try_lock_for(timeout) {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.guard_arc() })
+        } else {
+            None
+        }
+    }
+
+    /// Attempts to acquire this lock through an `Arc` until a timeout is
+    /// reached.
+    ///
+    /// This method is similar to the `try_lock_until` method; however, it
+    /// requires the `Mutex` to be inside of an `Arc` and the resulting mutex
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_lock_arc_until(
+        self: &Arc<Self>,
+        timeout: R::Instant,
+    ) -> Option<ArcMutexGuard<R, T>> {
+        if self.raw.try_lock_until(timeout) {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.guard_arc() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<R: RawMutexDeadlock, T: ?Sized> Mutex<R, T> {
+    /// Acquires this mutex, unless doing so would deadlock.
+    ///
+    /// This behaves like `lock`, except that if the current thread ends up in
+    /// a deadlock, the underlying mutex may choose to abort this acquisition
+    /// and return an error instead of blocking forever. The caller is then
+    /// expected to release the locks it holds so the other threads involved
+    /// can make progress.
+    #[inline]
+    pub fn lock_or_deadlock(&self) -> Result<MutexGuard<'_, R, T>, R::DeadlockError> {
+        self.raw.lock_or_deadlock()?;
+        // SAFETY: The lock is held, as required.
+        Ok(unsafe { self.guard() })
+    }
+}
+
+impl<R: RawMutexAsync, T: ?Sized> Mutex<R, T> {
+    /// Acquires this mutex asynchronously.
+    ///
+    /// The returned future resolves to an RAII guard once the mutex has been
+    /// acquired. Tasks waiting for the mutex are queued together with threads
+    /// blocked in `lock`, and the mutex is handed over to them with the same
+    /// fairness guarantees.
+    ///
+    /// Dropping the future before it completes gives up on acquiring the
+    /// mutex.
+    ///
+    /// Debugging state which the raw lock keeps per thread, such as deadlock
+    /// detection, lock order tracking or hold time warnings, is recorded
+    /// against the thread which polls the future rather than against the task.
+    /// On an executor which runs several tasks on one thread, a guard held
+    /// across an `.await` therefore appears to be held by every task running
+    /// on that thread until it is dropped.
+    #[inline]
+    pub fn lock_async(&self) -> MutexLockFuture<'_, R, T> {
+        MutexLockFuture {
+            mutex: Some(self),
+            waiter: R::Waiter::default(),
+            pending: false,
+        }
+    }
 }
 
 impl<R: RawMutex, T: ?Sized + Default> Default for Mutex<R, T> {
@@ -328,6 +511,56 @@ where
     }
 }
 
+/// A future which resolves to a `MutexGuard` once the mutex has been acquired.
+///
+/// This is created by the `lock_async` method on `Mutex`.
+#[must_use = "futures do nothing unless polled"]
+pub struct MutexLockFuture<'a, R: RawMutexAsync, T: ?Sized> {
+    mutex: Option<&'a Mutex<R, T>>,
+    waiter: R::Waiter,
+    pending: bool,
+}
+
+impl<'a, R: RawMutexAsync + 'a, T: ?Sized + 'a> Future for MutexLockFuture<'a, R, T> {
+    type Output = MutexGuard<'a, R, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<MutexGuard<'a, R, T>> {
+        let this = &mut *self;
+        let mutex = this
+            .mutex
+            .expect("`MutexLockFuture` polled after completion");
+        // SAFETY: The waiter is only ever used with this mutex.
+        match unsafe { mutex.raw.poll_lock(&mut this.waiter, cx) } {
+            Poll::Ready(()) => {
+                this.mutex = None;
+                this.pending = false;
+                // SAFETY: The lock is held, as required.
+                Poll::Ready(unsafe { mutex.guard() })
+            }
+            Poll::Pending => {
+                this.pending = true;
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<'a, R: RawMutexAsync + 'a, T: ?Sized + 'a> Drop for MutexLockFuture<'a, R, T> {
+    #[inline]
+    fn drop(&mut self) {
+        if let (true, Some(mutex)) = (self.pending, self.mutex) {
+            // SAFETY: The waiter is only ever used with this mutex.
+            unsafe { mutex.raw.cancel_lock(&mut self.waiter) };
+        }
+    }
+}
+
+impl<'a, R: RawMutexAsync + 'a, T: ?Sized + 'a> fmt::Debug for MutexLockFuture<'a, R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("MutexLockFuture { .. }")
+    }
+}
+
 /// An RAII implementation of a "scoped lock" of a mutex. When this structure is
 /// dropped (falls out of scope), the lock will be unlocked.
 ///
@@ -628,3 +861,334 @@ impl<'a, R: RawMutex + 'a, T: fmt::Display + ?Sized + 'a> fmt::Display
 
 #[cfg(feature = "owning_ref")]
 unsafe impl<'a, R: RawMutex + 'a, T: ?Sized + 'a> StableAddress for MappedMutexGuard<'a, R, T> {}
+
+/// An RAII mutex guard returned by the `Arc` locking operations on `Mutex`.
+///
+/// This is similar to the `MutexGuard` struct, except instead of using a
+/// reference to unlock the `Mutex` it uses an `Arc<Mutex>`. This has several
+/// advantages, most notably that it has a `'static` lifetime.
+///
+/// Like the borrowed guard, it is only `Send` if `R::GuardMarker` is `Send`,
+/// so a `'static` guard does not by itself allow unlocking from another thread.
+#[cfg(feature = "arc_lock")]
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct ArcMutexGuard<R: RawMutex, T: ?Sized> {
+    mutex: Arc<Mutex<R, T>>,
+    marker: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawMutex + Send + Sync, T: ?Sized + Send> Send for ArcMutexGuard<R, T> where
+    R::GuardMarker: Send
+{
+}
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawMutex + Sync, T: ?Sized + Sync> Sync for ArcMutexGuard<R, T> {}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: ?Sized> ArcMutexGuard<R, T> {
+    /// Returns a reference to the `Mutex` this is guarding, contained in its `Arc`.
+    #[inline]
+    pub fn mutex(s: &Self) -> &Arc<Mutex<R, T>> {
+        &s.mutex
+    }
+
+    /// Unlocks the mutex and returns the `Arc` that was held by the guard.
+    #[inline]
+    pub fn into_arc(s: Self) -> Arc<Mutex<R, T>> {
+        s.mutex.raw.unlock();
+        Self::take_arc(s)
+    }
+
+    /// Extracts the `Arc` from the guard without unlocking the mutex.
+    #[inline]
+    fn take_arc(s: Self) -> Arc<Mutex<R, T>> {
+        let s = ManuallyDrop::new(s);
+        // SAFETY: The guard is never used or dropped again.
+        unsafe { ptr::read(&s.mutex) }
+    }
+
+    /// Makes a new `MappedArcMutexGuard` for a component of the locked data.
+    ///
+    /// This operation cannot fail as the `ArcMutexGuard` passed
+    /// in already locked the mutex.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `ArcMutexGuard::map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn map<U: ?Sized, F>(s: Self, f: F) -> MappedArcMutexGuard<R, T, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        let data = f(unsafe { &mut *s.mutex.data.get() }) as *mut U;
+        MappedArcMutexGuard {
+            mutex: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        }
+    }
+
+    /// Attempts to make a new `MappedArcMutexGuard` for a component of the
+    /// locked data. The original guard is returned if the closure returns `None`.
+    ///
+    /// This operation cannot fail as the `ArcMutexGuard` passed
+    /// in already locked the mutex.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `ArcMutexGuard::try_map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn try_map<U: ?Sized, F>(s: Self, f: F) -> Result<MappedArcMutexGuard<R, T, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        let data = match f(unsafe { &mut *s.mutex.data.get() }) {
+            Some(data) => data as *mut U,
+            None => return Err(s),
+        };
+        Ok(MappedArcMutexGuard {
+            mutex: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        })
+    }
+
+    /// Temporarily unlocks the mutex to execute the given function.
+    ///
+    /// This is safe because `&mut` guarantees that there exist no other
+    /// references to the data protected by the mutex.
+    #[inline]
+    pub fn unlocked<F, U>(s: &mut Self, f: F) -> U
+    where
+        F: FnOnce() -> U,
+    {
+        s.mutex.raw.unlock();
+        defer!(s.mutex.raw.lock());
+        f()
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutexFair, T: ?Sized> ArcMutexGuard<R, T> {
+    /// Unlocks the mutex using a fair unlock protocol.
+    ///
+    /// This is functionally identical to the `unlock_fair` method on `MutexGuard`.
+    #[inline]
+    pub fn unlock_fair(s: Self) {
+        s.mutex.raw.unlock_fair();
+        drop(Self::take_arc(s));
+    }
+
+    /// Temporarily unlocks the mutex to execute the given function.
+    ///
+    /// This is functionally identical to the `unlocked_fair` method on `MutexGuard`.
+    #[inline]
+    pub fn unlocked_fair<F, U>(s: &mut Self, f: F) -> U
+    where
+        F: FnOnce() -> U,
+    {
+        s.mutex.raw.unlock_fair();
+        defer!(s.mutex.raw.lock());
+        f()
+    }
+
+    /// Temporarily yields the mutex to a waiting thread if there is one.
+    ///
+    /// This is functionally identical to the `bump` method on `MutexGuard`.
+    #[inline]
+    pub fn bump(s: &mut Self) {
+        s.mutex.raw.bump();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: ?Sized> Deref for ArcMutexGuard<R, T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: ?Sized> DerefMut for ArcMutexGuard<R, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: ?Sized> Drop for ArcMutexGuard<R, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.mutex.raw.unlock();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: fmt::Debug + ?Sized> fmt::Debug for ArcMutexGuard<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: fmt::Display + ?Sized> fmt::Display for ArcMutexGuard<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(all(feature = "arc_lock", feature = "owning_ref"))]
+unsafe impl<R: RawMutex, T: ?Sized> StableAddress for ArcMutexGuard<R, T> {}
+
+/// An RAII mutex guard returned by `ArcMutexGuard::map`, which can point to a
+/// subfield of the protected data.
+///
+/// The guard keeps the `Arc` of the original `Mutex` alive, so like
+/// `ArcMutexGuard` it has no lifetime requirements. The main difference
+/// between `MappedArcMutexGuard` and `ArcMutexGuard` is that the former
+/// doesn't support temporarily unlocking and re-locking, since that could
+/// introduce soundness issues if the locked object is modified by another
+/// thread.
+#[cfg(feature = "arc_lock")]
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct MappedArcMutexGuard<R: RawMutex, T: ?Sized, U: ?Sized> {
+    mutex: Arc<Mutex<R, T>>,
+    data: *mut U,
+    marker: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawMutex + Send + Sync, T: ?Sized + Send, U: ?Sized + Send> Send
+    for MappedArcMutexGuard<R, T, U>
+where
+    R::GuardMarker: Send,
+{
+}
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawMutex + Sync, T: ?Sized + Sync, U: ?Sized + Sync> Sync
+    for MappedArcMutexGuard<R, T, U>
+{
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: ?Sized, U: ?Sized> MappedArcMutexGuard<R, T, U> {
+    /// Returns a reference to the `Mutex` this is guarding, contained in its `Arc`.
+    #[inline]
+    pub fn mutex(s: &Self) -> &Arc<Mutex<R, T>> {
+        &s.mutex
+    }
+
+    /// Extracts the `Arc` from the guard without unlocking the mutex.
+    #[inline]
+    fn take_arc(s: Self) -> Arc<Mutex<R, T>> {
+        let s = ManuallyDrop::new(s);
+        // SAFETY: The guard is never used or dropped again.
+        unsafe { ptr::read(&s.mutex) }
+    }
+
+    /// Makes a new `MappedArcMutexGuard` for a component of the locked data.
+    ///
+    /// This operation cannot fail as the `MappedArcMutexGuard` passed
+    /// in already locked the mutex.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `MappedArcMutexGuard::map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn map<V: ?Sized, F>(s: Self, f: F) -> MappedArcMutexGuard<R, T, V>
+    where
+        F: FnOnce(&mut U) -> &mut V,
+    {
+        let data = f(unsafe { &mut *s.data }) as *mut V;
+        MappedArcMutexGuard {
+            mutex: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        }
+    }
+
+    /// Attempts to make a new `MappedArcMutexGuard` for a component of the
+    /// locked data. The original guard is returned if the closure returns `None`.
+    ///
+    /// This operation cannot fail as the `MappedArcMutexGuard` passed
+    /// in already locked the mutex.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `MappedArcMutexGuard::try_map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn try_map<V: ?Sized, F>(s: Self, f: F) -> Result<MappedArcMutexGuard<R, T, V>, Self>
+    where
+        F: FnOnce(&mut U) -> Option<&mut V>,
+    {
+        let data = match f(unsafe { &mut *s.data }) {
+            Some(data) => data as *mut V,
+            None => return Err(s),
+        };
+        Ok(MappedArcMutexGuard {
+            mutex: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutexFair, T: ?Sized, U: ?Sized> MappedArcMutexGuard<R, T, U> {
+    /// Unlocks the mutex using a fair unlock protocol.
+    ///
+    /// This is functionally identical to the `unlock_fair` method on `MutexGuard`.
+    #[inline]
+    pub fn unlock_fair(s: Self) {
+        s.mutex.raw.unlock_fair();
+        drop(Self::take_arc(s));
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: ?Sized, U: ?Sized> Deref for MappedArcMutexGuard<R, T, U> {
+    type Target = U;
+    #[inline]
+    fn deref(&self) -> &U {
+        unsafe { &*self.data }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: ?Sized, U: ?Sized> DerefMut for MappedArcMutexGuard<R, T, U> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { &mut *self.data }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: ?Sized, U: ?Sized> Drop for MappedArcMutexGuard<R, T, U> {
+    #[inline]
+    fn drop(&mut self) {
+        self.mutex.raw.unlock();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: ?Sized, U: fmt::Debug + ?Sized> fmt::Debug for MappedArcMutexGuard<R, T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawMutex, T: ?Sized, U: fmt::Display + ?Sized> fmt::Display
+    for MappedArcMutexGuard<R, T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(all(feature = "arc_lock", feature = "owning_ref"))]
+unsafe impl<R: RawMutex, T: ?Sized, U: ?Sized> StableAddress for MappedArcMutexGuard<R, T, U> {}
@@ -0,0 +1,494 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::mutex::{Mutex, MutexGuard, RawMutex};
+use crate::rwlock::{RawRwLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+use std::thread;
+
+/// Poison state shared by `PoisonMutex` and `PoisonRwLock`.
+struct Flag {
+    failed: AtomicBool,
+}
+
+impl Flag {
+    #[inline]
+    const fn new() -> Flag {
+        Flag {
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    /// Called when a guard is created. Records whether the thread was already
+    /// panicking, in which case dropping the guard must not poison the lock.
+    #[inline]
+    fn guard(&self) -> bool {
+        thread::panicking()
+    }
+
+    /// Called when a guard is dropped.
+    #[inline]
+    fn done(&self, panicking: bool) {
+        if !panicking && thread::panicking() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    fn get(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn clear(&self) {
+        self.failed.store(false, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn map_result<T, U>(&self, value: T, f: impl FnOnce(T) -> U) -> LockResult<U> {
+        if self.get() {
+            Err(PoisonError::new(f(value)))
+        } else {
+            Ok(f(value))
+        }
+    }
+}
+
+struct LockedPlaceholder;
+
+impl fmt::Debug for LockedPlaceholder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<locked>")
+    }
+}
+
+/// A mutex which is poisoned if a thread panics while holding it, like the
+/// standard library `Mutex`.
+///
+/// This is a wrapper around `Mutex` which can be used as a drop-in
+/// replacement for `std::sync::Mutex` in code that relies on poisoning to
+/// detect data which may have been left in an inconsistent state by a panic.
+/// Its methods return the same `LockResult` and `PoisonError` types as the
+/// standard library.
+///
+/// A mutex is poisoned when a `PoisonMutexGuard` is dropped while its thread
+/// is unwinding from a panic. Once poisoned, all future attempts to lock it
+/// return an `Err` which still contains the guard, so the data can be
+/// inspected and repaired before calling `clear_poison`.
+pub struct PoisonMutex<R: RawMutex, T: ?Sized> {
+    poison: Flag,
+    inner: Mutex<R, T>,
+}
+
+impl<R: RawMutex, T> PoisonMutex<R, T> {
+    /// Creates a new mutex in an unlocked and unpoisoned state ready for use.
+    #[cfg(feature = "nightly")]
+    #[inline]
+    pub const fn new(val: T) -> PoisonMutex<R, T> {
+        PoisonMutex {
+            poison: Flag::new(),
+            inner: Mutex::new(val),
+        }
+    }
+
+    /// Creates a new mutex in an unlocked and unpoisoned state ready for use.
+    #[cfg(not(feature = "nightly"))]
+    #[inline]
+    pub fn new(val: T) -> PoisonMutex<R, T> {
+        PoisonMutex {
+            poison: Flag::new(),
+            inner: Mutex::new(val),
+        }
+    }
+
+    /// Consumes this mutex, returning the underlying data.
+    ///
+    /// If the mutex is poisoned then the data is returned inside a
+    /// `PoisonError`.
+    #[inline]
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let data = self.inner.into_inner();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+impl<R: RawMutex, T: ?Sized> PoisonMutex<R, T> {
+    #[inline]
+    fn guard<'a>(&'a self, guard: MutexGuard<'a, R, T>) -> PoisonMutexGuard<'a, R, T> {
+        PoisonMutexGuard {
+            poison: &self.poison,
+            panicking: self.poison.guard(),
+            guard,
+        }
+    }
+
+    /// Acquires the mutex, blocking the current thread until it is able to do
+    /// so.
+    ///
+    /// If another thread panicked while holding the mutex then an error
+    /// containing the guard is returned. The lock is acquired in both cases.
+    #[inline]
+    pub fn lock(&self) -> LockResult<PoisonMutexGuard<'_, R, T>> {
+        let guard = self.inner.lock();
+        self.poison.map_result(guard, |guard| self.guard(guard))
+    }
+
+    /// Attempts to acquire the mutex without blocking.
+    ///
+    /// Returns `TryLockError::WouldBlock` if the mutex is currently locked and
+    /// `TryLockError::Poisoned` if it was acquired but is poisoned.
+    #[inline]
+    pub fn try_lock(&self) -> TryLockResult<PoisonMutexGuard<'_, R, T>> {
+        match self.inner.try_lock() {
+            Some(guard) => Ok(self.poison.map_result(guard, |guard| self.guard(guard))?),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    /// Returns whether the mutex is poisoned.
+    ///
+    /// Another thread may poison the mutex at any time, so this should only be
+    /// used as a hint.
+    #[inline]
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// Clears the poisoned state of the mutex.
+    ///
+    /// This should be called once the data protected by the mutex has been
+    /// restored to a consistent state, typically while holding the guard
+    /// returned inside the `PoisonError`.
+    #[inline]
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the mutex mutably, no actual locking needs to
+    /// take place. If the mutex is poisoned then the reference is returned
+    /// inside a `PoisonError`.
+    #[inline]
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.poison.get();
+        let data = self.inner.get_mut();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+impl<R: RawMutex, T: Default> Default for PoisonMutex<R, T> {
+    #[inline]
+    fn default() -> PoisonMutex<R, T> {
+        PoisonMutex::new(Default::default())
+    }
+}
+
+impl<R: RawMutex, T> From<T> for PoisonMutex<R, T> {
+    #[inline]
+    fn from(t: T) -> PoisonMutex<R, T> {
+        PoisonMutex::new(t)
+    }
+}
+
+impl<R: RawMutex, T: ?Sized + fmt::Debug> fmt::Debug for PoisonMutex<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("PoisonMutex");
+        match self.inner.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &LockedPlaceholder),
+        };
+        d.field("poisoned", &self.poison.get()).finish()
+    }
+}
+
+/// An RAII guard returned by `PoisonMutex::lock`. When it is dropped the mutex
+/// is unlocked, and poisoned if the current thread is panicking.
+///
+/// The data protected by the mutex can be accessed through this guard via its
+/// `Deref` and `DerefMut` implementations.
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct PoisonMutexGuard<'a, R: RawMutex, T: ?Sized> {
+    poison: &'a Flag,
+    panicking: bool,
+    guard: MutexGuard<'a, R, T>,
+}
+
+impl<'a, R: RawMutex + 'a, T: ?Sized + 'a> Deref for PoisonMutexGuard<'a, R, T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, R: RawMutex + 'a, T: ?Sized + 'a> DerefMut for PoisonMutexGuard<'a, R, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<'a, R: RawMutex + 'a, T: ?Sized + 'a> Drop for PoisonMutexGuard<'a, R, T> {
+    #[inline]
+    fn drop(&mut self) {
+        // The inner guard unlocks the mutex after this, so the poison flag is
+        // visible to the next thread to acquire it.
+        self.poison.done(self.panicking);
+    }
+}
+
+impl<'a, R: RawMutex + 'a, T: fmt::Debug + ?Sized + 'a> fmt::Debug for PoisonMutexGuard<'a, R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, R: RawMutex + 'a, T: fmt::Display + ?Sized + 'a> fmt::Display
+    for PoisonMutexGuard<'a, R, T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// A reader-writer lock which is poisoned if a thread panics while holding it
+/// in write mode, like the standard library `RwLock`.
+///
+/// This is a wrapper around `RwLock` which returns the same `LockResult` and
+/// `PoisonError` types as `std::sync::RwLock`. Only a panic while holding a
+/// write lock poisons the lock, since readers can't leave the data in an
+/// inconsistent state. Read locks are returned as plain `RwLockReadGuard`s.
+pub struct PoisonRwLock<R: RawRwLock, T: ?Sized> {
+    poison: Flag,
+    inner: RwLock<R, T>,
+}
+
+impl<R: RawRwLock, T> PoisonRwLock<R, T> {
+    /// Creates a new instance of a `PoisonRwLock<T>` which is unlocked and
+    /// unpoisoned.
+    #[cfg(feature = "nightly")]
+    #[inline]
+    pub const fn new(val: T) -> PoisonRwLock<R, T> {
+        PoisonRwLock {
+            poison: Flag::new(),
+            inner: RwLock::new(val),
+        }
+    }
+
+    /// Creates a new instance of a `PoisonRwLock<T>` which is unlocked and
+    /// unpoisoned.
+    #[cfg(not(feature = "nightly"))]
+    #[inline]
+    pub fn new(val: T) -> PoisonRwLock<R, T> {
+        PoisonRwLock {
+            poison: Flag::new(),
+            inner: RwLock::new(val),
+        }
+    }
+
+    /// Consumes this `PoisonRwLock`, returning the underlying data.
+    ///
+    /// If the lock is poisoned then the data is returned inside a
+    /// `PoisonError`.
+    #[inline]
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let data = self.inner.into_inner();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+impl<R: RawRwLock, T: ?Sized> PoisonRwLock<R, T> {
+    #[inline]
+    fn write_guard<'a>(
+        &'a self,
+        guard: RwLockWriteGuard<'a, R, T>,
+    ) -> PoisonRwLockWriteGuard<'a, R, T> {
+        PoisonRwLockWriteGuard {
+            poison: &self.poison,
+            panicking: self.poison.guard(),
+            guard,
+        }
+    }
+
+    /// Locks this `PoisonRwLock` with shared read access, blocking the current
+    /// thread until it can be acquired.
+    ///
+    /// If another thread panicked while holding a write lock then an error
+    /// containing the guard is returned. The lock is acquired in both cases.
+    #[inline]
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, R, T>> {
+        self.poison.map_result(self.inner.read(), |guard| guard)
+    }
+
+    /// Attempts to acquire this `PoisonRwLock` with shared read access without
+    /// blocking.
+    ///
+    /// Returns `TryLockError::WouldBlock` if the lock could not be acquired
+    /// and `TryLockError::Poisoned` if it was acquired but is poisoned.
+    #[inline]
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, R, T>> {
+        match self.inner.try_read() {
+            Some(guard) => Ok(self.poison.map_result(guard, |guard| guard)?),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    /// Locks this `PoisonRwLock` with exclusive write access, blocking the
+    /// current thread until it can be acquired.
+    ///
+    /// If another thread panicked while holding a write lock then an error
+    /// containing the guard is returned. The lock is acquired in both cases.
+    #[inline]
+    pub fn write(&self) -> LockResult<PoisonRwLockWriteGuard<'_, R, T>> {
+        let guard = self.inner.write();
+        self.poison
+            .map_result(guard, |guard| self.write_guard(guard))
+    }
+
+    /// Attempts to acquire this `PoisonRwLock` with exclusive write access
+    /// without blocking.
+    ///
+    /// Returns `TryLockError::WouldBlock` if the lock could not be acquired
+    /// and `TryLockError::Poisoned` if it was acquired but is poisoned.
+    #[inline]
+    pub fn try_write(&self) -> TryLockResult<PoisonRwLockWriteGuard<'_, R, T>> {
+        match self.inner.try_write() {
+            Some(guard) => Ok(self
+                .poison
+                .map_result(guard, |guard| self.write_guard(guard))?),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    /// Returns whether the lock is poisoned.
+    ///
+    /// Another thread may poison the lock at any time, so this should only be
+    /// used as a hint.
+    #[inline]
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// Clears the poisoned state of the lock.
+    ///
+    /// This should be called once the data protected by the lock has been
+    /// restored to a consistent state, typically while holding the write guard
+    /// returned inside the `PoisonError`.
+    #[inline]
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the lock mutably, no actual locking needs to
+    /// take place. If the lock is poisoned then the reference is returned
+    /// inside a `PoisonError`.
+    #[inline]
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.poison.get();
+        let data = self.inner.get_mut();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+impl<R: RawRwLock, T: Default> Default for PoisonRwLock<R, T> {
+    #[inline]
+    fn default() -> PoisonRwLock<R, T> {
+        PoisonRwLock::new(Default::default())
+    }
+}
+
+impl<R: RawRwLock, T> From<T> for PoisonRwLock<R, T> {
+    #[inline]
+    fn from(t: T) -> PoisonRwLock<R, T> {
+        PoisonRwLock::new(t)
+    }
+}
+
+impl<R: RawRwLock, T: ?Sized + fmt::Debug> fmt::Debug for PoisonRwLock<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("PoisonRwLock");
+        match self.inner.try_read() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &LockedPlaceholder),
+        };
+        d.field("poisoned", &self.poison.get()).finish()
+    }
+}
+
+/// An RAII guard returned by `PoisonRwLock::write`. When it is dropped the
+/// write lock is released, and the lock is poisoned if the current thread is
+/// panicking.
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct PoisonRwLockWriteGuard<'a, R: RawRwLock, T: ?Sized> {
+    poison: &'a Flag,
+    panicking: bool,
+    guard: RwLockWriteGuard<'a, R, T>,
+}
+
+impl<'a, R: RawRwLock + 'a, T: ?Sized + 'a> Deref for PoisonRwLockWriteGuard<'a, R, T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, R: RawRwLock + 'a, T: ?Sized + 'a> DerefMut for PoisonRwLockWriteGuard<'a, R, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<'a, R: RawRwLock + 'a, T: ?Sized + 'a> Drop for PoisonRwLockWriteGuard<'a, R, T> {
+    #[inline]
+    fn drop(&mut self) {
+        // The inner guard unlocks the lock after this, so the poison flag is
+        // visible to the next thread to acquire it.
+        self.poison.done(self.panicking);
+    }
+}
+
+impl<'a, R: RawRwLock + 'a, T: fmt::Debug + ?Sized + 'a> fmt::Debug
+    for PoisonRwLockWriteGuard<'a, R, T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, R: RawRwLock + 'a, T: fmt::Display + ?Sized + 'a> fmt::Display
+    for PoisonRwLockWriteGuard<'a, R, T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
@@ -7,9 +7,19 @@
 
 use core::cell::UnsafeCell;
 use core::fmt;
+use core::future::Future;
 use core::marker::PhantomData;
 use core::mem;
 use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+#[cfg(feature = "arc_lock")]
+use alloc::sync::Arc;
+#[cfg(feature = "arc_lock")]
+use core::mem::ManuallyDrop;
+#[cfg(feature = "arc_lock")]
+use core::ptr;
 
 #[cfg(feature = "owning_ref")]
 use owning_ref::StableAddress;
@@ -124,6 +134,30 @@ pub unsafe trait RawRwLockTimed: RawRwLock {
     fn try_lock_exclusive_until(&self, timeout: Self::Instant) -> bool;
 }
 
+/// Additional methods for RwLocks which support failing a lock acquisition
+/// which would otherwise deadlock.
+///
+/// The error type is specified as an associated type so that this trait is
+/// usable even in `no_std` environments.
+///
+/// # Safety
+///
+/// Implementations of this trait must ensure that the lock is held in the
+/// requested mode when a method returns `Ok`, and that it isn't acquired
+/// when it returns `Err`.
+pub unsafe trait RawRwLockDeadlock: RawRwLock {
+    /// Error returned when the acquisition was aborted to break a deadlock.
+    type DeadlockError;
+
+    /// Acquires a shared lock, unless the current thread is chosen to break a
+    /// deadlock it is involved in.
+    fn lock_shared_or_deadlock(&self) -> Result<(), Self::DeadlockError>;
+
+    /// Acquires an exclusive lock, unless the current thread is chosen to
+    /// break a deadlock it is involved in.
+    fn lock_exclusive_or_deadlock(&self) -> Result<(), Self::DeadlockError>;
+}
+
 /// Additional methods for RwLocks which support recursive read locks.
 ///
 /// These are guaranteed to succeed without blocking if
@@ -219,6 +253,90 @@ pub unsafe trait RawRwLockUpgradeTimed: RawRwLockUpgrade + RawRwLockTimed {
     fn try_upgrade_until(&self, timeout: Self::Instant) -> bool;
 }
 
+/// Additional methods for RwLocks which support being locked from
+/// asynchronous code.
+///
+/// Instead of blocking the current thread, a task waiting for the lock
+/// registers its `Waker` and is woken up once it should poll again.
+///
+/// # Safety
+///
+/// Implementations of this trait must ensure that a task is only woken up
+/// with the lock held if the next poll will return `Poll::Ready`, and that
+/// cancelling releases the lock if it was handed over to the task.
+pub unsafe trait RawRwLockAsync: RawRwLock {
+    /// State kept by a task between polls while it is waiting for the lock.
+    type Waiter: Default + Unpin;
+
+    /// Attempts to acquire a shared lock, registering the waker of `cx` to be
+    /// woken up if the lock could not be acquired yet.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must only be used to acquire a shared lock of this `RwLock`.
+    unsafe fn poll_lock_shared(&self, waiter: &mut Self::Waiter, cx: &mut Context<'_>) -> Poll<()>;
+
+    /// Gives up on acquiring a shared lock after `poll_lock_shared` returned
+    /// `Poll::Pending`.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must only be used to acquire a shared lock of this `RwLock`.
+    unsafe fn cancel_lock_shared(&self, waiter: &mut Self::Waiter);
+
+    /// Attempts to acquire an exclusive lock, registering the waker of `cx` to
+    /// be woken up if the lock could not be acquired yet.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must only be used to acquire an exclusive lock of this
+    /// `RwLock`.
+    unsafe fn poll_lock_exclusive(
+        &self,
+        waiter: &mut Self::Waiter,
+        cx: &mut Context<'_>,
+    ) -> Poll<()>;
+
+    /// Gives up on acquiring an exclusive lock after `poll_lock_exclusive`
+    /// returned `Poll::Pending`.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must only be used to acquire an exclusive lock of this
+    /// `RwLock`.
+    unsafe fn cancel_lock_exclusive(&self, waiter: &mut Self::Waiter);
+}
+
+/// Additional methods for RwLocks which support upgradable locks and being
+/// locked from asynchronous code.
+///
+/// # Safety
+///
+/// The same requirements as for `RawRwLockAsync` apply to upgradable locks.
+pub unsafe trait RawRwLockUpgradeAsync: RawRwLockAsync + RawRwLockUpgrade {
+    /// Attempts to acquire an upgradable lock, registering the waker of `cx`
+    /// to be woken up if the lock could not be acquired yet.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must only be used to acquire an upgradable lock of this
+    /// `RwLock`.
+    unsafe fn poll_lock_upgradable(
+        &self,
+        waiter: &mut Self::Waiter,
+        cx: &mut Context<'_>,
+    ) -> Poll<()>;
+
+    /// Gives up on acquiring an upgradable lock after `poll_lock_upgradable`
+    /// returned `Poll::Pending`.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must only be used to acquire an upgradable lock of this
+    /// `RwLock`.
+    unsafe fn cancel_lock_upgradable(&self, waiter: &mut Self::Waiter);
+}
+
 /// A reader-writer lock
 ///
 /// This type of lock allows a number of readers or at most one writer at any
@@ -320,6 +438,30 @@ impl<R: RawRwLock, T: ?Sized> RwLock<R, T> {
         }
     }
 
+    /// # Safety
+    ///
+    /// The lock must be held when calling this method.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    unsafe fn read_guard_arc(self: &Arc<Self>) -> ArcRwLockReadGuard<R, T> {
+        ArcRwLockReadGuard {
+            rwlock: self.clone(),
+            marker: PhantomData,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The lock must be held when calling this method.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    unsafe fn write_guard_arc(self: &Arc<Self>) -> ArcRwLockWriteGuard<R, T> {
+        ArcRwLockWriteGuard {
+            rwlock: self.clone(),
+            marker: PhantomData,
+        }
+    }
+
     /// Locks this `RwLock` with shared read access, blocking the current thread
     /// until it can be acquired.
     ///
@@ -388,6 +530,65 @@ impl<R: RawRwLock, T: ?Sized> RwLock<R, T> {
         }
     }
 
+    /// Locks this `RwLock` with shared read access, through an `Arc`.
+    ///
+    /// This method is similar to the `read` method; however, it requires the
+    /// `RwLock` to be inside of an `Arc` and the resulting read guard has no
+    /// lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn read_arc(self: &Arc<Self>) -> ArcRwLockReadGuard<R, T> {
+        self.raw.lock_shared();
+        // SAFETY: The lock is held, as required.
+        unsafe { self.read_guard_arc() }
+    }
+
+    /// Attempts to lock this `RwLock` with shared read access, through an `Arc`.
+    ///
+    /// This method is similar to the `try_read` method; however, it requires
+    /// the `RwLock` to be inside of an `Arc` and the resulting read guard has
+    /// no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_read_arc(self: &Arc<Self>) -> Option<ArcRwLockReadGuard<R, T>> {
+        if self.raw.try_lock_shared() {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.read_guard_arc() })
+        } else {
+            None
+        }
+    }
+
+    /// Locks this `RwLock` with exclusive write access, through an `Arc`.
+    ///
+    /// This method is similar to the `write` method; however, it requires the
+    /// `RwLock` to be inside of an `Arc` and the resulting write guard has no
+    /// lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn write_arc(self: &Arc<Self>) -> ArcRwLockWriteGuard<R, T> {
+        self.raw.lock_exclusive();
+        // SAFETY: The lock is held, as required.
+        unsafe { self.write_guard_arc() }
+    }
+
+    /// Attempts to lock this `RwLock` with exclusive write access, through an
+    /// `Arc`.
+    ///
+    /// This method is similar to the `try_write` method; however, it requires
+    /// the `RwLock` to be inside of an `Arc` and the resulting write guard has
+    /// no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_write_arc(self: &Arc<Self>) -> Option<ArcRwLockWriteGuard<R, T>> {
+        if self.raw.try_lock_exclusive() {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.write_guard_arc() })
+        } else {
+            None
+        }
+    }
+
     /// Returns a mutable reference to the underlying data.
     ///
     /// Since this call borrows the `RwLock` mutably, no actual locking needs to
@@ -542,6 +743,114 @@ impl<R: RawRwLockTimed, T: ?Sized> RwLock<R, T> {
             None
         }
     }
+
+    /// Attempts to acquire this `RwLock` with shared read access, through an
+    /// `Arc`, until a timeout is reached.
+    ///
+    /// This method is similar to the `try_read_for` method; however, it
+    /// requires the `RwLock` to be inside of an `Arc` and the resulting read
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_read_arc_for(
+        self: &Arc<Self>,
+        timeout: R::Duration,
+    ) -> Option<ArcRwLockReadGuard<R, T>> {
+        if self.raw.try_lock_shared_for(timeout) {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.read_guard_arc() })
+        } else {
+            None
+        }
+    }
+
+    /// Attempts to acquire this `RwLock` with shared read access, through an
+    /// `Arc`, until a timeout is reached.
+    ///
+    /// This method is similar to the `try_read_until` method; however, it
+    /// requires the `RwLock` to be inside of an `Arc` and the resulting read
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_read_arc_until(
+        self: &Arc<Self>,
+        timeout: R::Instant,
+    ) -> Option<ArcRwLockReadGuard<R, T>> {
+        if self.raw.try_lock_shared_until(timeout) {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.read_guard_arc() })
+        } else {
+            None
+        }
+    }
+
+    /// Attempts to acquire this `RwLock` with exclusive write access, through
+    /// an `Arc`, until a timeout is reached.
+    ///
+    /// This method is similar to the `try_write_for` method; however, it
+    /// requires the `RwLock` to be inside of an `Arc` and the resulting write
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_write_arc_for(
+        self: &Arc<Self>,
+        timeout: R::Duration,
+    ) -> Option<ArcRwLockWriteGuard<R, T>> {
+        if self.raw.try_lock_exclusive_for(timeout) {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.write_guard_arc() })
+        } else {
+            None
+        }
+    }
+
+    /// Attempts to acquire this `RwLock` with exclusive write access, through
+    /// an `Arc`, until a timeout is reached.
+    ///
+    /// This method is similar to the `try_write_until` method; however, it
+    /// requires the `RwLock` to be inside of an `Arc` and the resulting write
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_write_arc_until(
+        self: &Arc<Self>,
+        timeout: R::Instant,
+    ) -> Option<ArcRwLockWriteGuard<R, T>> {
+        if self.raw.try_lock_exclusive_until(timeout) {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.write_guard_arc() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<R: RawRwLockDeadlock, T: ?Sized> RwLock<R, T> {
+    /// Locks this `RwLock` with shared read access, unless doing so would
+    /// deadlock.
+    ///
+    /// This behaves like `read`, except that if the current thread ends up in
+    /// a deadlock, the underlying lock may choose to abort this acquisition
+    /// and return an error instead of blocking forever.
+    #[inline]
+    pub fn read_or_deadlock(&self) -> Result<RwLockReadGuard<'_, R, T>, R::DeadlockError> {
+        self.raw.lock_shared_or_deadlock()?;
+        // SAFETY: The lock is held, as required.
+        Ok(unsafe { self.read_guard() })
+    }
+
+    /// Locks this `RwLock` with exclusive write access, unless doing so would
+    /// deadlock.
+    ///
+    /// This behaves like `write`, except that if the current thread ends up
+    /// in a deadlock, the underlying lock may choose to abort this acquisition
+    /// and return an error instead of blocking forever.
+    #[inline]
+    pub fn write_or_deadlock(&self) -> Result<RwLockWriteGuard<'_, R, T>, R::DeadlockError> {
+        self.raw.lock_exclusive_or_deadlock()?;
+        // SAFETY: The lock is held, as required.
+        Ok(unsafe { self.write_guard() })
+    }
 }
 
 impl<R: RawRwLockRecursive, T: ?Sized> RwLock<R, T> {
@@ -586,6 +895,37 @@ impl<R: RawRwLockRecursive, T: ?Sized> RwLock<R, T> {
             None
         }
     }
+
+    /// Locks this `RwLock` with shared read access, through an `Arc`, without
+    /// deadlocking in case of a recursive lock.
+    ///
+    /// This method is similar to the `read_recursive` method; however, it
+    /// requires the `RwLock` to be inside of an `Arc` and the resulting read
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn read_recursive_arc(self: &Arc<Self>) -> ArcRwLockReadGuard<R, T> {
+        self.raw.lock_shared_recursive();
+        // SAFETY: The lock is held, as required.
+        unsafe { self.read_guard_arc() }
+    }
+
+    /// Attempts to lock this `RwLock` with shared read access, through an
+    /// `Arc`, without deadlocking in case of a recursive lock.
+    ///
+    /// This method is similar to the `try_read_recursive` method; however, it
+    /// requires the `RwLock` to be inside of an `Arc` and the resulting read
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_read_recursive_arc(self: &Arc<Self>) -> Option<ArcRwLockReadGuard<R, T>> {
+        if self.raw.try_lock_shared_recursive() {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.read_guard_arc() })
+        } else {
+            None
+        }
+    }
 }
 
 impl<R: RawRwLockRecursiveTimed, T: ?Sized> RwLock<R, T> {
@@ -644,6 +984,18 @@ impl<R: RawRwLockUpgrade, T: ?Sized> RwLock<R, T> {
         }
     }
 
+    /// # Safety
+    ///
+    /// The lock must be held when calling this method.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    unsafe fn upgradable_guard_arc(self: &Arc<Self>) -> ArcRwLockUpgradableReadGuard<R, T> {
+        ArcRwLockUpgradableReadGuard {
+            rwlock: self.clone(),
+            marker: PhantomData,
+        }
+    }
+
     /// Locks this `RwLock` with upgradable read access, blocking the current thread
     /// until it can be acquired.
     ///
@@ -676,6 +1028,36 @@ impl<R: RawRwLockUpgrade, T: ?Sized> RwLock<R, T> {
             None
         }
     }
+
+    /// Locks this `RwLock` with upgradable read access, through an `Arc`.
+    ///
+    /// This method is similar to the `upgradable_read` method; however, it
+    /// requires the `RwLock` to be inside of an `Arc` and the resulting read
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn upgradable_read_arc(self: &Arc<Self>) -> ArcRwLockUpgradableReadGuard<R, T> {
+        self.raw.lock_upgradable();
+        // SAFETY: The lock is held, as required.
+        unsafe { self.upgradable_guard_arc() }
+    }
+
+    /// Attempts to lock this `RwLock` with upgradable read access, through an
+    /// `Arc`.
+    ///
+    /// This method is similar to the `try_upgradable_read` method; however, it
+    /// requires the `RwLock` to be inside of an `Arc` and the resulting read
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_upgradable_read_arc(self: &Arc<Self>) -> Option<ArcRwLockUpgradableReadGuard<R, T>> {
+        if self.raw.try_lock_upgradable() {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.upgradable_guard_arc() })
+        } else {
+            None
+        }
+    }
 }
 
 impl<R: RawRwLockUpgradeTimed, T: ?Sized> RwLock<R, T> {
@@ -716,6 +1098,120 @@ impl<R: RawRwLockUpgradeTimed, T: ?Sized> RwLock<R, T> {
             None
         }
     }
+
+    /// Attempts to lock this `RwLock` with upgradable read access, through an
+    /// `Arc`, until a timeout is reached.
+    ///
+    /// This method is similar to the `try_upgradable_read_for` method; however,
+    /// it requires the `RwLock` to be inside of an `Arc` and the resulting read
+    /// guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_upgradable_read_arc_for(
+        self: &Arc<Self>,
+        timeout: R::Duration,
+    ) -> Option<ArcRwLockUpgradableReadGuard<R, T>> {
+        if self.raw.try_lock_upgradable_for(timeout) {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.upgradable_guard_arc() })
+        } else {
+            None
+        }
+    }
+
+    /// Attempts to lock this `RwLock` with upgradable read access, through an
+    /// `Arc`, until a timeout is reached.
+    ///
+    /// This method is similar to the `try_upgradable_read_until` method;
+    /// however, it requires the `RwLock` to be inside of an `Arc` and the
+    /// resulting read guard has no lifetime requirements.
+    #[cfg(feature = "arc_lock")]
+    #[inline]
+    pub fn try_upgradable_read_arc_until(
+        self: &Arc<Self>,
+        timeout: R::Instant,
+    ) -> Option<ArcRwLockUpgradableReadGuard<R, T>> {
+        if self.raw.try_lock_upgradable_until(timeout) {
+            // SAFETY: The lock is held, as required.
+            Some(unsafe { self.upgradable_guard_arc() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<R: RawRwLockAsync, T: ?Sized> RwLock<R, T> {
+    /// Locks this `RwLock` with shared read access asynchronously.
+    ///
+    /// The returned future resolves to an RAII guard once shared access has
+    /// been acquired. Tasks waiting for the lock are queued together with
+    /// threads blocked in `read` and `write`, and the lock is handed over to
+    /// them with the same fairness guarantees.
+    ///
+    /// Dropping the future before it completes gives up on acquiring the lock.
+    ///
+    /// Debugging state which the raw lock keeps per thread, such as deadlock
+    /// detection, lock order tracking or hold time warnings, is recorded
+    /// against the thread which polls the future rather than against the task.
+    /// On an executor which runs several tasks on one thread, a guard held
+    /// across an `.await` therefore appears to be held by every task running
+    /// on that thread until it is dropped.
+    #[inline]
+    pub fn read_async(&self) -> RwLockReadFuture<'_, R, T> {
+        RwLockReadFuture {
+            rwlock: Some(self),
+            waiter: R::Waiter::default(),
+            pending: false,
+        }
+    }
+
+    /// Locks this `RwLock` with exclusive write access asynchronously.
+    ///
+    /// The returned future resolves to an RAII guard once exclusive access has
+    /// been acquired. Tasks waiting for the lock are queued together with
+    /// threads blocked in `read` and `write`, and the lock is handed over to
+    /// them with the same fairness guarantees.
+    ///
+    /// Dropping the future before it completes gives up on acquiring the lock.
+    ///
+    /// Debugging state which the raw lock keeps per thread, such as deadlock
+    /// detection, lock order tracking or hold time warnings, is recorded
+    /// against the thread which polls the future rather than against the task.
+    /// On an executor which runs several tasks on one thread, a guard held
+    /// across an `.await` therefore appears to be held by every task running
+    /// on that thread until it is dropped.
+    #[inline]
+    pub fn write_async(&self) -> RwLockWriteFuture<'_, R, T> {
+        RwLockWriteFuture {
+            rwlock: Some(self),
+            waiter: R::Waiter::default(),
+            pending: false,
+        }
+    }
+}
+
+impl<R: RawRwLockUpgradeAsync, T: ?Sized> RwLock<R, T> {
+    /// Locks this `RwLock` with upgradable read access asynchronously.
+    ///
+    /// The returned future resolves to an RAII guard once upgradable access
+    /// has been acquired.
+    ///
+    /// Dropping the future before it completes gives up on acquiring the lock.
+    ///
+    /// Debugging state which the raw lock keeps per thread, such as deadlock
+    /// detection, lock order tracking or hold time warnings, is recorded
+    /// against the thread which polls the future rather than against the task.
+    /// On an executor which runs several tasks on one thread, a guard held
+    /// across an `.await` therefore appears to be held by every task running
+    /// on that thread until it is dropped.
+    #[inline]
+    pub fn upgradable_read_async(&self) -> RwLockUpgradableReadFuture<'_, R, T> {
+        RwLockUpgradableReadFuture {
+            rwlock: Some(self),
+            waiter: R::Waiter::default(),
+            pending: false,
+        }
+    }
 }
 
 impl<R: RawRwLock, T: ?Sized + Default> Default for RwLock<R, T> {
@@ -752,31 +1248,193 @@ impl<R: RawRwLock, T: ?Sized + fmt::Debug> fmt::Debug for RwLock<R, T> {
     }
 }
 
-/// RAII structure used to release the shared read access of a lock when
-/// dropped.
-#[must_use = "if unused the RwLock will immediately unlock"]
-pub struct RwLockReadGuard<'a, R: RawRwLock, T: ?Sized> {
-    rwlock: &'a RwLock<R, T>,
-    marker: PhantomData<(&'a T, R::GuardMarker)>,
+/// A future which resolves to a `RwLockReadGuard` once the lock has been
+/// acquired.
+///
+/// This is created by the `read_async` method on `RwLock`.
+#[must_use = "futures do nothing unless polled"]
+pub struct RwLockReadFuture<'a, R: RawRwLockAsync, T: ?Sized> {
+    rwlock: Option<&'a RwLock<R, T>>,
+    waiter: R::Waiter,
+    pending: bool,
+}
+
+impl<'a, R: RawRwLockAsync + 'a, T: ?Sized + 'a> Future for RwLockReadFuture<'a, R, T> {
+    type Output = RwLockReadGuard<'a, R, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<RwLockReadGuard<'a, R, T>> {
+        let this = &mut *self;
+        let rwlock = this
+            .rwlock
+            .expect("`RwLockReadFuture` polled after completion");
+        // SAFETY: The waiter is only ever used with this lock.
+        match unsafe { rwlock.raw.poll_lock_shared(&mut this.waiter, cx) } {
+            Poll::Ready(()) => {
+                this.rwlock = None;
+                this.pending = false;
+                // SAFETY: The lock is held, as required.
+                Poll::Ready(unsafe { rwlock.read_guard() })
+            }
+            Poll::Pending => {
+                this.pending = true;
+                Poll::Pending
+            }
+        }
+    }
 }
 
-unsafe impl<'a, R: RawRwLock + 'a, T: ?Sized + Sync + 'a> Sync for RwLockReadGuard<'a, R, T> {}
+impl<'a, R: RawRwLockAsync + 'a, T: ?Sized + 'a> Drop for RwLockReadFuture<'a, R, T> {
+    #[inline]
+    fn drop(&mut self) {
+        if let (true, Some(rwlock)) = (self.pending, self.rwlock) {
+            // SAFETY: The waiter is only ever used with this lock.
+            unsafe { rwlock.raw.cancel_lock_shared(&mut self.waiter) };
+        }
+    }
+}
 
-impl<'a, R: RawRwLock + 'a, T: ?Sized + 'a> RwLockReadGuard<'a, R, T> {
-    /// Returns a reference to the original reader-writer lock object.
-    pub fn rwlock(s: &Self) -> &'a RwLock<R, T> {
-        s.rwlock
+impl<'a, R: RawRwLockAsync + 'a, T: ?Sized + 'a> fmt::Debug for RwLockReadFuture<'a, R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("RwLockReadFuture { .. }")
     }
+}
 
-    /// Make a new `MappedRwLockReadGuard` for a component of the locked data.
-    ///
-    /// This operation cannot fail as the `RwLockReadGuard` passed
-    /// in already locked the data.
-    ///
-    /// This is an associated function that needs to be
-    /// used as `RwLockReadGuard::map(...)`. A method would interfere with methods of
-    /// the same name on the contents of the locked data.
-    #[inline]
+/// A future which resolves to a `RwLockWriteGuard` once the lock has been
+/// acquired.
+///
+/// This is created by the `write_async` method on `RwLock`.
+#[must_use = "futures do nothing unless polled"]
+pub struct RwLockWriteFuture<'a, R: RawRwLockAsync, T: ?Sized> {
+    rwlock: Option<&'a RwLock<R, T>>,
+    waiter: R::Waiter,
+    pending: bool,
+}
+
+impl<'a, R: RawRwLockAsync + 'a, T: ?Sized + 'a> Future for RwLockWriteFuture<'a, R, T> {
+    type Output = RwLockWriteGuard<'a, R, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<RwLockWriteGuard<'a, R, T>> {
+        let this = &mut *self;
+        let rwlock = this
+            .rwlock
+            .expect("`RwLockWriteFuture` polled after completion");
+        // SAFETY: The waiter is only ever used with this lock.
+        match unsafe { rwlock.raw.poll_lock_exclusive(&mut this.waiter, cx) } {
+            Poll::Ready(()) => {
+                this.rwlock = None;
+                this.pending = false;
+                // SAFETY: The lock is held, as required.
+                Poll::Ready(unsafe { rwlock.write_guard() })
+            }
+            Poll::Pending => {
+                this.pending = true;
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<'a, R: RawRwLockAsync + 'a, T: ?Sized + 'a> Drop for RwLockWriteFuture<'a, R, T> {
+    #[inline]
+    fn drop(&mut self) {
+        if let (true, Some(rwlock)) = (self.pending, self.rwlock) {
+            // SAFETY: The waiter is only ever used with this lock.
+            unsafe { rwlock.raw.cancel_lock_exclusive(&mut self.waiter) };
+        }
+    }
+}
+
+impl<'a, R: RawRwLockAsync + 'a, T: ?Sized + 'a> fmt::Debug for RwLockWriteFuture<'a, R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("RwLockWriteFuture { .. }")
+    }
+}
+
+/// A future which resolves to a `RwLockUpgradableReadGuard` once the lock has been
+/// acquired.
+///
+/// This is created by the `upgradable_read_async` method on `RwLock`.
+#[must_use = "futures do nothing unless polled"]
+pub struct RwLockUpgradableReadFuture<'a, R: RawRwLockUpgradeAsync, T: ?Sized> {
+    rwlock: Option<&'a RwLock<R, T>>,
+    waiter: R::Waiter,
+    pending: bool,
+}
+
+impl<'a, R: RawRwLockUpgradeAsync + 'a, T: ?Sized + 'a> Future
+    for RwLockUpgradableReadFuture<'a, R, T>
+{
+    type Output = RwLockUpgradableReadGuard<'a, R, T>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<RwLockUpgradableReadGuard<'a, R, T>> {
+        let this = &mut *self;
+        let rwlock = this
+            .rwlock
+            .expect("`RwLockUpgradableReadFuture` polled after completion");
+        // SAFETY: The waiter is only ever used with this lock.
+        match unsafe { rwlock.raw.poll_lock_upgradable(&mut this.waiter, cx) } {
+            Poll::Ready(()) => {
+                this.rwlock = None;
+                this.pending = false;
+                // SAFETY: The lock is held, as required.
+                Poll::Ready(unsafe { rwlock.upgradable_guard() })
+            }
+            Poll::Pending => {
+                this.pending = true;
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<'a, R: RawRwLockUpgradeAsync + 'a, T: ?Sized + 'a> Drop
+    for RwLockUpgradableReadFuture<'a, R, T>
+{
+    #[inline]
+    fn drop(&mut self) {
+        if let (true, Some(rwlock)) = (self.pending, self.rwlock) {
+            // SAFETY: The waiter is only ever used with this lock.
+            unsafe { rwlock.raw.cancel_lock_upgradable(&mut self.waiter) };
+        }
+    }
+}
+
+impl<'a, R: RawRwLockUpgradeAsync + 'a, T: ?Sized + 'a> fmt::Debug
+    for RwLockUpgradableReadFuture<'a, R, T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("RwLockUpgradableReadFuture { .. }")
+    }
+}
+
+/// RAII structure used to release the shared read access of a lock when
+/// dropped.
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct RwLockReadGuard<'a, R: RawRwLock, T: ?Sized> {
+    rwlock: &'a RwLock<R, T>,
+    marker: PhantomData<(&'a T, R::GuardMarker)>,
+}
+
+unsafe impl<'a, R: RawRwLock + 'a, T: ?Sized + Sync + 'a> Sync for RwLockReadGuard<'a, R, T> {}
+
+impl<'a, R: RawRwLock + 'a, T: ?Sized + 'a> RwLockReadGuard<'a, R, T> {
+    /// Returns a reference to the original reader-writer lock object.
+    pub fn rwlock(s: &Self) -> &'a RwLock<R, T> {
+        s.rwlock
+    }
+
+    /// Make a new `MappedRwLockReadGuard` for a component of the locked data.
+    ///
+    /// This operation cannot fail as the `RwLockReadGuard` passed
+    /// in already locked the data.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `RwLockReadGuard::map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
     pub fn map<U: ?Sized, F>(s: Self, f: F) -> MappedRwLockReadGuard<'a, R, U>
     where
         F: FnOnce(&T) -> &U,
@@ -1611,3 +2269,921 @@ unsafe impl<'a, R: RawRwLock + 'a, T: ?Sized + 'a> StableAddress
     for MappedRwLockWriteGuard<'a, R, T>
 {
 }
+
+/// An RAII rwlock guard returned by the `Arc` locking operations on `RwLock`.
+///
+/// This is similar to the `RwLockReadGuard` struct, except instead of using a
+/// reference to unlock the `RwLock` it uses an `Arc<RwLock>`. This has several
+/// advantages, most notably that it has a `'static` lifetime.
+///
+/// Like the borrowed guard, it is only `Send` if `R::GuardMarker` is `Send`,
+/// so a `'static` guard does not by itself allow unlocking from another thread.
+#[cfg(feature = "arc_lock")]
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct ArcRwLockReadGuard<R: RawRwLock, T: ?Sized> {
+    rwlock: Arc<RwLock<R, T>>,
+    marker: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawRwLock + Send + Sync, T: ?Sized + Send + Sync> Send for ArcRwLockReadGuard<R, T> where
+    R::GuardMarker: Send
+{
+}
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawRwLock + Sync, T: ?Sized + Sync> Sync for ArcRwLockReadGuard<R, T> {}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized> ArcRwLockReadGuard<R, T> {
+    /// Returns a reference to the `RwLock` this is guarding, contained in its `Arc`.
+    #[inline]
+    pub fn rwlock(s: &Self) -> &Arc<RwLock<R, T>> {
+        &s.rwlock
+    }
+
+    /// Unlocks the `RwLock` and returns the `Arc` that was held by the guard.
+    #[inline]
+    pub fn into_arc(s: Self) -> Arc<RwLock<R, T>> {
+        s.rwlock.raw.unlock_shared();
+        Self::take_arc(s)
+    }
+
+    /// Extracts the `Arc` from the guard without unlocking the `RwLock`.
+    #[inline]
+    fn take_arc(s: Self) -> Arc<RwLock<R, T>> {
+        let s = ManuallyDrop::new(s);
+        // SAFETY: The guard is never used or dropped again.
+        unsafe { ptr::read(&s.rwlock) }
+    }
+
+    /// Make a new `MappedArcRwLockReadGuard` for a component of the locked data.
+    ///
+    /// This operation cannot fail as the `ArcRwLockReadGuard` passed
+    /// in already locked the data.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `ArcRwLockReadGuard::map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn map<U: ?Sized, F>(s: Self, f: F) -> MappedArcRwLockReadGuard<R, T, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        let data = f(unsafe { &*s.rwlock.data.get() }) as *const U;
+        MappedArcRwLockReadGuard {
+            rwlock: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        }
+    }
+
+    /// Attempts to make a new `MappedArcRwLockReadGuard` for a component of the
+    /// locked data. The original guard is returned if the closure returns `None`.
+    ///
+    /// This operation cannot fail as the `ArcRwLockReadGuard` passed
+    /// in already locked the data.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `ArcRwLockReadGuard::try_map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn try_map<U: ?Sized, F>(s: Self, f: F) -> Result<MappedArcRwLockReadGuard<R, T, U>, Self>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        let data = match f(unsafe { &*s.rwlock.data.get() }) {
+            Some(data) => data as *const U,
+            None => return Err(s),
+        };
+        Ok(MappedArcRwLockReadGuard {
+            rwlock: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        })
+    }
+
+    /// Temporarily unlocks the `RwLock` to execute the given function.
+    ///
+    /// This is functionally identical to the `unlocked` method on `RwLockReadGuard`.
+    #[inline]
+    pub fn unlocked<F, U>(s: &mut Self, f: F) -> U
+    where
+        F: FnOnce() -> U,
+    {
+        s.rwlock.raw.unlock_shared();
+        defer!(s.rwlock.raw.lock_shared());
+        f()
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockFair, T: ?Sized> ArcRwLockReadGuard<R, T> {
+    /// Unlocks the `RwLock` using a fair unlock protocol.
+    ///
+    /// This is functionally identical to the `unlock_fair` method on `RwLockReadGuard`.
+    #[inline]
+    pub fn unlock_fair(s: Self) {
+        s.rwlock.raw.unlock_shared_fair();
+        drop(Self::take_arc(s));
+    }
+
+    /// Temporarily unlocks the `RwLock` to execute the given function.
+    ///
+    /// This is functionally identical to the `unlocked_fair` method on `RwLockReadGuard`.
+    #[inline]
+    pub fn unlocked_fair<F, U>(s: &mut Self, f: F) -> U
+    where
+        F: FnOnce() -> U,
+    {
+        s.rwlock.raw.unlock_shared_fair();
+        defer!(s.rwlock.raw.lock_shared());
+        f()
+    }
+
+    /// Temporarily yields the `RwLock` to a waiting thread if there is one.
+    ///
+    /// This is functionally identical to the `bump` method on `RwLockReadGuard`.
+    #[inline]
+    pub fn bump(s: &mut Self) {
+        s.rwlock.raw.bump_shared();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized> Deref for ArcRwLockReadGuard<R, T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized> Drop for ArcRwLockReadGuard<R, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.rwlock.raw.unlock_shared();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: fmt::Debug + ?Sized> fmt::Debug for ArcRwLockReadGuard<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: fmt::Display + ?Sized> fmt::Display for ArcRwLockReadGuard<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(all(feature = "arc_lock", feature = "owning_ref"))]
+unsafe impl<R: RawRwLock, T: ?Sized> StableAddress for ArcRwLockReadGuard<R, T> {}
+
+/// An RAII rwlock guard returned by the `Arc` locking operations on `RwLock`.
+///
+/// This is similar to the `RwLockWriteGuard` struct, except instead of using a
+/// reference to unlock the `RwLock` it uses an `Arc<RwLock>`. This has several
+/// advantages, most notably that it has a `'static` lifetime.
+///
+/// Like the borrowed guard, it is only `Send` if `R::GuardMarker` is `Send`,
+/// so a `'static` guard does not by itself allow unlocking from another thread.
+#[cfg(feature = "arc_lock")]
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct ArcRwLockWriteGuard<R: RawRwLock, T: ?Sized> {
+    rwlock: Arc<RwLock<R, T>>,
+    marker: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawRwLock + Send + Sync, T: ?Sized + Send + Sync> Send for ArcRwLockWriteGuard<R, T> where
+    R::GuardMarker: Send
+{
+}
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawRwLock + Sync, T: ?Sized + Sync> Sync for ArcRwLockWriteGuard<R, T> {}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized> ArcRwLockWriteGuard<R, T> {
+    /// Returns a reference to the `RwLock` this is guarding, contained in its `Arc`.
+    #[inline]
+    pub fn rwlock(s: &Self) -> &Arc<RwLock<R, T>> {
+        &s.rwlock
+    }
+
+    /// Unlocks the `RwLock` and returns the `Arc` that was held by the guard.
+    #[inline]
+    pub fn into_arc(s: Self) -> Arc<RwLock<R, T>> {
+        s.rwlock.raw.unlock_exclusive();
+        Self::take_arc(s)
+    }
+
+    /// Extracts the `Arc` from the guard without unlocking the `RwLock`.
+    #[inline]
+    fn take_arc(s: Self) -> Arc<RwLock<R, T>> {
+        let s = ManuallyDrop::new(s);
+        // SAFETY: The guard is never used or dropped again.
+        unsafe { ptr::read(&s.rwlock) }
+    }
+
+    /// Make a new `MappedArcRwLockWriteGuard` for a component of the locked data.
+    ///
+    /// This operation cannot fail as the `ArcRwLockWriteGuard` passed
+    /// in already locked the data.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `ArcRwLockWriteGuard::map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn map<U: ?Sized, F>(s: Self, f: F) -> MappedArcRwLockWriteGuard<R, T, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        let data = f(unsafe { &mut *s.rwlock.data.get() }) as *mut U;
+        MappedArcRwLockWriteGuard {
+            rwlock: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        }
+    }
+
+    /// Attempts to make a new `MappedArcRwLockWriteGuard` for a component of the
+    /// locked data. The original guard is returned if the closure returns `None`.
+    ///
+    /// This operation cannot fail as the `ArcRwLockWriteGuard` passed
+    /// in already locked the data.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `ArcRwLockWriteGuard::try_map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn try_map<U: ?Sized, F>(s: Self, f: F) -> Result<MappedArcRwLockWriteGuard<R, T, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        let data = match f(unsafe { &mut *s.rwlock.data.get() }) {
+            Some(data) => data as *mut U,
+            None => return Err(s),
+        };
+        Ok(MappedArcRwLockWriteGuard {
+            rwlock: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        })
+    }
+
+    /// Temporarily unlocks the `RwLock` to execute the given function.
+    ///
+    /// This is functionally identical to the `unlocked` method on `RwLockWriteGuard`.
+    #[inline]
+    pub fn unlocked<F, U>(s: &mut Self, f: F) -> U
+    where
+        F: FnOnce() -> U,
+    {
+        s.rwlock.raw.unlock_exclusive();
+        defer!(s.rwlock.raw.lock_exclusive());
+        f()
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockDowngrade, T: ?Sized> ArcRwLockWriteGuard<R, T> {
+    /// Atomically downgrades a write lock into a read lock without allowing any
+    /// writers to take exclusive access of the lock in the meantime.
+    ///
+    /// This is functionally identical to the `downgrade` method on `RwLockWriteGuard`.
+    pub fn downgrade(s: Self) -> ArcRwLockReadGuard<R, T> {
+        s.rwlock.raw.downgrade();
+        ArcRwLockReadGuard {
+            rwlock: Self::take_arc(s),
+            marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockUpgradeDowngrade, T: ?Sized> ArcRwLockWriteGuard<R, T> {
+    /// Atomically downgrades a write lock into an upgradable read lock without
+    /// allowing any writers to take exclusive access of the lock in the meantime.
+    ///
+    /// This is functionally identical to the `downgrade_to_upgradable` method
+    /// on `RwLockWriteGuard`.
+    pub fn downgrade_to_upgradable(s: Self) -> ArcRwLockUpgradableReadGuard<R, T> {
+        s.rwlock.raw.downgrade_to_upgradable();
+        ArcRwLockUpgradableReadGuard {
+            rwlock: Self::take_arc(s),
+            marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockFair, T: ?Sized> ArcRwLockWriteGuard<R, T> {
+    /// Unlocks the `RwLock` using a fair unlock protocol.
+    ///
+    /// This is functionally identical to the `unlock_fair` method on `RwLockWriteGuard`.
+    #[inline]
+    pub fn unlock_fair(s: Self) {
+        s.rwlock.raw.unlock_exclusive_fair();
+        drop(Self::take_arc(s));
+    }
+
+    /// Temporarily unlocks the `RwLock` to execute the given function.
+    ///
+    /// This is functionally identical to the `unlocked_fair` method on `RwLockWriteGuard`.
+    #[inline]
+    pub fn unlocked_fair<F, U>(s: &mut Self, f: F) -> U
+    where
+        F: FnOnce() -> U,
+    {
+        s.rwlock.raw.unlock_exclusive_fair();
+        defer!(s.rwlock.raw.lock_exclusive());
+        f()
+    }
+
+    /// Temporarily yields the `RwLock` to a waiting thread if there is one.
+    ///
+    /// This is functionally identical to the `bump` method on `RwLockWriteGuard`.
+    #[inline]
+    pub fn bump(s: &mut Self) {
+        s.rwlock.raw.bump_exclusive();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized> Deref for ArcRwLockWriteGuard<R, T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized> DerefMut for ArcRwLockWriteGuard<R, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.rwlock.data.get() }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized> Drop for ArcRwLockWriteGuard<R, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.rwlock.raw.unlock_exclusive();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: fmt::Debug + ?Sized> fmt::Debug for ArcRwLockWriteGuard<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: fmt::Display + ?Sized> fmt::Display for ArcRwLockWriteGuard<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(all(feature = "arc_lock", feature = "owning_ref"))]
+unsafe impl<R: RawRwLock, T: ?Sized> StableAddress for ArcRwLockWriteGuard<R, T> {}
+
+/// An RAII rwlock guard returned by the `Arc` locking operations on `RwLock`.
+///
+/// This is similar to the `RwLockUpgradableReadGuard` struct, except instead of
+/// using a reference to unlock the `RwLock` it uses an `Arc<RwLock>`. This has
+/// several advantages, most notably that it has a `'static` lifetime.
+///
+/// Like the borrowed guard, it is only `Send` if `R::GuardMarker` is `Send`,
+/// so a `'static` guard does not by itself allow unlocking from another thread.
+#[cfg(feature = "arc_lock")]
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct ArcRwLockUpgradableReadGuard<R: RawRwLockUpgrade, T: ?Sized> {
+    rwlock: Arc<RwLock<R, T>>,
+    marker: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawRwLockUpgrade + Send + Sync, T: ?Sized + Send + Sync> Send
+    for ArcRwLockUpgradableReadGuard<R, T>
+where
+    R::GuardMarker: Send,
+{
+}
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawRwLockUpgrade + Sync, T: ?Sized + Sync> Sync
+    for ArcRwLockUpgradableReadGuard<R, T>
+{
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockUpgrade, T: ?Sized> ArcRwLockUpgradableReadGuard<R, T> {
+    /// Returns a reference to the `RwLock` this is guarding, contained in its `Arc`.
+    #[inline]
+    pub fn rwlock(s: &Self) -> &Arc<RwLock<R, T>> {
+        &s.rwlock
+    }
+
+    /// Unlocks the `RwLock` and returns the `Arc` that was held by the guard.
+    #[inline]
+    pub fn into_arc(s: Self) -> Arc<RwLock<R, T>> {
+        s.rwlock.raw.unlock_upgradable();
+        Self::take_arc(s)
+    }
+
+    /// Extracts the `Arc` from the guard without unlocking the `RwLock`.
+    #[inline]
+    fn take_arc(s: Self) -> Arc<RwLock<R, T>> {
+        let s = ManuallyDrop::new(s);
+        // SAFETY: The guard is never used or dropped again.
+        unsafe { ptr::read(&s.rwlock) }
+    }
+
+    /// Temporarily unlocks the `RwLock` to execute the given function.
+    ///
+    /// This is functionally identical to the `unlocked` method on
+    /// `RwLockUpgradableReadGuard`.
+    #[inline]
+    pub fn unlocked<F, U>(s: &mut Self, f: F) -> U
+    where
+        F: FnOnce() -> U,
+    {
+        s.rwlock.raw.unlock_upgradable();
+        defer!(s.rwlock.raw.lock_upgradable());
+        f()
+    }
+
+    /// Atomically upgrades an upgradable read lock lock into a exclusive write lock,
+    /// blocking the current thread until it can be acquired.
+    pub fn upgrade(s: Self) -> ArcRwLockWriteGuard<R, T> {
+        s.rwlock.raw.upgrade();
+        ArcRwLockWriteGuard {
+            rwlock: Self::take_arc(s),
+            marker: PhantomData,
+        }
+    }
+
+    /// Tries to atomically upgrade an upgradable read lock into a exclusive write lock.
+    ///
+    /// If the access could not be granted at this time, then the current guard is returned.
+    pub fn try_upgrade(s: Self) -> Result<ArcRwLockWriteGuard<R, T>, Self> {
+        if s.rwlock.raw.try_upgrade() {
+            Ok(ArcRwLockWriteGuard {
+                rwlock: Self::take_arc(s),
+                marker: PhantomData,
+            })
+        } else {
+            Err(s)
+        }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockUpgradeFair, T: ?Sized> ArcRwLockUpgradableReadGuard<R, T> {
+    /// Unlocks the `RwLock` using a fair unlock protocol.
+    ///
+    /// This is functionally identical to the `unlock_fair` method on
+    /// `RwLockUpgradableReadGuard`.
+    #[inline]
+    pub fn unlock_fair(s: Self) {
+        s.rwlock.raw.unlock_upgradable_fair();
+        drop(Self::take_arc(s));
+    }
+
+    /// Temporarily unlocks the `RwLock` to execute the given function.
+    ///
+    /// This is functionally identical to the `unlocked_fair` method on
+    /// `RwLockUpgradableReadGuard`.
+    #[inline]
+    pub fn unlocked_fair<F, U>(s: &mut Self, f: F) -> U
+    where
+        F: FnOnce() -> U,
+    {
+        s.rwlock.raw.unlock_upgradable_fair();
+        defer!(s.rwlock.raw.lock_upgradable());
+        f()
+    }
+
+    /// Temporarily yields the `RwLock` to a waiting thread if there is one.
+    ///
+    /// This is functionally identical to the `bump` method on
+    /// `RwLockUpgradableReadGuard`.
+    #[inline]
+    pub fn bump(s: &mut Self) {
+        s.rwlock.raw.bump_upgradable();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockUpgradeDowngrade, T: ?Sized> ArcRwLockUpgradableReadGuard<R, T> {
+    /// Atomically downgrades an upgradable read lock lock into a shared read lock
+    /// without allowing any writers to take exclusive access of the lock in the
+    /// meantime.
+    ///
+    /// This is functionally identical to the `downgrade` method on
+    /// `RwLockUpgradableReadGuard`.
+    pub fn downgrade(s: Self) -> ArcRwLockReadGuard<R, T> {
+        s.rwlock.raw.downgrade_upgradable();
+        ArcRwLockReadGuard {
+            rwlock: Self::take_arc(s),
+            marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockUpgradeTimed, T: ?Sized> ArcRwLockUpgradableReadGuard<R, T> {
+    /// Tries to atomically upgrade an upgradable read lock into a exclusive
+    /// write lock, until a timeout is reached.
+    ///
+    /// If the access could not be granted before the timeout expires, then
+    /// the current guard is returned.
+    pub fn try_upgrade_for(
+        s: Self,
+        timeout: R::Duration,
+    ) -> Result<ArcRwLockWriteGuard<R, T>, Self> {
+        if s.rwlock.raw.try_upgrade_for(timeout) {
+            Ok(ArcRwLockWriteGuard {
+                rwlock: Self::take_arc(s),
+                marker: PhantomData,
+            })
+        } else {
+            Err(s)
+        }
+    }
+
+    /// Tries to atomically upgrade an upgradable read lock into a exclusive
+    /// write lock, until a timeout is reached.
+    ///
+    /// If the access could not be granted before the timeout expires, then
+    /// the current guard is returned.
+    #[inline]
+    pub fn try_upgrade_until(
+        s: Self,
+        timeout: R::Instant,
+    ) -> Result<ArcRwLockWriteGuard<R, T>, Self> {
+        if s.rwlock.raw.try_upgrade_until(timeout) {
+            Ok(ArcRwLockWriteGuard {
+                rwlock: Self::take_arc(s),
+                marker: PhantomData,
+            })
+        } else {
+            Err(s)
+        }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockUpgrade, T: ?Sized> Deref for ArcRwLockUpgradableReadGuard<R, T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockUpgrade, T: ?Sized> Drop for ArcRwLockUpgradableReadGuard<R, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.rwlock.raw.unlock_upgradable();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockUpgrade, T: fmt::Debug + ?Sized> fmt::Debug
+    for ArcRwLockUpgradableReadGuard<R, T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockUpgrade, T: fmt::Display + ?Sized> fmt::Display
+    for ArcRwLockUpgradableReadGuard<R, T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(all(feature = "arc_lock", feature = "owning_ref"))]
+unsafe impl<R: RawRwLockUpgrade, T: ?Sized> StableAddress for ArcRwLockUpgradableReadGuard<R, T> {}
+
+/// An RAII read lock guard returned by `ArcRwLockReadGuard::map`, which can
+/// point to a subfield of the protected data.
+///
+/// The guard keeps the `Arc` of the original `RwLock` alive, so like
+/// `ArcRwLockReadGuard` it has no lifetime requirements.
+#[cfg(feature = "arc_lock")]
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct MappedArcRwLockReadGuard<R: RawRwLock, T: ?Sized, U: ?Sized> {
+    rwlock: Arc<RwLock<R, T>>,
+    data: *const U,
+    marker: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawRwLock + Send + Sync, T: ?Sized + Send + Sync, U: ?Sized + Sync> Send
+    for MappedArcRwLockReadGuard<R, T, U>
+where
+    R::GuardMarker: Send,
+{
+}
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawRwLock + Sync, T: ?Sized + Sync, U: ?Sized + Sync> Sync
+    for MappedArcRwLockReadGuard<R, T, U>
+{
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: ?Sized> MappedArcRwLockReadGuard<R, T, U> {
+    /// Returns a reference to the `RwLock` this is guarding, contained in its `Arc`.
+    #[inline]
+    pub fn rwlock(s: &Self) -> &Arc<RwLock<R, T>> {
+        &s.rwlock
+    }
+
+    /// Extracts the `Arc` from the guard without unlocking the `RwLock`.
+    #[inline]
+    fn take_arc(s: Self) -> Arc<RwLock<R, T>> {
+        let s = ManuallyDrop::new(s);
+        // SAFETY: The guard is never used or dropped again.
+        unsafe { ptr::read(&s.rwlock) }
+    }
+
+    /// Make a new `MappedArcRwLockReadGuard` for a component of the locked data.
+    ///
+    /// This operation cannot fail as the `MappedArcRwLockReadGuard` passed
+    /// in already locked the data.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `MappedArcRwLockReadGuard::map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn map<V: ?Sized, F>(s: Self, f: F) -> MappedArcRwLockReadGuard<R, T, V>
+    where
+        F: FnOnce(&U) -> &V,
+    {
+        let data = f(unsafe { &*s.data }) as *const V;
+        MappedArcRwLockReadGuard {
+            rwlock: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        }
+    }
+
+    /// Attempts to make a new `MappedArcRwLockReadGuard` for a component of the
+    /// locked data. The original guard is returned if the closure returns `None`.
+    ///
+    /// This operation cannot fail as the `MappedArcRwLockReadGuard` passed
+    /// in already locked the data.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `MappedArcRwLockReadGuard::try_map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn try_map<V: ?Sized, F>(s: Self, f: F) -> Result<MappedArcRwLockReadGuard<R, T, V>, Self>
+    where
+        F: FnOnce(&U) -> Option<&V>,
+    {
+        let data = match f(unsafe { &*s.data }) {
+            Some(data) => data as *const V,
+            None => return Err(s),
+        };
+        Ok(MappedArcRwLockReadGuard {
+            rwlock: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockFair, T: ?Sized, U: ?Sized> MappedArcRwLockReadGuard<R, T, U> {
+    /// Unlocks the `RwLock` using a fair unlock protocol.
+    ///
+    /// This is functionally identical to the `unlock_fair` method on `RwLockReadGuard`.
+    #[inline]
+    pub fn unlock_fair(s: Self) {
+        s.rwlock.raw.unlock_shared_fair();
+        drop(Self::take_arc(s));
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: ?Sized> Deref for MappedArcRwLockReadGuard<R, T, U> {
+    type Target = U;
+    #[inline]
+    fn deref(&self) -> &U {
+        unsafe { &*self.data }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: ?Sized> Drop for MappedArcRwLockReadGuard<R, T, U> {
+    #[inline]
+    fn drop(&mut self) {
+        self.rwlock.raw.unlock_shared();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: fmt::Debug + ?Sized> fmt::Debug
+    for MappedArcRwLockReadGuard<R, T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: fmt::Display + ?Sized> fmt::Display
+    for MappedArcRwLockReadGuard<R, T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(all(feature = "arc_lock", feature = "owning_ref"))]
+unsafe impl<R: RawRwLock, T: ?Sized, U: ?Sized> StableAddress
+    for MappedArcRwLockReadGuard<R, T, U>
+{
+}
+
+/// An RAII write lock guard returned by `ArcRwLockWriteGuard::map`, which can
+/// point to a subfield of the protected data.
+///
+/// The guard keeps the `Arc` of the original `RwLock` alive, so like
+/// `ArcRwLockWriteGuard` it has no lifetime requirements.
+#[cfg(feature = "arc_lock")]
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct MappedArcRwLockWriteGuard<R: RawRwLock, T: ?Sized, U: ?Sized> {
+    rwlock: Arc<RwLock<R, T>>,
+    data: *mut U,
+    marker: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawRwLock + Send + Sync, T: ?Sized + Send + Sync, U: ?Sized + Send> Send
+    for MappedArcRwLockWriteGuard<R, T, U>
+where
+    R::GuardMarker: Send,
+{
+}
+#[cfg(feature = "arc_lock")]
+unsafe impl<R: RawRwLock + Sync, T: ?Sized + Sync, U: ?Sized + Sync> Sync
+    for MappedArcRwLockWriteGuard<R, T, U>
+{
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: ?Sized> MappedArcRwLockWriteGuard<R, T, U> {
+    /// Returns a reference to the `RwLock` this is guarding, contained in its `Arc`.
+    #[inline]
+    pub fn rwlock(s: &Self) -> &Arc<RwLock<R, T>> {
+        &s.rwlock
+    }
+
+    /// Extracts the `Arc` from the guard without unlocking the `RwLock`.
+    #[inline]
+    fn take_arc(s: Self) -> Arc<RwLock<R, T>> {
+        let s = ManuallyDrop::new(s);
+        // SAFETY: The guard is never used or dropped again.
+        unsafe { ptr::read(&s.rwlock) }
+    }
+
+    /// Make a new `MappedArcRwLockWriteGuard` for a component of the locked data.
+    ///
+    /// This operation cannot fail as the `MappedArcRwLockWriteGuard` passed
+    /// in already locked the data.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `MappedArcRwLockWriteGuard::map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn map<V: ?Sized, F>(s: Self, f: F) -> MappedArcRwLockWriteGuard<R, T, V>
+    where
+        F: FnOnce(&mut U) -> &mut V,
+    {
+        let data = f(unsafe { &mut *s.data }) as *mut V;
+        MappedArcRwLockWriteGuard {
+            rwlock: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        }
+    }
+
+    /// Attempts to make a new `MappedArcRwLockWriteGuard` for a component of the
+    /// locked data. The original guard is returned if the closure returns `None`.
+    ///
+    /// This operation cannot fail as the `MappedArcRwLockWriteGuard` passed
+    /// in already locked the data.
+    ///
+    /// This is an associated function that needs to be
+    /// used as `MappedArcRwLockWriteGuard::try_map(...)`. A method would interfere with methods of
+    /// the same name on the contents of the locked data.
+    #[inline]
+    pub fn try_map<V: ?Sized, F>(s: Self, f: F) -> Result<MappedArcRwLockWriteGuard<R, T, V>, Self>
+    where
+        F: FnOnce(&mut U) -> Option<&mut V>,
+    {
+        let data = match f(unsafe { &mut *s.data }) {
+            Some(data) => data as *mut V,
+            None => return Err(s),
+        };
+        Ok(MappedArcRwLockWriteGuard {
+            rwlock: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockDowngrade, T: ?Sized, U: ?Sized> MappedArcRwLockWriteGuard<R, T, U> {
+    /// Atomically downgrades a write lock into a read lock without allowing any
+    /// writers to take exclusive access of the lock in the meantime.
+    ///
+    /// This is functionally identical to the `downgrade` method on
+    /// `MappedRwLockWriteGuard`.
+    pub fn downgrade(s: Self) -> MappedArcRwLockReadGuard<R, T, U> {
+        s.rwlock.raw.downgrade();
+        let data = s.data as *const U;
+        MappedArcRwLockReadGuard {
+            rwlock: Self::take_arc(s),
+            data,
+            marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLockFair, T: ?Sized, U: ?Sized> MappedArcRwLockWriteGuard<R, T, U> {
+    /// Unlocks the `RwLock` using a fair unlock protocol.
+    ///
+    /// This is functionally identical to the `unlock_fair` method on `RwLockWriteGuard`.
+    #[inline]
+    pub fn unlock_fair(s: Self) {
+        s.rwlock.raw.unlock_exclusive_fair();
+        drop(Self::take_arc(s));
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: ?Sized> Deref for MappedArcRwLockWriteGuard<R, T, U> {
+    type Target = U;
+    #[inline]
+    fn deref(&self) -> &U {
+        unsafe { &*self.data }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: ?Sized> DerefMut for MappedArcRwLockWriteGuard<R, T, U> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { &mut *self.data }
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: ?Sized> Drop for MappedArcRwLockWriteGuard<R, T, U> {
+    #[inline]
+    fn drop(&mut self) {
+        self.rwlock.raw.unlock_exclusive();
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: fmt::Debug + ?Sized> fmt::Debug
+    for MappedArcRwLockWriteGuard<R, T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(feature = "arc_lock")]
+impl<R: RawRwLock, T: ?Sized, U: fmt::Display + ?Sized> fmt::Display
+    for MappedArcRwLockWriteGuard<R, T, U>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(all(feature = "arc_lock", feature = "owning_ref"))]
+unsafe impl<R: RawRwLock, T: ?Sized, U: ?Sized> StableAddress
+    for MappedArcRwLockWriteGuard<R, T, U>
+{
+}
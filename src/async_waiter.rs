@@ -0,0 +1,70 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::task::{Context, Poll};
+use parking_lot_core::{self, ParkResult, ParkToken, UnparkToken, WakerEntry};
+
+/// State kept by a task waiting for one of the raw locks in this crate.
+///
+/// The queue entry is only allocated once the task actually needs to wait.
+#[derive(Default)]
+pub struct AsyncWaiter {
+    entry: Option<WakerEntry>,
+
+    // Set by `RawRwLock` once a writer owns WRITER_BIT and is waiting for the
+    // remaining readers to exit the lock.
+    pub(crate) waiting_for_readers: bool,
+}
+
+impl AsyncWaiter {
+    /// Returns whether the task is currently queued, or was unparked but has
+    /// not been polled since.
+    #[inline]
+    pub(crate) fn is_parked(&self) -> bool {
+        match self.entry {
+            Some(ref entry) => entry.is_parked(),
+            None => false,
+        }
+    }
+
+    /// Parks the task in the queue for `key`, or checks whether a previous park
+    /// has completed. `validate` is not called in the latter case.
+    ///
+    /// # Safety
+    ///
+    /// The requirements of `parking_lot_core::park` apply to `key` and
+    /// `validate`.
+    #[inline]
+    pub(crate) unsafe fn park(
+        &mut self,
+        key: usize,
+        validate: impl FnOnce() -> bool,
+        cx: &mut Context<'_>,
+        park_token: ParkToken,
+    ) -> Poll<ParkResult> {
+        let entry = self.entry.get_or_insert_with(WakerEntry::new);
+        parking_lot_core::park_waker(key, validate, entry, cx.waker(), park_token)
+    }
+
+    /// Removes the task from the queue it is parked in. Returns the unpark
+    /// token if the task was already unparked.
+    ///
+    /// # Safety
+    ///
+    /// The requirements of the `timed_out` callback of `parking_lot_core::park`
+    /// apply to `cancelled`.
+    #[inline]
+    pub(crate) unsafe fn cancel(
+        &mut self,
+        cancelled: impl FnOnce(usize, bool),
+    ) -> Option<UnparkToken> {
+        match self.entry {
+            Some(ref mut entry) => parking_lot_core::cancel_park(entry, cancelled),
+            None => None,
+        }
+    }
+}
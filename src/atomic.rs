@@ -0,0 +1,247 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Futex-like waiting on atomic variables
+//!
+//! These functions allow a thread to block until an atomic variable no longer
+//! holds a given value, similarly to C++20's `std::atomic::wait`. Waiting
+//! threads are queued in the parking lot under the address of the atomic, so
+//! the atomic itself doesn't need any extra space.
+//!
+//! A thread which changes the value has to call `wake_one` or `wake_all`
+//! afterwards to wake up the waiting threads.
+//!
+//! # Examples
+//!
+//! ```
+//! use parking_lot::atomic;
+//! use std::sync::atomic::{AtomicU32, Ordering};
+//! use std::sync::Arc;
+//! use std::thread;
+//!
+//! let ready = Arc::new(AtomicU32::new(0));
+//! let ready2 = ready.clone();
+//!
+//! thread::spawn(move || {
+//!     ready2.store(1, Ordering::Release);
+//!     atomic::wake_all(&*ready2);
+//! });
+//!
+//! // Wait until the value is no longer 0
+//! atomic::wait(&*ready, 0, None);
+//! assert_eq!(ready.load(Ordering::Acquire), 1);
+//! ```
+
+use crate::util;
+use core::sync::atomic::{
+    AtomicBool, AtomicI16, AtomicI32, AtomicI8, AtomicIsize, AtomicPtr, AtomicU16, AtomicU32,
+    AtomicU8, AtomicUsize, Ordering,
+};
+use parking_lot_core::{self, ParkResult, SpinWait, DEFAULT_PARK_TOKEN, DEFAULT_UNPARK_TOKEN};
+use std::time::Duration;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// An atomic type which can be waited on with `wait`.
+///
+/// This trait is sealed and is implemented for the atomic types of the
+/// standard library, except the 64-bit ones which aren't available on every
+/// platform.
+pub trait Atomic: sealed::Sealed {
+    /// The type of the value stored in the atomic.
+    type Value: Copy + PartialEq;
+
+    #[doc(hidden)]
+    fn load_value(&self, order: Ordering) -> Self::Value;
+}
+
+macro_rules! impl_atomic {
+    ($($atomic:ty => $value:ty,)*) => {
+        $(
+            impl sealed::Sealed for $atomic {}
+
+            impl Atomic for $atomic {
+                type Value = $value;
+
+                #[inline]
+                fn load_value(&self, order: Ordering) -> $value {
+                    self.load(order)
+                }
+            }
+        )*
+    };
+}
+
+impl_atomic! {
+    AtomicBool => bool,
+    AtomicI8 => i8,
+    AtomicI16 => i16,
+    AtomicI32 => i32,
+    AtomicIsize => isize,
+    AtomicU8 => u8,
+    AtomicU16 => u16,
+    AtomicU32 => u32,
+    AtomicUsize => usize,
+}
+
+impl<T> sealed::Sealed for AtomicPtr<T> {}
+
+impl<T> Atomic for AtomicPtr<T> {
+    type Value = *mut T;
+
+    #[inline]
+    fn load_value(&self, order: Ordering) -> *mut T {
+        self.load(order)
+    }
+}
+
+/// Blocks the current thread while `atomic` holds the value `expected`.
+///
+/// This returns `true` as soon as the value is observed to be different from
+/// `expected`, with an `Acquire` load, or `false` if the timeout elapsed
+/// first. It never returns spuriously: a wakeup which finds the value still
+/// equal to `expected`, for example because it was changed and then changed
+/// back, makes the thread wait again.
+///
+/// The thread spins for a short while before being parked, in case the value
+/// changes soon. Once parked, it is only woken up by a call to `wake_one` or
+/// `wake_all` with the same atomic, so the thread changing the value has to
+/// call one of them.
+#[inline]
+pub fn wait<A: Atomic>(atomic: &A, expected: A::Value, timeout: Option<Duration>) -> bool {
+    if atomic.load_value(Ordering::Acquire) != expected {
+        return true;
+    }
+    wait_slow(atomic, expected, timeout)
+}
+
+#[cold]
+fn wait_slow<A: Atomic>(atomic: &A, expected: A::Value, timeout: Option<Duration>) -> bool {
+    let deadline = timeout.and_then(util::to_deadline);
+    let mut spinwait = SpinWait::new();
+    loop {
+        if atomic.load_value(Ordering::Acquire) != expected {
+            return true;
+        }
+
+        // Spin a few times in case the value changes shortly
+        if spinwait.spin() {
+            continue;
+        }
+
+        // Park our thread until we are woken up by wake_one or wake_all
+        let addr = atomic as *const _ as usize;
+        let validate = || atomic.load_value(Ordering::Relaxed) == expected;
+        let before_sleep = || {};
+        let timed_out = |_, _| {};
+        // SAFETY:
+        //   * `addr` is only used as a key by the functions of this module.
+        //   * `validate`/`timed_out` does not panic or call into any function of `parking_lot`.
+        //   * `before_sleep` does not call `park`, nor does it panic.
+        let park_result = unsafe {
+            parking_lot_core::park(
+                addr,
+                validate,
+                before_sleep,
+                timed_out,
+                DEFAULT_PARK_TOKEN,
+                deadline,
+            )
+        };
+        match park_result {
+            // Loop back and check whether the value changed
+            ParkResult::Unparked(_) | ParkResult::Invalid => (),
+
+            // Timeout expired, unless the value changed in the meantime
+            ParkResult::TimedOut => return atomic.load_value(Ordering::Acquire) != expected,
+        }
+    }
+}
+
+/// Wakes up one thread blocked in `wait` on `atomic`.
+///
+/// Returns whether a thread was woken up.
+#[inline]
+pub fn wake_one<A: Atomic>(atomic: &A) -> bool {
+    let addr = atomic as *const _ as usize;
+    // SAFETY: `addr` is only used as a key by the functions of this module.
+    let result = unsafe { parking_lot_core::unpark_one(addr, |_| DEFAULT_UNPARK_TOKEN) };
+    result.unparked_threads != 0
+}
+
+/// Wakes up all threads blocked in `wait` on `atomic`.
+///
+/// Returns the number of threads woken up.
+#[inline]
+pub fn wake_all<A: Atomic>(atomic: &A) -> usize {
+    let addr = atomic as *const _ as usize;
+    // SAFETY: `addr` is only used as a key by the functions of this module.
+    unsafe { parking_lot_core::unpark_all(addr, DEFAULT_UNPARK_TOKEN) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wait, wake_all, wake_one};
+    use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn smoke() {
+        let atomic = AtomicU32::new(1);
+        assert!(wait(&atomic, 0, None));
+        assert!(!wake_one(&atomic));
+        assert_eq!(wake_all(&atomic), 0);
+    }
+
+    #[test]
+    fn wait_timeout() {
+        let atomic = AtomicBool::new(false);
+        let start = Instant::now();
+        assert!(!wait(&atomic, false, Some(Duration::from_millis(20))));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn wake_one_thread() {
+        let atomic = Arc::new(AtomicU32::new(0));
+        let atomic2 = atomic.clone();
+        let t = thread::spawn(move || wait(&*atomic2, 0, None));
+        thread::sleep(Duration::from_millis(20));
+        atomic.store(1, Ordering::Release);
+        wake_one(&*atomic);
+        assert!(t.join().unwrap());
+    }
+
+    #[test]
+    fn wake_without_change() {
+        let atomic = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+        let t = {
+            let (atomic, done) = (atomic.clone(), done.clone());
+            thread::spawn(move || {
+                let result = wait(&*atomic, 0, Some(Duration::from_secs(10)));
+                done.store(true, Ordering::Relaxed);
+                result
+            })
+        };
+
+        // A wakeup without changing the value doesn't make the waiter return
+        while wake_all(&*atomic) == 0 {
+            thread::yield_now();
+        }
+        thread::sleep(Duration::from_millis(20));
+        assert!(!done.load(Ordering::Relaxed));
+
+        atomic.store(1, Ordering::Release);
+        wake_all(&*atomic);
+        assert!(t.join().unwrap());
+    }
+}
@@ -0,0 +1,474 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::util;
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use parking_lot_core::{self, FilterOp, ParkResult, ParkToken, SpinWait, UnparkToken};
+use std::time::{Duration, Instant};
+
+/// The lower half of the `state` of a `Barrier` counts the threads which have
+/// arrived in the current generation.
+const GENERATION_SHIFT: u32 = (core::mem::size_of::<usize>() * 8 / 2) as u32;
+/// Mask of bits used to count arrived threads.
+const COUNT_MASK: usize = (1 << GENERATION_SHIFT) - 1;
+
+// UnparkToken used to indicate that the generation of the parked thread was
+// released. Parked threads return on this token without looking at the
+// generation in the `state` again, since it only has half a word and wraps
+// around after 65536 generations on 32-bit targets.
+const TOKEN_RELEASED: UnparkToken = UnparkToken(1);
+
+/// A `BarrierWaitResult` is returned by `wait` when all threads in the
+/// `Barrier` have rendezvoused.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns whether this thread from `wait` is the "leader thread".
+    ///
+    /// Only one thread will have `true` returned from their result, all other
+    /// threads will have `false` returned.
+    #[inline]
+    pub fn is_leader(self) -> bool {
+        self.0
+    }
+}
+
+/// A barrier enables multiple threads to synchronize the beginning of some
+/// computation.
+///
+/// Once the required number of threads have called `wait`, all of them are
+/// released and the barrier is automatically reset for the next generation,
+/// which makes it reusable. Exactly one of the released threads is chosen as
+/// the leader of its generation.
+///
+/// # Differences from the standard library `Barrier`
+///
+/// - Only requires 2 words of space, whereas the standard library boxes a
+///   `Mutex` and a `Condvar`.
+/// - Can be statically constructed.
+/// - Does not require any drop glue when dropped.
+/// - Efficient handling of micro-contention using adaptive spinning.
+/// - Supports waiting with a timeout, in which case the thread is withdrawn
+///   from the barrier again if it times out.
+/// - The number of threads can be changed with `set_num_threads` and the
+///   current generation can be released early with `reset`.
+///
+/// # Examples
+///
+/// ```
+/// use parking_lot::Barrier;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let barrier = Arc::new(Barrier::new(10));
+/// let handles: Vec<_> = (0..10)
+///     .map(|_| {
+///         let barrier = barrier.clone();
+///         // The same messages will be printed together.
+///         // You will NOT see any interleaving.
+///         thread::spawn(move || {
+///             println!("before wait");
+///             barrier.wait();
+///             println!("after wait");
+///         })
+///     })
+///     .collect();
+///
+/// // Wait for other threads to finish.
+/// for handle in handles {
+///     handle.join().unwrap();
+/// }
+/// ```
+pub struct Barrier {
+    /// The upper half of this word holds the current generation and the lower
+    /// half the number of threads which have arrived in that generation.
+    state: AtomicUsize,
+    num_threads: AtomicUsize,
+}
+
+impl Barrier {
+    /// Creates a new barrier that can block a given number of threads.
+    ///
+    /// A barrier will block `n`-1 threads which call `wait` and then wake up
+    /// all threads at once when the `n`th thread calls `wait`. A barrier
+    /// created with `n` equal to 0 behaves like one created with 1.
+    ///
+    /// The arrived threads are counted in half of a word, so `n` must not
+    /// exceed 65535 on 32-bit targets, or 4294967295 on 64-bit targets. This
+    /// can't be checked here since this is a `const fn`, so `wait` panics
+    /// instead if the limit is exceeded.
+    ///
+    /// The generation is counted in the other half of the word and wraps
+    /// around. A thread blocked in `wait` is released by its own generation
+    /// regardless, but one which is preempted for exactly a multiple of 65536
+    /// generations between arriving and blocking, on 32-bit targets, would
+    /// wait for the next generation instead.
+    #[inline]
+    pub const fn new(n: usize) -> Barrier {
+        Barrier {
+            state: AtomicUsize::new(0),
+            num_threads: AtomicUsize::new(n),
+        }
+    }
+
+    /// Returns the number of threads which must call `wait` to release a
+    /// generation of the barrier.
+    #[inline]
+    pub fn num_threads(&self) -> usize {
+        self.num_threads.load(Ordering::Relaxed)
+    }
+
+    /// Changes the number of threads which must call `wait` to release a
+    /// generation of the barrier.
+    ///
+    /// If at least `n` threads are already waiting on the barrier then the
+    /// current generation is released immediately. None of the released
+    /// threads will be the leader in that case.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `n` exceeds the limit described in `new`.
+    #[inline]
+    pub fn set_num_threads(&self, n: usize) {
+        check_num_threads(n);
+        self.num_threads.store(n, Ordering::SeqCst);
+        let state = self.state.load(Ordering::SeqCst);
+        if state & COUNT_MASK != 0 {
+            self.try_release(state, |count| count >= n);
+        }
+    }
+
+    /// Releases all threads currently waiting on the barrier and starts a new
+    /// generation.
+    ///
+    /// None of the released threads will be the leader of their generation.
+    /// Returns the number of threads which were released.
+    #[inline]
+    pub fn reset(&self) -> usize {
+        let state = self.state.load(Ordering::SeqCst);
+        if state & COUNT_MASK != 0 && self.try_release(state, |_| true) {
+            state & COUNT_MASK
+        } else {
+            0
+        }
+    }
+
+    /// Blocks the current thread until all threads have rendezvoused here.
+    ///
+    /// Barriers are re-usable after all threads have rendezvoused once, and can
+    /// be used continuously.
+    ///
+    /// A single (arbitrary) thread will receive a `BarrierWaitResult` that
+    /// returns `true` from `is_leader` when returning from this function, and
+    /// all other threads will receive a result that will return `false` from
+    /// `is_leader`.
+    #[inline]
+    pub fn wait(&self) -> BarrierWaitResult {
+        let result = self.wait_internal(None);
+        debug_assert!(result.is_some());
+        result.unwrap_or(BarrierWaitResult(false))
+    }
+
+    /// Blocks the current thread until all threads have rendezvoused here,
+    /// timing out after the specified time instant.
+    ///
+    /// If the timeout expires before the generation is released, the current
+    /// thread is withdrawn from the barrier again so that it no longer counts
+    /// towards the number of threads required, and `None` is returned.
+    #[inline]
+    pub fn wait_until(&self, timeout: Instant) -> Option<BarrierWaitResult> {
+        self.wait_internal(Some(timeout))
+    }
+
+    /// Blocks the current thread until all threads have rendezvoused here,
+    /// timing out after a specified duration.
+    ///
+    /// If the timeout expires before the generation is released, the current
+    /// thread is withdrawn from the barrier again so that it no longer counts
+    /// towards the number of threads required, and `None` is returned.
+    #[inline]
+    pub fn wait_for(&self, timeout: Duration) -> Option<BarrierWaitResult> {
+        self.wait_internal(util::to_deadline(timeout))
+    }
+
+    // This is a non-generic function to keep the inlined wrappers small.
+    fn wait_internal(&self, timeout: Option<Instant>) -> Option<BarrierWaitResult> {
+        // Check the limit before arriving, since too many arrivals would
+        // overflow into the generation.
+        check_num_threads(self.num_threads.load(Ordering::Relaxed));
+        let state = self.state.fetch_add(1, Ordering::SeqCst) + 1;
+        let num_threads = self.num_threads.load(Ordering::SeqCst);
+        if self.try_release(state, |count| count >= num_threads) {
+            return Some(BarrierWaitResult(true));
+        }
+        if self.wait_for_generation(state >> GENERATION_SHIFT, timeout) {
+            Some(BarrierWaitResult(false))
+        } else {
+            None
+        }
+    }
+
+    /// Starts a new generation if we are still in the generation of `state`
+    /// and `should_release` returns true for the number of arrived threads.
+    /// Returns whether this call released the generation.
+    #[inline]
+    fn try_release(&self, mut state: usize, should_release: impl Fn(usize) -> bool) -> bool {
+        let generation = state >> GENERATION_SHIFT;
+        loop {
+            if state >> GENERATION_SHIFT != generation || !should_release(state & COUNT_MASK) {
+                return false;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                generation.wrapping_add(1) << GENERATION_SHIFT,
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(x) => state = x,
+            }
+        }
+
+        // Only wake up the threads parked in the released generation. Threads
+        // which arrived in the next one may already be parked.
+        let addr = self as *const _ as usize;
+        let filter = |ParkToken(token)| {
+            if token == generation {
+                FilterOp::Unpark
+            } else {
+                FilterOp::Skip
+            }
+        };
+        let callback = |_| TOKEN_RELEASED;
+        // SAFETY:
+        //   * `addr` is an address we control.
+        //   * `filter`/`callback` does not panic or call into any function of `parking_lot`.
+        unsafe {
+            parking_lot_core::unpark_filter(addr, filter, callback);
+        }
+        true
+    }
+
+    /// Waits until `generation` has been released. Returns false if the
+    /// timeout expired, in which case our arrival has been withdrawn.
+    #[cold]
+    fn wait_for_generation(&self, generation: usize, timeout: Option<Instant>) -> bool {
+        let mut spinwait = SpinWait::new();
+        loop {
+            if self.state.load(Ordering::Acquire) >> GENERATION_SHIFT != generation {
+                return true;
+            }
+
+            // Spin a few times in case the remaining threads arrive shortly
+            if spinwait.spin() {
+                continue;
+            }
+
+            // Park our thread until the generation is released
+            let addr = self as *const _ as usize;
+            let validate = || self.state.load(Ordering::Relaxed) >> GENERATION_SHIFT == generation;
+            let before_sleep = || {};
+            let timed_out = |_, _| {};
+            // SAFETY:
+            //   * `addr` is an address we control.
+            //   * `validate`/`timed_out` does not panic or call into any function of `parking_lot`.
+            //   * `before_sleep` does not call `park`, nor does it panic.
+            let park_result = unsafe {
+                parking_lot_core::park(
+                    addr,
+                    validate,
+                    before_sleep,
+                    timed_out,
+                    ParkToken(generation),
+                    timeout,
+                )
+            };
+            match park_result {
+                // Our generation was released
+                ParkResult::Unparked(TOKEN_RELEASED) => return true,
+
+                // Loop back and check whether the generation was released
+                ParkResult::Unparked(_) | ParkResult::Invalid => (),
+
+                // Timeout expired, withdraw our arrival unless the generation
+                // was released in the meantime.
+                ParkResult::TimedOut => {
+                    let mut state = self.state.load(Ordering::Relaxed);
+                    loop {
+                        if state >> GENERATION_SHIFT != generation {
+                            return true;
+                        }
+                        match self.state.compare_exchange_weak(
+                            state,
+                            state - 1,
+                            Ordering::Relaxed,
+                            Ordering::Relaxed,
+                        ) {
+                            Ok(_) => return false,
+                            Err(x) => state = x,
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Panics if `n` arrived threads can't be counted in the lower half of the `state`.
+#[inline]
+fn check_num_threads(n: usize) {
+    if n > COUNT_MASK {
+        panic!("Barrier thread count exceeds the maximum of {}", COUNT_MASK);
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("Barrier { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Barrier;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::{channel, TryRecvError};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_barrier() {
+        const N: usize = 10;
+
+        let barrier = Arc::new(Barrier::new(N));
+        let (tx, rx) = channel();
+
+        for _ in 0..N - 1 {
+            let c = barrier.clone();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                tx.send(c.wait().is_leader()).unwrap();
+            });
+        }
+
+        // At this point, all spawned threads should be blocked,
+        // so we shouldn't get anything from the port
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        let mut leader_found = barrier.wait().is_leader();
+
+        // Now, the barrier is cleared and we should get data.
+        for _ in 0..N - 1 {
+            if rx.recv().unwrap() {
+                assert!(!leader_found);
+                leader_found = true;
+            }
+        }
+        assert!(leader_found);
+    }
+
+    #[test]
+    fn test_reuse() {
+        const N: usize = 4;
+        const ROUNDS: usize = 100;
+
+        let barrier = Arc::new(Barrier::new(N));
+        let leaders = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..N)
+            .map(|_| {
+                let (barrier, leaders) = (barrier.clone(), leaders.clone());
+                thread::spawn(move || {
+                    for _ in 0..ROUNDS {
+                        if barrier.wait().is_leader() {
+                            leaders.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(leaders.load(Ordering::Relaxed), ROUNDS);
+    }
+
+    #[test]
+    fn test_wait_for_timeout() {
+        let barrier = Barrier::new(2);
+        assert!(barrier.wait_for(Duration::from_millis(10)).is_none());
+
+        // The timed out thread no longer counts towards the barrier.
+        let barrier = Arc::new(barrier);
+        let barrier2 = barrier.clone();
+        let t = thread::spawn(move || barrier2.wait().is_leader());
+        thread::sleep(Duration::from_millis(10));
+        assert!(barrier.wait_for(Duration::from_secs(10)).is_some());
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_set_num_threads() {
+        let barrier = Arc::new(Barrier::new(3));
+        let barrier2 = barrier.clone();
+        let t = thread::spawn(move || barrier2.wait().is_leader());
+        while barrier.state.load(Ordering::SeqCst) & super::COUNT_MASK == 0 {
+            thread::yield_now();
+        }
+        barrier.set_num_threads(1);
+        assert!(!t.join().unwrap());
+        assert_eq!(barrier.num_threads(), 1);
+        assert!(barrier.wait().is_leader());
+    }
+
+    #[test]
+    fn test_reset() {
+        let barrier = Arc::new(Barrier::new(3));
+        assert_eq!(barrier.reset(), 0);
+        let barrier2 = barrier.clone();
+        let t = thread::spawn(move || barrier2.wait().is_leader());
+        while barrier.reset() == 0 {
+            thread::yield_now();
+        }
+        assert!(!t.join().unwrap());
+    }
+
+    #[test]
+    fn test_generation_wraparound() {
+        let barrier = Arc::new(Barrier::new(2));
+        let (tx, rx) = channel();
+        let barrier2 = barrier.clone();
+        thread::spawn(move || {
+            barrier2.wait();
+            tx.send(()).unwrap();
+        });
+        thread::sleep(Duration::from_millis(10));
+
+        // Release the generation of the parked thread and simulate the
+        // generation wrapping around to the same value before it runs.
+        assert!(barrier.wait().is_leader());
+        barrier.state.store(0, Ordering::SeqCst);
+        rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Barrier thread count exceeds the maximum")]
+    fn test_too_many_threads() {
+        Barrier::new(!0).wait();
+    }
+
+    #[test]
+    fn test_barrier_debug() {
+        let barrier = Barrier::new(1);
+        assert_eq!(format!("{:?}", barrier), "Barrier { .. }");
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::raw_mutex::RawMutex;
+use crate::remutex::RawThreadId;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use lock_api::{self, GetThreadId, GuardNoSend};
+use std::time::Instant;
+
+// Returns a non-zero id for the current thread.
+#[inline]
+pub(crate) fn current_thread_id() -> usize {
+    RawThreadId.nonzero_thread_id().get()
+}
+
+/// Raw mutex type which checks that it is used correctly by its owner thread.
+///
+/// This is a `RawMutex` which remembers which thread locked it, so that
+/// locking it again from the same thread, which would deadlock, and unlocking
+/// it from another thread panic immediately instead.
+pub struct RawCheckedMutex {
+    raw: RawMutex,
+
+    // Id of the thread holding the lock, or 0 if it isn't locked. This is
+    // only modified by the thread holding the lock.
+    owner: AtomicUsize,
+}
+
+impl RawCheckedMutex {
+    #[inline]
+    fn set_owner(&self) {
+        self.owner.store(current_thread_id(), Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn clear_owner(&self) {
+        if self.owner.load(Ordering::Relaxed) != current_thread_id() {
+            panic!("attempted to unlock a CheckedMutex which isn't locked by the current thread");
+        }
+        self.owner.store(0, Ordering::Relaxed);
+    }
+}
+
+unsafe impl lock_api::RawMutex for RawCheckedMutex {
+    const INIT: RawCheckedMutex = RawCheckedMutex {
+        raw: RawMutex::INIT,
+        owner: AtomicUsize::new(0),
+    };
+
+    // Guards must be dropped on the thread which owns the lock.
+    type GuardMarker = GuardNoSend;
+
+    #[inline]
+    fn lock(&self) {
+        // Only the current thread can have stored its own id.
+        if self.owner.load(Ordering::Relaxed) == current_thread_id() {
+            panic!(
+                "attempted to lock a CheckedMutex which is already locked by the current thread"
+            );
+        }
+        self.raw.lock();
+        self.set_owner();
+    }
+
+    #[inline]
+    fn try_lock(&self) -> bool {
+        let result = self.raw.try_lock();
+        if result {
+            self.set_owner();
+        }
+        result
+    }
+
+    #[inline]
+    fn unlock(&self) {
+        self.clear_owner();
+        self.raw.unlock();
+    }
+}
+
+unsafe impl lock_api::RawMutexFair for RawCheckedMutex {
+    #[inline]
+    fn unlock_fair(&self) {
+        self.clear_owner();
+        self.raw.unlock_fair();
+    }
+
+    #[inline]
+    fn bump(&self) {
+        self.clear_owner();
+        self.raw.bump();
+        self.set_owner();
+    }
+}
+
+unsafe impl lock_api::RawMutexTimed for RawCheckedMutex {
+    type Duration = Duration;
+    type Instant = Instant;
+
+    #[inline]
+    fn try_lock_until(&self, timeout: Instant) -> bool {
+        let result = self.raw.try_lock_until(timeout);
+        if result {
+            self.set_owner();
+        }
+        result
+    }
+
+    #[inline]
+    fn try_lock_for(&self, timeout: Duration) -> bool {
+        let result = self.raw.try_lock_for(timeout);
+        if result {
+            self.set_owner();
+        }
+        result
+    }
+}
+
+/// A mutex which panics when it is misused instead of deadlocking or causing
+/// undefined behavior.
+///
+/// This type is identical to `Mutex` except for the following points:
+///
+/// - Locking it with `lock` from the thread which already holds it panics
+///   instead of deadlocking forever.
+/// - Unlocking it from a thread which doesn't hold it, for example with
+///   `force_unlock`, panics.
+/// - `CheckedMutexGuard` can't be used with a `Condvar`.
+///
+/// These checks make it slightly slower than `Mutex`, so it is meant to be
+/// used while debugging locking issues.
+pub type CheckedMutex<T> = lock_api::Mutex<RawCheckedMutex, T>;
+
+/// An RAII implementation of a "scoped lock" of a checked mutex. When this
+/// structure is dropped (falls out of scope), the lock will be unlocked.
+///
+/// The data protected by the mutex can be accessed through this guard via its
+/// `Deref` and `DerefMut` implementations.
+pub type CheckedMutexGuard<'a, T> = lock_api::MutexGuard<'a, RawCheckedMutex, T>;
+
+/// An RAII mutex guard returned by `CheckedMutexGuard::map`, which can point
+/// to a subfield of the protected data.
+pub type MappedCheckedMutexGuard<'a, T> = lock_api::MappedMutexGuard<'a, RawCheckedMutex, T>;
+
+#[cfg(test)]
+mod tests {
+    use crate::CheckedMutex;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn smoke() {
+        let m = Arc::new(CheckedMutex::new(0));
+        let m2 = m.clone();
+        let t = thread::spawn(move || *m2.lock() += 1);
+        *m.lock() += 1;
+        t.join().unwrap();
+        assert_eq!(*m.lock(), 2);
+
+        // Failing to lock recursively isn't a deadlock.
+        let _guard = m.lock();
+        assert!(m.try_lock().is_none());
+        assert!(m.try_lock_for(Duration::from_millis(1)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "already locked by the current thread")]
+    fn recursive_lock() {
+        let m = CheckedMutex::new(());
+        let _guard = m.lock();
+        let _guard2 = m.lock();
+    }
+
+    #[test]
+    fn foreign_unlock() {
+        let m = Arc::new(CheckedMutex::new(()));
+        std::mem::forget(m.lock());
+        let m2 = m.clone();
+        let result = thread::spawn(move || unsafe { m2.force_unlock() }).join();
+        assert!(result.is_err());
+
+        // The owner can still unlock it.
+        unsafe { m.force_unlock() };
+        assert!(m.try_lock().is_some());
+    }
+}
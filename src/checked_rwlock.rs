@@ -0,0 +1,240 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::checked_mutex::current_thread_id;
+use crate::raw_rwlock::RawRwLock;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use lock_api::{self, GuardNoSend};
+use std::time::Instant;
+
+/// Raw reader-writer lock type which checks that it is used correctly by the
+/// thread holding its exclusive lock.
+///
+/// This is a `RawRwLock` which remembers which thread holds the exclusive
+/// lock, so that locking it again from that thread, which would deadlock, and
+/// releasing the exclusive lock from another thread panic immediately
+/// instead.
+///
+/// Shared locks are not tracked, so acquiring the exclusive lock while holding
+/// a shared lock on the same thread still deadlocks.
+pub struct RawCheckedRwLock {
+    raw: RawRwLock,
+
+    // Id of the thread holding the exclusive lock, or 0 if there is none.
+    // This is only modified by the thread holding the exclusive lock.
+    writer: AtomicUsize,
+}
+
+impl RawCheckedRwLock {
+    #[inline]
+    fn check_not_writer(&self) {
+        // Only the current thread can have stored its own id.
+        if self.writer.load(Ordering::Relaxed) == current_thread_id() {
+            panic!("attempted to lock a CheckedRwLock while holding its write lock");
+        }
+    }
+
+    #[inline]
+    fn set_writer(&self) {
+        self.writer.store(current_thread_id(), Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn clear_writer(&self) {
+        if self.writer.load(Ordering::Relaxed) != current_thread_id() {
+            panic!(
+                "attempted to release the write lock of a CheckedRwLock which isn't held by \
+                 the current thread"
+            );
+        }
+        self.writer.store(0, Ordering::Relaxed);
+    }
+}
+
+unsafe impl lock_api::RawRwLock for RawCheckedRwLock {
+    const INIT: RawCheckedRwLock = RawCheckedRwLock {
+        raw: RawRwLock::INIT,
+        writer: AtomicUsize::new(0),
+    };
+
+    // Guards must be dropped on the thread which owns the lock.
+    type GuardMarker = GuardNoSend;
+
+    #[inline]
+    fn lock_shared(&self) {
+        self.check_not_writer();
+        self.raw.lock_shared();
+    }
+
+    #[inline]
+    fn try_lock_shared(&self) -> bool {
+        self.raw.try_lock_shared()
+    }
+
+    #[inline]
+    fn unlock_shared(&self) {
+        self.raw.unlock_shared();
+    }
+
+    #[inline]
+    fn lock_exclusive(&self) {
+        self.check_not_writer();
+        self.raw.lock_exclusive();
+        self.set_writer();
+    }
+
+    #[inline]
+    fn try_lock_exclusive(&self) -> bool {
+        let result = self.raw.try_lock_exclusive();
+        if result {
+            self.set_writer();
+        }
+        result
+    }
+
+    #[inline]
+    fn unlock_exclusive(&self) {
+        self.clear_writer();
+        self.raw.unlock_exclusive();
+    }
+}
+
+unsafe impl lock_api::RawRwLockFair for RawCheckedRwLock {
+    #[inline]
+    fn unlock_shared_fair(&self) {
+        self.raw.unlock_shared_fair();
+    }
+
+    #[inline]
+    fn unlock_exclusive_fair(&self) {
+        self.clear_writer();
+        self.raw.unlock_exclusive_fair();
+    }
+
+    #[inline]
+    fn bump_shared(&self) {
+        self.raw.bump_shared();
+    }
+
+    #[inline]
+    fn bump_exclusive(&self) {
+        self.clear_writer();
+        self.raw.bump_exclusive();
+        self.set_writer();
+    }
+}
+
+unsafe impl lock_api::RawRwLockDowngrade for RawCheckedRwLock {
+    #[inline]
+    fn downgrade(&self) {
+        self.clear_writer();
+        self.raw.downgrade();
+    }
+}
+
+unsafe impl lock_api::RawRwLockTimed for RawCheckedRwLock {
+    type Duration = Duration;
+    type Instant = Instant;
+
+    #[inline]
+    fn try_lock_shared_for(&self, timeout: Duration) -> bool {
+        self.raw.try_lock_shared_for(timeout)
+    }
+
+    #[inline]
+    fn try_lock_shared_until(&self, timeout: Instant) -> bool {
+        self.raw.try_lock_shared_until(timeout)
+    }
+
+    #[inline]
+    fn try_lock_exclusive_for(&self, timeout: Duration) -> bool {
+        let result = self.raw.try_lock_exclusive_for(timeout);
+        if result {
+            self.set_writer();
+        }
+        result
+    }
+
+    #[inline]
+    fn try_lock_exclusive_until(&self, timeout: Instant) -> bool {
+        let result = self.raw.try_lock_exclusive_until(timeout);
+        if result {
+            self.set_writer();
+        }
+        result
+    }
+}
+
+/// A reader-writer lock which panics when it is misused instead of
+/// deadlocking or causing undefined behavior.
+///
+/// This type is identical to `RwLock` except for the following points:
+///
+/// - Acquiring a read or write lock with `read` or `write` from the thread
+///   which holds the write lock panics instead of deadlocking forever.
+/// - Releasing the write lock from a thread which doesn't hold it, for
+///   example with `force_unlock_write`, panics.
+/// - Upgradable read locks are not supported.
+///
+/// These checks make it slightly slower than `RwLock`, so it is meant to be
+/// used while debugging locking issues.
+pub type CheckedRwLock<T> = lock_api::RwLock<RawCheckedRwLock, T>;
+
+/// RAII structure used to release the shared read access of a lock when
+/// dropped.
+pub type CheckedRwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawCheckedRwLock, T>;
+
+/// RAII structure used to release the exclusive write access of a lock when
+/// dropped.
+pub type CheckedRwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawCheckedRwLock, T>;
+
+#[cfg(test)]
+mod tests {
+    use crate::{CheckedRwLock, CheckedRwLockWriteGuard};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn smoke() {
+        let l = CheckedRwLock::new(());
+        drop(l.read());
+        drop(l.write());
+        drop((l.read(), l.read()));
+        let guard = l.write();
+        assert!(l.try_read().is_none());
+        let _guard = CheckedRwLockWriteGuard::downgrade(guard);
+        drop(l.read());
+    }
+
+    #[test]
+    #[should_panic(expected = "while holding its write lock")]
+    fn write_then_read() {
+        let l = CheckedRwLock::new(());
+        let _guard = l.write();
+        let _guard2 = l.read();
+    }
+
+    #[test]
+    #[should_panic(expected = "while holding its write lock")]
+    fn write_then_write() {
+        let l = CheckedRwLock::new(());
+        let _guard = l.write();
+        let _guard2 = l.write();
+    }
+
+    #[test]
+    fn foreign_unlock() {
+        let l = Arc::new(CheckedRwLock::new(()));
+        std::mem::forget(l.write());
+        let l2 = l.clone();
+        let result = thread::spawn(move || unsafe { l2.force_unlock_write() }).join();
+        assert!(result.is_err());
+        unsafe { l.force_unlock_write() };
+        assert!(l.try_write().is_some());
+    }
+}
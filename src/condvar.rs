@@ -7,19 +7,21 @@
 
 use crate::mutex::MutexGuard;
 use crate::raw_mutex::{RawMutex, TOKEN_HANDOFF, TOKEN_NORMAL};
-use crate::{deadlock, util};
+use crate::{deadlock, hold_time, stats, util};
 use core::{
     fmt, ptr,
     sync::atomic::{AtomicPtr, Ordering},
 };
 use lock_api::RawMutex as RawMutex_;
-use parking_lot_core::{self, ParkResult, RequeueOp, UnparkResult, DEFAULT_PARK_TOKEN};
+use parking_lot_core::{
+    self, deadlock::ResourceKind, ParkResult, RequeueOp, UnparkResult, DEFAULT_PARK_TOKEN,
+};
 use std::time::{Duration, Instant};
 
 /// A type indicating whether a timed wait on a condition variable returned
 /// due to a time out or not.
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
-pub struct WaitTimeoutResult(bool);
+pub struct WaitTimeoutResult(pub(crate) bool);
 
 impl WaitTimeoutResult {
     /// Returns whether the wait was known to have timed out.
@@ -301,8 +303,13 @@ impl Condvar {
             let result;
             let mut bad_mutex = false;
             let mut requeued = false;
+            let addr = self as *const _ as usize;
+            let mut contention = stats::Contention::new();
             {
-                let addr = self as *const _ as usize;
+                // The mutex is unlocked by `before_sleep`, which isn't allowed
+                // to call the hold-time hook, so report the hold once we are
+                // done parking.
+                let _hold = hold_time::release(mutex as *const _ as usize);
                 let lock_addr = mutex as *const _ as *mut _;
                 let validate = || {
                     // Ensure we don't use two different mutexes with the same
@@ -334,6 +341,9 @@ impl Condvar {
                         self.state.store(ptr::null_mut(), Ordering::Relaxed);
                     }
                 };
+                contention.park();
+                deadlock::set_wait_kind(ResourceKind::Condvar);
+                deadlock::set_reacquire_resource(lock_addr as usize, ResourceKind::Mutex);
                 result = parking_lot_core::park(
                     addr,
                     validate,
@@ -343,6 +353,7 @@ impl Condvar {
                     timeout,
                 );
             }
+            contention.finish(addr, false);
 
             // Panic if we tried to use multiple mutexes with a Condvar. Note
             // that at this point the MutexGuard is still locked. It will be
@@ -353,7 +364,7 @@ impl Condvar {
 
             // ... and re-lock it once we are done sleeping
             if result == ParkResult::Unparked(TOKEN_HANDOFF) {
-                deadlock::acquire_resource(mutex as *const _ as usize);
+                mutex.on_acquire();
             } else {
                 mutex.lock();
             }
@@ -7,40 +7,310 @@
 //! ```
 //! #[cfg(feature = "deadlock_detection")]
 //! { // only for #[cfg]
-//! use std::thread;
 //! use std::time::Duration;
+//! use parking_lot::deadlock::{self, Watchdog};
+//!
+//! // Start a background thread which checks for deadlocks every 10s and
+//! // prints a report for each of them
+//! let mut watchdog = Watchdog::new(Duration::from_secs(10));
+//! watchdog.add_handler(deadlock::print_report);
+//! watchdog.start();
+//!
+//! // Dropping the watchdog stops it, so keep it alive for the rest of the
+//! // program.
+//! std::mem::forget(watchdog);
+//! } // only for #[cfg]
+//! ```
+//!
+//! Deadlocks can also be checked for manually:
+//!
+//! ```
+//! #[cfg(feature = "deadlock_detection")]
+//! { // only for #[cfg]
 //! use parking_lot::deadlock;
 //!
-//! // Create a background thread which checks for deadlocks every 10s
-//! thread::spawn(move || {
-//!     loop {
-//!         thread::sleep(Duration::from_secs(10));
-//!         let deadlocks = deadlock::check_deadlock();
-//!         if deadlocks.is_empty() {
-//!             continue;
-//!         }
-//!
-//!         println!("{} deadlocks detected", deadlocks.len());
-//!         for (i, threads) in deadlocks.iter().enumerate() {
-//!             println!("Deadlock #{}", i);
-//!             for t in threads {
-//!                 println!("Thread Id {:#?}", t.thread_id());
-//!                 println!("{:#?}", t.backtrace());
-//!             }
-//!         }
+//! let deadlocks = deadlock::check_deadlock();
+//! println!("{} deadlocks detected", deadlocks.len());
+//! for (i, threads) in deadlocks.iter().enumerate() {
+//!     println!("Deadlock #{}", i);
+//!     for t in threads {
+//!         println!("Thread Id {:#?}", t.thread_id());
+//!         println!("Blocked on {}", t.blocked_on());
+//!         println!("{:#?}", t.backtrace());
 //!     }
-//! });
+//! }
+//! } // only for #[cfg]
+//! ```
+//!
+//! A `DeadlockReport` can also be built from each cycle. It only contains
+//! plain data, including which thread waits for which other thread, and its
+//! `Display` implementation is suitable for logging:
+//!
+//! ```
+//! #[cfg(feature = "deadlock_detection")]
+//! { // only for #[cfg]
+//! use parking_lot::deadlock::{self, DeadlockReport};
+//!
+//! for cycle in deadlock::check_deadlock() {
+//!     eprintln!("{}", DeadlockReport::new(&cycle));
+//! }
+//! } // only for #[cfg]
+//! ```
+//!
+//! # Recovery
+//!
+//! By default the threads of a deadlock stay blocked forever once it has been
+//! detected. Lock acquisitions made through `Mutex::lock_or_deadlock`,
+//! `RwLock::read_or_deadlock` or `RwLock::write_or_deadlock` may instead be
+//! aborted to break the deadlock: one of them is chosen as the victim in
+//! each cycle and returns a `DeadlockError`, after which the caller is
+//! expected to release the locks it holds. The other threads keep waiting.
+//!
+//! ```
+//! #[cfg(feature = "deadlock_detection")]
+//! { // only for #[cfg]
+//! use parking_lot::{DeadlockError, Mutex};
+//!
+//! fn transfer(from: &Mutex<u32>, to: &Mutex<u32>) -> Result<(), DeadlockError> {
+//!     let mut from = from.lock_or_deadlock()?;
+//!     let mut to = to.lock_or_deadlock()?;
+//!     *from -= 1;
+//!     *to += 1;
+//!     Ok(())
+//! }
+//!
+//! let (a, b) = (Mutex::new(1), Mutex::new(0));
+//! transfer(&a, &b).unwrap();
+//! } // only for #[cfg]
+//! ```
+//!
+//! # Exiting threads
+//!
+//! A thread which exits while still holding locks, for example because a
+//! guard was leaked with `mem::forget`, leaves them locked forever. This is
+//! reported when the thread exits, through a hook which prints the report to
+//! standard error by default. Enabling `set_capture_backtraces` includes where
+//! each of these locks was acquired.
+//!
+//! ```
+//! #[cfg(feature = "deadlock_detection")]
+//! { // only for #[cfg]
+//! use parking_lot::deadlock;
+//!
+//! deadlock::set_capture_backtraces(true);
+//! deadlock::set_exit_hook(Box::new(|thread| {
+//!     eprintln!("{}", thread);
+//!     std::process::abort();
+//! }));
 //! } // only for #[cfg]
 //! ```
 
 #[cfg(feature = "deadlock_detection")]
-pub use parking_lot_core::deadlock::check_deadlock;
-pub(crate) use parking_lot_core::deadlock::{acquire_resource, release_resource};
+pub use self::watchdog::{abort_on_deadlock, print_report, Watchdog};
+pub(crate) use parking_lot_core::deadlock::{
+    acquire_resource_with_kind, release_resource, set_reacquire_resource, set_wait_kind,
+};
+#[cfg(feature = "deadlock_detection")]
+pub use parking_lot_core::deadlock::{
+    check_deadlock, set_capture_backtraces, set_exit_hook, DeadlockReport, DeadlockedThread,
+    ExitedThread, HeldResource, Resource, ResourceKind, ThreadReport, WaitEdge,
+};
+use std::error::Error;
+use std::fmt;
+
+/// Error returned by `Mutex::lock_or_deadlock`, `RwLock::read_or_deadlock` and
+/// `RwLock::write_or_deadlock` when the current thread was chosen to break a
+/// deadlock.
+///
+/// The lock was not acquired. The caller should release the locks it holds,
+/// for example by returning the error, so that the other threads of the
+/// deadlock can make progress.
+///
+/// This is only ever returned when the `deadlock_detection` feature is
+/// enabled and `check_deadlock` finds the deadlock, for example from a
+/// `Watchdog`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeadlockError(());
+
+impl fmt::Display for DeadlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("lock acquisition aborted to break a deadlock")
+    }
+}
+
+impl Error for DeadlockError {}
+
+/// Runs the slow path of a lock acquisition without timeout, allowing the
+/// deadlock detector to abort the wait. `lock_slow` returns false if the
+/// wait was aborted.
+#[inline]
+pub(crate) fn lock_or_deadlock(lock_slow: impl FnOnce() -> bool) -> Result<(), DeadlockError> {
+    parking_lot_core::deadlock::set_wait_recoverable(true);
+    let acquired = lock_slow();
+    parking_lot_core::deadlock::set_wait_recoverable(false);
+    if acquired {
+        Ok(())
+    } else {
+        Err(DeadlockError(()))
+    }
+}
+
+#[cfg(feature = "deadlock_detection")]
+mod watchdog {
+    use super::{check_deadlock, DeadlockReport, DeadlockedThread};
+    use crate::{Condvar, Mutex, MutexGuard};
+    use std::fmt;
+    use std::process;
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    type Handler = Arc<dyn Fn(&[DeadlockedThread]) + Send + Sync>;
+
+    struct Shared {
+        handlers: Mutex<Vec<Handler>>,
+        stop: Mutex<bool>,
+        condvar: Condvar,
+    }
+
+    /// A background thread which periodically checks for deadlocks and calls
+    /// the registered handlers with each detected cycle.
+    ///
+    /// The watchdog is stopped when it is dropped.
+    pub struct Watchdog {
+        interval: Duration,
+        shared: Arc<Shared>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Watchdog {
+        /// Creates a new watchdog which checks for deadlocks every `interval`
+        /// once started.
+        ///
+        /// No handlers are registered initially.
+        pub fn new(interval: Duration) -> Watchdog {
+            Watchdog {
+                interval,
+                shared: Arc::new(Shared {
+                    handlers: Mutex::new(Vec::new()),
+                    stop: Mutex::new(false),
+                    condvar: Condvar::new(),
+                }),
+                thread: None,
+            }
+        }
+
+        /// Registers a handler which is called with the threads of each
+        /// detected deadlock cycle.
+        ///
+        /// Handlers are called on the watchdog thread, in the order in which
+        /// they were registered. They can be added while the watchdog is
+        /// running, through a shared reference.
+        pub fn add_handler<F>(&self, handler: F) -> &Watchdog
+        where
+            F: Fn(&[DeadlockedThread]) + Send + Sync + 'static,
+        {
+            self.shared.handlers.lock().push(Arc::new(handler));
+            self
+        }
+
+        /// Starts the watchdog thread. Does nothing if it is already running.
+        pub fn start(&mut self) -> &mut Watchdog {
+            if self.thread.is_none() {
+                *self.shared.stop.lock() = false;
+                let shared = self.shared.clone();
+                let interval = self.interval;
+                let thread = thread::Builder::new()
+                    .name("deadlock watchdog".to_owned())
+                    .spawn(move || run(&shared, interval))
+                    .expect("failed to spawn the deadlock watchdog thread");
+                self.thread = Some(thread);
+            }
+            self
+        }
+
+        /// Stops the watchdog thread and waits for it to exit. Does nothing
+        /// if it isn't running.
+        pub fn stop(&mut self) -> &mut Watchdog {
+            if let Some(thread) = self.thread.take() {
+                *self.shared.stop.lock() = true;
+                self.shared.condvar.notify_one();
+                // A panicking handler already reported its panic.
+                let _ = thread.join();
+            }
+            self
+        }
+
+        /// Returns whether the watchdog thread is running.
+        pub fn is_running(&self) -> bool {
+            self.thread.is_some()
+        }
+    }
+
+    impl Drop for Watchdog {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    impl fmt::Debug for Watchdog {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Watchdog")
+                .field("interval", &self.interval)
+                .field("running", &self.is_running())
+                .finish()
+        }
+    }
+
+    fn run(shared: &Shared, interval: Duration) {
+        let mut stop = shared.stop.lock();
+        loop {
+            let deadline = Instant::now() + interval;
+            while !*stop {
+                if shared.condvar.wait_until(&mut stop, deadline).timed_out() {
+                    break;
+                }
+            }
+            if *stop {
+                return;
+            }
+
+            // Don't hold any lock while checking for deadlocks and running the
+            // handlers, so that handlers may register other handlers.
+            MutexGuard::unlocked(&mut stop, || {
+                let deadlocks = check_deadlock();
+                if !deadlocks.is_empty() {
+                    let handlers = shared.handlers.lock().clone();
+                    for cycle in &deadlocks {
+                        for handler in &handlers {
+                            handler(cycle);
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    /// A handler which prints a `DeadlockReport` of the cycle to standard
+    /// error.
+    pub fn print_report(cycle: &[DeadlockedThread]) {
+        eprintln!("{}", DeadlockReport::new(cycle));
+    }
+
+    /// A handler which prints a `DeadlockReport` of the cycle to standard
+    /// error and then aborts the process.
+    pub fn abort_on_deadlock(cycle: &[DeadlockedThread]) {
+        print_report(cycle);
+        process::abort();
+    }
+}
 
 #[cfg(test)]
 #[cfg(feature = "deadlock_detection")]
 mod tests {
-    use crate::{Mutex, ReentrantMutex, RwLock};
+    use super::{DeadlockError, DeadlockReport, Resource, ResourceKind, WaitEdge, Watchdog};
+    use crate::{Condvar, Mutex, Once, ReentrantMutex, RwLock, RwLockUpgradableReadGuard};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::{Arc, Barrier};
     use std::thread::{self, sleep};
     use std::time::Duration;
@@ -212,7 +482,6 @@ mod tests {
         assert!(!check_deadlock());
     }
 
-    #[cfg(rwlock_deadlock_detection_not_supported)]
     #[test]
     fn test_rwlock_deadlock_reentrant() {
         let _guard = DEADLOCK_DETECTION_LOCK.lock();
@@ -223,7 +492,7 @@ mod tests {
 
         let _t1 = thread::spawn(move || {
             let _g = m1.read();
-            let _ = m1.write();
+            let _g = m1.write();
         });
 
         sleep(Duration::from_millis(50));
@@ -231,4 +500,646 @@ mod tests {
 
         assert!(!check_deadlock());
     }
+
+    #[test]
+    fn test_deadlock_report() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m: Arc<Mutex<()>> = Default::default();
+        let rw: Arc<RwLock<()>> = Default::default();
+        let m_key = unsafe { m.raw() as *const _ as usize };
+        let rw_key = unsafe { rw.raw() as *const _ as usize };
+        let b = Arc::new(Barrier::new(3));
+
+        let m_ = m.clone();
+        let rw_ = rw.clone();
+        let b1 = b.clone();
+        let b2 = b.clone();
+
+        assert!(!check_deadlock());
+
+        let _t1 = thread::Builder::new()
+            .name("holds-mutex".to_owned())
+            .spawn(move || {
+                let _g = m.lock();
+                b1.wait();
+                let _g = rw_.write();
+            })
+            .unwrap();
+
+        let _t2 = thread::Builder::new()
+            .name("holds-rwlock".to_owned())
+            .spawn(move || {
+                let _g = rw.read();
+                b2.wait();
+                let _g = m_.lock();
+            })
+            .unwrap();
+
+        b.wait();
+        sleep(Duration::from_millis(50));
+        let deadlocks = parking_lot_core::deadlock::check_deadlock();
+        assert_eq!(deadlocks.len(), 1);
+
+        let report = DeadlockReport::new(&deadlocks[0]);
+        assert_eq!(report.threads.len(), 2);
+        let t1 = report
+            .threads
+            .iter()
+            .find(|t| t.thread_name.as_ref().map(|n| &n[..]) == Some("holds-mutex"))
+            .unwrap();
+        let t2 = report
+            .threads
+            .iter()
+            .find(|t| t.thread_name.as_ref().map(|n| &n[..]) == Some("holds-rwlock"))
+            .unwrap();
+        // The writer has already acquired the writer bit and is waiting for
+        // the reader to exit on the secondary key, which is reported as the
+        // lock itself.
+        assert_eq!(
+            t1.blocked_on,
+            Resource {
+                key: rw_key,
+                kind: ResourceKind::RwLockExclusive
+            }
+        );
+        assert!(t1.held_resources.contains(&Resource {
+            key: m_key,
+            kind: ResourceKind::Mutex
+        }));
+        assert_eq!(
+            t2.blocked_on,
+            Resource {
+                key: m_key,
+                kind: ResourceKind::Mutex
+            }
+        );
+        assert_eq!(
+            t1.held_resources.iter().filter(|r| r.key == rw_key).count(),
+            1
+        );
+        assert!(t2.held_resources.contains(&Resource {
+            key: rw_key,
+            kind: ResourceKind::RwLockShared
+        }));
+
+        assert_eq!(report.edges.len(), 2);
+        assert!(report.edges.contains(&WaitEdge {
+            waiting_thread_id: t1.thread_id,
+            key: rw_key,
+            holding_thread_id: t2.thread_id,
+        }));
+        assert!(report.edges.contains(&WaitEdge {
+            waiting_thread_id: t2.thread_id,
+            key: m_key,
+            holding_thread_id: t1.thread_id,
+        }));
+
+        let text = report.to_string();
+        assert!(text.starts_with("deadlock between 2 threads\n"));
+        assert!(text.contains("'holds-mutex' blocked on"));
+        assert!(text.contains("(rwlock exclusive)"));
+
+        assert!(!check_deadlock());
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let cycles = Arc::new(AtomicUsize::new(0));
+        let threads = Arc::new(AtomicUsize::new(0));
+        let mut watchdog = Watchdog::new(Duration::from_millis(20));
+        let cycles_ = cycles.clone();
+        watchdog.add_handler(move |_| {
+            cycles_.fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(!watchdog.is_running());
+        watchdog.start();
+        assert!(watchdog.is_running());
+
+        // Handlers can be added while the watchdog is running.
+        let threads_ = threads.clone();
+        let running = &watchdog;
+        running.add_handler(move |cycle| {
+            threads_.fetch_add(cycle.len(), Ordering::SeqCst);
+        });
+
+        let m1: Arc<Mutex<()>> = Default::default();
+        let m2: Arc<Mutex<()>> = Default::default();
+        let b = Arc::new(Barrier::new(2));
+        let m1_ = m1.clone();
+        let m2_ = m2.clone();
+        let b_ = b.clone();
+        let _t1 = thread::spawn(move || {
+            let _g = m1.lock();
+            b_.wait();
+            let _g = m2_.lock();
+        });
+        let _t2 = thread::spawn(move || {
+            let _g = m2.lock();
+            b.wait();
+            let _g = m1_.lock();
+        });
+
+        let start = std::time::Instant::now();
+        while cycles.load(Ordering::SeqCst) == 0 && start.elapsed() < Duration::from_secs(5) {
+            sleep(Duration::from_millis(10));
+        }
+        watchdog.stop();
+        assert!(!watchdog.is_running());
+
+        // Each cycle is only reported once.
+        assert_eq!(cycles.load(Ordering::SeqCst), 1);
+        assert_eq!(threads.load(Ordering::SeqCst), 2);
+        assert!(!check_deadlock());
+    }
+
+    #[test]
+    fn test_condvar_deadlock() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m1: Arc<Mutex<()>> = Default::default();
+        let m2: Arc<Mutex<()>> = Default::default();
+        let c: Arc<Condvar> = Default::default();
+        let m1_key = unsafe { m1.raw() as *const _ as usize };
+        let c_key = &*c as *const _ as usize;
+        let b = Arc::new(Barrier::new(2));
+
+        let m1_ = m1.clone();
+        let m2_ = m2.clone();
+        let b_ = b.clone();
+
+        assert!(!check_deadlock());
+
+        // The waiter holds m2 while waiting, and can't reacquire m1 once
+        // notified since the other thread holds it while waiting for m2.
+        let _t1 = thread::spawn(move || {
+            let _g2 = m2.lock();
+            let mut g1 = m1.lock();
+            b_.wait();
+            c.wait(&mut g1);
+        });
+
+        let _t2 = thread::spawn(move || {
+            b.wait();
+            let _g1 = m1_.lock();
+            let _g2 = m2_.lock();
+        });
+
+        sleep(Duration::from_millis(50));
+        let deadlocks = parking_lot_core::deadlock::check_deadlock();
+        assert_eq!(deadlocks.len(), 1);
+        assert_eq!(deadlocks[0].len(), 2);
+        let waiter = deadlocks[0]
+            .iter()
+            .find(|t| t.reacquires().is_some())
+            .unwrap();
+        assert_eq!(
+            waiter.blocked_on(),
+            Resource {
+                key: c_key,
+                kind: ResourceKind::Condvar
+            }
+        );
+        assert_eq!(
+            waiter.reacquires(),
+            Some(Resource {
+                key: m1_key,
+                kind: ResourceKind::Mutex
+            })
+        );
+        assert_eq!(DeadlockReport::new(&deadlocks[0]).edges.len(), 2);
+
+        assert!(!check_deadlock());
+    }
+
+    // A thread which fails to park on a custom resource must not report the
+    // kind it set for that park on its next one.
+    #[test]
+    fn test_invalid_park_resets_wait_kind() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m: Arc<Mutex<()>> = Default::default();
+        let resource = Arc::new(0u8);
+        let key = &*resource as *const _ as usize;
+        let b = Arc::new(Barrier::new(2));
+
+        let m_ = m.clone();
+        let b_ = b.clone();
+
+        assert!(!check_deadlock());
+
+        let _t1 = thread::spawn(move || unsafe {
+            parking_lot_core::deadlock::acquire_resource(key);
+            b_.wait();
+            let _g = m_.lock();
+            drop(resource);
+        });
+
+        let _t2 = thread::spawn(move || unsafe {
+            let _g = m.lock();
+            b.wait();
+            super::set_wait_kind(ResourceKind::RwLockExclusive);
+            let invalid = parking_lot_core::park(
+                key,
+                || false,
+                || {},
+                |_, _| {},
+                parking_lot_core::DEFAULT_PARK_TOKEN,
+                None,
+            );
+            assert_eq!(invalid, parking_lot_core::ParkResult::Invalid);
+            parking_lot_core::park(
+                key,
+                || true,
+                || {},
+                |_, _| {},
+                parking_lot_core::DEFAULT_PARK_TOKEN,
+                None,
+            );
+        });
+
+        sleep(Duration::from_millis(50));
+        let deadlocks = parking_lot_core::deadlock::check_deadlock();
+        assert_eq!(deadlocks.len(), 1);
+        assert!(deadlocks[0].iter().any(|t| t.blocked_on()
+            == Resource {
+                key,
+                kind: ResourceKind::Other
+            }));
+
+        assert!(!check_deadlock());
+    }
+
+    // A condvar wait which fails validation must not make the next park of
+    // the thread look like it has to reacquire the mutex afterwards.
+    #[test]
+    fn test_invalid_park_resets_reacquire() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m1: Arc<Mutex<()>> = Default::default();
+        let m2: Arc<Mutex<()>> = Default::default();
+        let m1_key = unsafe { m1.raw() as *const _ as usize };
+        let unrelated = Arc::new(0u8);
+        let key = &*unrelated as *const _ as usize;
+        let b = Arc::new(Barrier::new(2));
+
+        let m1_ = m1.clone();
+        let m2_ = m2.clone();
+        let b_ = b.clone();
+
+        assert!(!check_deadlock());
+
+        // This thread only waits for a key which nobody holds, so it isn't
+        // part of any deadlock even though the other thread waits for it.
+        let _t1 = thread::spawn(move || unsafe {
+            let _g2 = m2.lock();
+            b_.wait();
+            super::set_reacquire_resource(m1_key, ResourceKind::Mutex);
+            let invalid = parking_lot_core::park(
+                key,
+                || false,
+                || {},
+                |_, _| {},
+                parking_lot_core::DEFAULT_PARK_TOKEN,
+                None,
+            );
+            assert_eq!(invalid, parking_lot_core::ParkResult::Invalid);
+            parking_lot_core::park(
+                key,
+                || true,
+                || {},
+                |_, _| {},
+                parking_lot_core::DEFAULT_PARK_TOKEN,
+                None,
+            );
+            drop(unrelated);
+        });
+
+        let _t2 = thread::spawn(move || {
+            let _g1 = m1_.lock();
+            b.wait();
+            let _g2 = m2_.lock();
+        });
+
+        sleep(Duration::from_millis(50));
+        assert!(!check_deadlock());
+    }
+
+    #[test]
+    fn test_once_deadlock() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m: Arc<Mutex<()>> = Default::default();
+        let once = Arc::new(Once::new());
+        let once_key = &*once as *const _ as usize;
+        let b = Arc::new(Barrier::new(2));
+
+        let m_ = m.clone();
+        let once_ = once.clone();
+        let b_ = b.clone();
+
+        assert!(!check_deadlock());
+
+        // The initializer waits for a mutex held by a thread which waits for
+        // the initialization to complete.
+        let _t1 = thread::spawn(move || {
+            once.call_once(|| {
+                b_.wait();
+                let _g = m_.lock();
+            });
+        });
+
+        let _t2 = thread::spawn(move || {
+            let _g = m.lock();
+            b.wait();
+            once_.call_once(|| {});
+        });
+
+        sleep(Duration::from_millis(50));
+        let deadlocks = parking_lot_core::deadlock::check_deadlock();
+        assert_eq!(deadlocks.len(), 1);
+        assert_eq!(deadlocks[0].len(), 2);
+        assert!(deadlocks[0].iter().any(|t| t.blocked_on()
+            == Resource {
+                key: once_key,
+                kind: ResourceKind::Once
+            }));
+        assert!(deadlocks[0].iter().any(|t| t.held_resources()
+            == [Resource {
+                key: once_key,
+                kind: ResourceKind::Once
+            }]));
+
+        assert!(!check_deadlock());
+    }
+
+    #[test]
+    fn test_rwlock_upgradable_deadlock() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m1: Arc<RwLock<()>> = Default::default();
+        let b = Arc::new(Barrier::new(2));
+
+        let m1_ = m1.clone();
+        let b_ = b.clone();
+
+        assert!(!check_deadlock());
+
+        // The upgrade waits for the reader, which waits for the upgradable
+        // lock.
+        let _t1 = thread::spawn(move || {
+            let g = m1.upgradable_read();
+            b_.wait();
+            let _g = RwLockUpgradableReadGuard::upgrade(g);
+        });
+
+        let _t2 = thread::spawn(move || {
+            let _g = m1_.read();
+            b.wait();
+            sleep(Duration::from_millis(10));
+            let _g = m1_.upgradable_read();
+        });
+
+        sleep(Duration::from_millis(50));
+        assert!(check_deadlock());
+
+        assert!(!check_deadlock());
+    }
+
+    #[test]
+    fn test_rwlock_upgrade_deadlock_reentrant() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m1: Arc<RwLock<()>> = Default::default();
+
+        assert!(!check_deadlock());
+
+        let _t1 = thread::spawn(move || {
+            let _g = m1.read();
+            let g = m1.upgradable_read();
+            let _g = RwLockUpgradableReadGuard::upgrade(g);
+        });
+
+        sleep(Duration::from_millis(50));
+        assert!(check_deadlock());
+
+        assert!(!check_deadlock());
+    }
+
+    #[test]
+    fn test_rwlock_pending_writer_deadlock() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m1: Arc<RwLock<()>> = Default::default();
+        let m2: Arc<Mutex<()>> = Default::default();
+        let b = Arc::new(Barrier::new(3));
+
+        let m1_ = m1.clone();
+        let m1__ = m1.clone();
+        let m2_ = m2.clone();
+        let b1 = b.clone();
+        let b2 = b.clone();
+
+        assert!(!check_deadlock());
+
+        // The second reader is blocked by the writer, which waits for the
+        // first reader to exit.
+        let _t1 = thread::spawn(move || {
+            let _g = m1.read();
+            b1.wait();
+            let _g = m2.lock();
+        });
+
+        let _t2 = thread::spawn(move || {
+            let _g = m2_.lock();
+            b2.wait();
+            sleep(Duration::from_millis(20));
+            let _g = m1_.read();
+        });
+
+        let _t3 = thread::spawn(move || {
+            b.wait();
+            let _g = m1__.write();
+        });
+
+        sleep(Duration::from_millis(70));
+        let deadlocks = parking_lot_core::deadlock::check_deadlock();
+        assert_eq!(deadlocks.len(), 1);
+        assert_eq!(deadlocks[0].len(), 3);
+
+        assert!(!check_deadlock());
+    }
+
+    #[test]
+    fn test_rwlock_upgrade_no_deadlock() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m1: Arc<RwLock<()>> = Default::default();
+        let b = Arc::new(Barrier::new(2));
+
+        let m1_ = m1.clone();
+        let b_ = b.clone();
+
+        assert!(!check_deadlock());
+
+        // Upgrading while another thread holds a shared lock isn't a deadlock
+        // on its own.
+        let t1 = thread::spawn(move || {
+            let _g = m1.read();
+            b_.wait();
+            sleep(Duration::from_millis(100));
+        });
+
+        let t2 = thread::spawn(move || {
+            let g = m1_.upgradable_read();
+            b.wait();
+            let _g = RwLockUpgradableReadGuard::upgrade(g);
+        });
+
+        sleep(Duration::from_millis(50));
+        assert!(!check_deadlock());
+
+        t1.join().unwrap();
+        t2.join().unwrap();
+        assert!(!check_deadlock());
+    }
+
+    #[test]
+    fn test_mutex_deadlock_recovery() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m1: Arc<Mutex<()>> = Default::default();
+        let m2: Arc<Mutex<()>> = Default::default();
+        let b = Arc::new(Barrier::new(3));
+
+        assert!(!check_deadlock());
+
+        let t1 = {
+            let (m1, m2, b) = (m1.clone(), m2.clone(), b.clone());
+            thread::spawn(move || {
+                let _g = m1.lock();
+                b.wait();
+                let _g2 = m2.lock();
+            })
+        };
+
+        // Only this thread accepts to give up, so it is always the victim
+        let t2 = {
+            let (m1, m2, b) = (m1.clone(), m2.clone(), b.clone());
+            thread::spawn(move || {
+                let _g = m2.lock();
+                b.wait();
+                m1.lock_or_deadlock().map(|_| ())
+            })
+        };
+
+        b.wait();
+        sleep(Duration::from_millis(50));
+        let deadlocks = parking_lot_core::deadlock::check_deadlock();
+        assert_eq!(deadlocks.len(), 1);
+        assert_eq!(deadlocks[0].iter().filter(|t| t.is_victim()).count(), 1);
+        assert!(DeadlockReport::new(&deadlocks[0])
+            .to_string()
+            .contains("wait aborted to break the deadlock"));
+
+        // The victim releases its lock, which lets the other thread finish
+        assert_eq!(t2.join().unwrap(), Err(DeadlockError(())));
+        t1.join().unwrap();
+        assert!(!check_deadlock());
+        assert!(m1.lock_or_deadlock().is_ok());
+        assert!(m2.lock_or_deadlock().is_ok());
+    }
+
+    #[test]
+    fn test_rwlock_deadlock_recovery() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m1: Arc<RwLock<()>> = Default::default();
+        let m2: Arc<RwLock<()>> = Default::default();
+        let b = Arc::new(Barrier::new(3));
+
+        assert!(!check_deadlock());
+
+        let t1 = {
+            let (m1, m2, b) = (m1.clone(), m2.clone(), b.clone());
+            thread::spawn(move || {
+                let _g = m1.read();
+                b.wait();
+                let _g2 = m2.write();
+            })
+        };
+
+        // The victim is waiting for the reader to leave after having set the
+        // writer bit, which must be cleared again when giving up.
+        let t2 = {
+            let (m1, m2, b) = (m1.clone(), m2.clone(), b.clone());
+            thread::spawn(move || {
+                let _g = m2.write();
+                b.wait();
+                m1.write_or_deadlock().map(|_| ())
+            })
+        };
+
+        b.wait();
+        sleep(Duration::from_millis(50));
+        assert!(check_deadlock());
+
+        assert!(t2.join().unwrap().is_err());
+        t1.join().unwrap();
+        assert!(!check_deadlock());
+        assert!(m1.write_or_deadlock().is_ok());
+        assert!(m2.read_or_deadlock().is_ok());
+    }
+
+    #[test]
+    fn test_exit_while_holding_lock() {
+        let _guard = DEADLOCK_DETECTION_LOCK.lock();
+
+        let m: Arc<Mutex<()>> = Default::default();
+        let key = unsafe { m.raw() as *const _ as usize };
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let reports2 = reports.clone();
+        super::set_capture_backtraces(true);
+        super::set_exit_hook(Box::new(move |thread| {
+            // Other tests may leak locks concurrently
+            if thread
+                .held_resources()
+                .iter()
+                .any(|r| r.resource().key == key)
+            {
+                // Panicking here would abort since the thread is exiting
+                reports2.lock().unwrap().push((
+                    thread.to_string(),
+                    thread.thread_name().map(|name| name.to_owned()),
+                    thread.held_resources()[0].backtrace().is_some(),
+                ));
+            }
+        }));
+
+        let m2 = m.clone();
+        thread::Builder::new()
+            .name("leaker".to_owned())
+            .spawn(move || {
+                // Released before exiting, so not reported
+                drop(m2.lock());
+                std::mem::forget(m2.lock());
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        super::set_exit_hook(Box::new(|thread| eprintln!("{}", thread)));
+        super::set_capture_backtraces(false);
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        let (ref report, ref name, has_backtrace) = reports[0];
+        assert!(report.contains("exited while holding 1 resources"));
+        assert!(report.contains(&format!("holding {:#x} (mutex)", key)));
+        assert_eq!(name.as_ref().map(|name| &name[..]), Some("leaker"));
+        assert!(has_backtrace);
+    }
 }
@@ -0,0 +1,497 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::condvar::WaitTimeoutResult;
+use crate::raw_mutex::{TOKEN_HANDOFF, TOKEN_NORMAL};
+use crate::util;
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use parking_lot_core::{self, ParkResult, SpinWait, UnparkResult, DEFAULT_PARK_TOKEN};
+use std::time::{Duration, Instant};
+
+/// This bit is set in the `state` of an event while it is set. It must be the
+/// lowest bit so that `new` can be a `const fn`.
+const SET_BIT: usize = 0b01;
+/// This bit is set in the `state` of an event just before parking a thread.
+const PARKED_BIT: usize = 0b10;
+
+/// An event which stays set until it is reset, like a Windows manual-reset
+/// event.
+///
+/// While the event is set, `wait` returns immediately. While it isn't, `wait`
+/// blocks until `set` is called, which wakes up all the waiting threads.
+///
+/// Waking up is not spurious: a thread blocked in `wait` only returns once
+/// `set` was called or its timeout elapsed, even if the event is reset again
+/// before it gets to run.
+///
+/// # Examples
+///
+/// ```
+/// use parking_lot::ManualResetEvent;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let event = Arc::new(ManualResetEvent::new(false));
+/// let event2 = event.clone();
+///
+/// thread::spawn(move || {
+///     // Do some initialization...
+///     event2.set();
+/// });
+///
+/// event.wait();
+/// assert!(event.is_set());
+/// ```
+pub struct ManualResetEvent {
+    state: AtomicUsize,
+}
+
+impl ManualResetEvent {
+    /// Creates a new event, which is initially set if `set` is true.
+    #[inline]
+    pub const fn new(set: bool) -> ManualResetEvent {
+        ManualResetEvent {
+            state: AtomicUsize::new(set as usize),
+        }
+    }
+
+    /// Returns whether the event is currently set.
+    #[inline]
+    pub fn is_set(&self) -> bool {
+        self.state.load(Ordering::Acquire) & SET_BIT != 0
+    }
+
+    /// Sets the event, waking up all the threads waiting for it.
+    #[inline]
+    pub fn set(&self) {
+        let state = self.state.swap(SET_BIT, Ordering::Release);
+        if state & PARKED_BIT != 0 {
+            // SAFETY: `addr` is an address we control.
+            unsafe {
+                let addr = self as *const _ as usize;
+                parking_lot_core::unpark_all(addr, TOKEN_NORMAL);
+            }
+        }
+    }
+
+    /// Resets the event, so that the following calls to `wait` block until it
+    /// is set again.
+    #[inline]
+    pub fn reset(&self) {
+        self.state.fetch_and(!SET_BIT, Ordering::Relaxed);
+    }
+
+    /// Blocks the current thread until the event is set.
+    #[inline]
+    pub fn wait(&self) {
+        if !self.is_set() {
+            let result = self.wait_slow(None);
+            debug_assert!(result);
+        }
+    }
+
+    /// Blocks the current thread until the event is set, timing out after the
+    /// specified time instant.
+    ///
+    /// The returned `WaitTimeoutResult` value indicates if the timeout is
+    /// known to have elapsed.
+    #[inline]
+    pub fn wait_until(&self, timeout: Instant) -> WaitTimeoutResult {
+        if self.is_set() {
+            return WaitTimeoutResult(false);
+        }
+        WaitTimeoutResult(!self.wait_slow(Some(timeout)))
+    }
+
+    /// Blocks the current thread until the event is set, timing out after a
+    /// specified duration.
+    ///
+    /// The returned `WaitTimeoutResult` value indicates if the timeout is
+    /// known to have elapsed.
+    #[inline]
+    pub fn wait_for(&self, timeout: Duration) -> WaitTimeoutResult {
+        if self.is_set() {
+            return WaitTimeoutResult(false);
+        }
+        WaitTimeoutResult(!self.wait_slow(util::to_deadline(timeout)))
+    }
+
+    /// Waits until the event is set. Returns false if the timeout expired
+    /// first.
+    #[cold]
+    fn wait_slow(&self, timeout: Option<Instant>) -> bool {
+        park_until(&self.state, timeout, |state| {
+            if state & SET_BIT != 0 {
+                Some(state)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl Default for ManualResetEvent {
+    #[inline]
+    fn default() -> ManualResetEvent {
+        ManualResetEvent::new(false)
+    }
+}
+
+impl fmt::Debug for ManualResetEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManualResetEvent")
+            .field("is_set", &self.is_set())
+            .finish()
+    }
+}
+
+/// An event which is reset automatically when it releases a waiting thread,
+/// like a Windows auto-reset event.
+///
+/// Calling `set` releases exactly one thread: either a thread currently
+/// blocked in `wait`, which is woken up while the event stays reset, or, if
+/// there is none, the next thread to call `wait`, which resets the event.
+/// Setting an event which is already set has no effect.
+///
+/// Waking up is not spurious: a thread blocked in `wait` only returns once it
+/// consumed a call to `set` or its timeout elapsed.
+///
+/// # Examples
+///
+/// ```
+/// use parking_lot::AutoResetEvent;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let event = Arc::new(AutoResetEvent::new(false));
+/// let event2 = event.clone();
+///
+/// thread::spawn(move || {
+///     event2.set();
+/// });
+///
+/// event.wait();
+/// assert!(!event.is_set());
+/// ```
+pub struct AutoResetEvent {
+    state: AtomicUsize,
+}
+
+impl AutoResetEvent {
+    /// Creates a new event, which is initially set if `set` is true.
+    #[inline]
+    pub const fn new(set: bool) -> AutoResetEvent {
+        AutoResetEvent {
+            state: AtomicUsize::new(set as usize),
+        }
+    }
+
+    /// Returns whether the event is currently set, which means that the next
+    /// call to `wait` will return immediately.
+    #[inline]
+    pub fn is_set(&self) -> bool {
+        self.state.load(Ordering::Acquire) & SET_BIT != 0
+    }
+
+    /// Sets the event, releasing one thread waiting for it if there is one.
+    #[inline]
+    pub fn set(&self) {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & SET_BIT != 0 {
+                return;
+            }
+            if state & PARKED_BIT != 0 {
+                self.set_slow();
+                return;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state | SET_BIT,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(x) => state = x,
+            }
+        }
+    }
+
+    /// Resets the event, so that the next call to `wait` blocks until it is
+    /// set again.
+    #[inline]
+    pub fn reset(&self) {
+        self.state.fetch_and(!SET_BIT, Ordering::Relaxed);
+    }
+
+    /// Blocks the current thread until the event is set, and resets it.
+    #[inline]
+    pub fn wait(&self) {
+        if !self.try_consume() {
+            let result = self.wait_slow(None);
+            debug_assert!(result);
+        }
+    }
+
+    /// Blocks the current thread until the event is set, and resets it,
+    /// timing out after the specified time instant.
+    ///
+    /// The returned `WaitTimeoutResult` value indicates if the timeout is
+    /// known to have elapsed, in which case the event was not reset.
+    #[inline]
+    pub fn wait_until(&self, timeout: Instant) -> WaitTimeoutResult {
+        if self.try_consume() {
+            return WaitTimeoutResult(false);
+        }
+        WaitTimeoutResult(!self.wait_slow(Some(timeout)))
+    }
+
+    /// Blocks the current thread until the event is set, and resets it,
+    /// timing out after a specified duration.
+    ///
+    /// The returned `WaitTimeoutResult` value indicates if the timeout is
+    /// known to have elapsed, in which case the event was not reset.
+    #[inline]
+    pub fn wait_for(&self, timeout: Duration) -> WaitTimeoutResult {
+        if self.try_consume() {
+            return WaitTimeoutResult(false);
+        }
+        WaitTimeoutResult(!self.wait_slow(util::to_deadline(timeout)))
+    }
+
+    /// Resets the event if it is set, and returns whether it was.
+    #[inline]
+    fn try_consume(&self) -> bool {
+        self.state
+            .compare_exchange(SET_BIT, 0, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Waits until the event is set and resets it. Returns false if the
+    /// timeout expired first.
+    #[cold]
+    fn wait_slow(&self, timeout: Option<Instant>) -> bool {
+        park_until(&self.state, timeout, |state| {
+            if state & SET_BIT != 0 {
+                Some(state & !SET_BIT)
+            } else {
+                None
+            }
+        })
+    }
+
+    #[cold]
+    fn set_slow(&self) {
+        let addr = self as *const _ as usize;
+        let callback = |result: UnparkResult| {
+            // Hand the event off to the unparked thread without setting it,
+            // and clear the parked bit if there are no more parked threads.
+            if result.unparked_threads != 0 {
+                if !result.have_more_threads {
+                    self.state.fetch_and(!PARKED_BIT, Ordering::Relaxed);
+                }
+                return TOKEN_HANDOFF;
+            }
+
+            // The parked threads timed out in the meantime, so set the event
+            // for the next thread calling `wait`.
+            self.state.store(SET_BIT, Ordering::Release);
+            TOKEN_NORMAL
+        };
+        // SAFETY:
+        //   * `addr` is an address we control.
+        //   * `callback` does not panic or call into any function of `parking_lot`.
+        unsafe {
+            parking_lot_core::unpark_one(addr, callback);
+        }
+    }
+}
+
+impl Default for AutoResetEvent {
+    #[inline]
+    fn default() -> AutoResetEvent {
+        AutoResetEvent::new(false)
+    }
+}
+
+impl fmt::Debug for AutoResetEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AutoResetEvent")
+            .field("is_set", &self.is_set())
+            .finish()
+    }
+}
+
+/// Blocks until `acquire` returns the new state for the current `state`, or
+/// the timeout expires. A thread which is unparked by the setter has been
+/// released, so this returns true without checking the state again.
+fn park_until(
+    state: &AtomicUsize,
+    timeout: Option<Instant>,
+    acquire: impl Fn(usize) -> Option<usize>,
+) -> bool {
+    let mut spinwait = SpinWait::new();
+    let mut current = state.load(Ordering::Relaxed);
+    loop {
+        // Grab the event if it is set
+        if let Some(new) = acquire(current) {
+            match state.compare_exchange_weak(current, new, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(x) => current = x,
+            }
+            continue;
+        }
+
+        // If there are no parked threads, try spinning a few times
+        if current & PARKED_BIT == 0 && spinwait.spin() {
+            current = state.load(Ordering::Relaxed);
+            continue;
+        }
+
+        // Set the parked bit
+        if current & PARKED_BIT == 0 {
+            if let Err(x) = state.compare_exchange_weak(
+                current,
+                current | PARKED_BIT,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                current = x;
+                continue;
+            }
+        }
+
+        // Park our thread until we are woken up by a call to `set`
+        let addr = state as *const _ as usize;
+        let validate = || state.load(Ordering::Relaxed) == PARKED_BIT;
+        let before_sleep = || {};
+        let timed_out = |_, was_last_thread| {
+            // Clear the parked bit if we were the last parked thread
+            if was_last_thread {
+                state.fetch_and(!PARKED_BIT, Ordering::Relaxed);
+            }
+        };
+        // SAFETY:
+        //   * `addr` is an address we control.
+        //   * `validate`/`timed_out` does not panic or call into any function of `parking_lot`.
+        //   * `before_sleep` does not call `park`, nor does it panic.
+        match unsafe {
+            parking_lot_core::park(
+                addr,
+                validate,
+                before_sleep,
+                timed_out,
+                DEFAULT_PARK_TOKEN,
+                timeout,
+            )
+        } {
+            // We were released by `set`
+            ParkResult::Unparked(_) => return true,
+
+            // The event was set or the parked bit cleared before we could
+            // park, try again
+            ParkResult::Invalid => (),
+
+            // Timeout expired
+            ParkResult::TimedOut => return false,
+        }
+
+        // Loop back and try grabbing the event again
+        spinwait.reset();
+        current = state.load(Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AutoResetEvent, ManualResetEvent};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn manual_smoke() {
+        let event = ManualResetEvent::new(false);
+        assert!(!event.is_set());
+        assert!(event.wait_for(Duration::from_millis(10)).timed_out());
+        event.set();
+        event.wait();
+        event.wait();
+        assert!(event.is_set());
+        event.reset();
+        assert!(!event.is_set());
+    }
+
+    #[test]
+    fn manual_wakes_all() {
+        let event = Arc::new(ManualResetEvent::new(false));
+        let waiters: Vec<_> = (0..4)
+            .map(|_| {
+                let event = event.clone();
+                thread::spawn(move || event.wait_for(Duration::from_secs(10)).timed_out())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(20));
+
+        // Threads which were waiting are released even if the event is reset
+        // right away.
+        event.set();
+        event.reset();
+        for waiter in waiters {
+            assert!(!waiter.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn auto_smoke() {
+        let event = AutoResetEvent::new(true);
+        assert!(event.is_set());
+        event.wait();
+        assert!(!event.is_set());
+        assert!(event.wait_for(Duration::from_millis(10)).timed_out());
+
+        // Setting twice only releases one wait
+        event.set();
+        event.set();
+        assert!(!event.wait_for(Duration::from_millis(10)).timed_out());
+        assert!(event.wait_for(Duration::from_millis(10)).timed_out());
+    }
+
+    #[test]
+    fn auto_releases_one() {
+        const N: usize = 4;
+        let event = Arc::new(AutoResetEvent::new(false));
+        let released = Arc::new(AtomicUsize::new(0));
+        let waiters: Vec<_> = (0..N)
+            .map(|_| {
+                let (event, released) = (event.clone(), released.clone());
+                thread::spawn(move || {
+                    event.wait();
+                    released.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(20));
+
+        for i in 1..=N {
+            event.set();
+            while released.load(Ordering::SeqCst) < i {
+                thread::yield_now();
+            }
+            thread::sleep(Duration::from_millis(5));
+            assert_eq!(released.load(Ordering::SeqCst), i);
+        }
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert!(!event.is_set());
+    }
+}
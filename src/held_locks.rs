@@ -0,0 +1,395 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Per-thread held locks
+//!
+//! This feature is optional and can be enabled via the `held_locks` feature
+//! flag. When it is disabled, none of the instrumentation is compiled in.
+//! Unlike the deadlock detector, it doesn't depend on any other crate.
+//!
+//! Every thread keeps a list of the `Mutex`, `ReentrantMutex` and `RwLock`
+//! instances it currently holds, in acquisition order. `held_locks` returns
+//! that list for the calling thread, and `assert_no_locks_held` panics if it
+//! isn't empty, for example at request boundaries or before blocking I/O.
+//!
+//! Locks are identified by the address of their raw lock, which can be
+//! obtained with `Mutex::raw` or `RwLock::raw`. A name can be given to that
+//! address with `set_name`. If the `backtrace` feature is enabled as well,
+//! `set_capture_backtraces` makes every acquisition record where it happened.
+//!
+//! # Example
+//!
+//! ```
+//! #[cfg(feature = "held_locks")]
+//! { // only for #[cfg]
+//! use parking_lot::{held_locks, Mutex};
+//!
+//! let mutex = Mutex::new(0);
+//! let address = unsafe { mutex.raw() } as *const _ as usize;
+//! held_locks::set_name(address, Some("counter"));
+//!
+//! let guard = mutex.lock();
+//! for lock in parking_lot::held_locks() {
+//!     println!("{}", lock);
+//! }
+//! drop(guard);
+//! parking_lot::assert_no_locks_held();
+//! } // only for #[cfg]
+//! ```
+
+#[cfg(feature = "held_locks")]
+pub use parking_lot_core::deadlock::ResourceKind;
+#[cfg(not(feature = "held_locks"))]
+use parking_lot_core::deadlock::ResourceKind;
+
+#[cfg(all(feature = "held_locks", feature = "backtrace"))]
+pub use self::held_locks_impl::set_capture_backtraces;
+#[cfg(feature = "held_locks")]
+pub use self::held_locks_impl::{assert_no_locks_held, held_locks, set_name, HeldLock};
+
+/// Records that the current thread acquired the lock at `_address`.
+#[inline]
+pub(crate) fn acquire(_address: usize, _kind: ResourceKind) {
+    #[cfg(feature = "held_locks")]
+    held_locks_impl::acquire(_address, _kind);
+}
+
+/// Records that the lock at `_address` held by the current thread is now held
+/// in `_kind` mode, without moving it in the list.
+#[inline]
+pub(crate) fn change(_address: usize, _kind: ResourceKind) {
+    #[cfg(feature = "held_locks")]
+    held_locks_impl::change(_address, _kind);
+}
+
+/// Records that the current thread released the lock at `_address`.
+#[inline]
+pub(crate) fn release(_address: usize) {
+    #[cfg(feature = "held_locks")]
+    held_locks_impl::release(_address);
+}
+
+#[cfg(feature = "held_locks")]
+mod held_locks_impl {
+    use super::ResourceKind;
+    use crate::Lazy;
+    #[cfg(feature = "backtrace")]
+    use backtrace::Backtrace;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::fmt;
+    #[cfg(feature = "backtrace")]
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Mutex, PoisonError};
+
+    /// A lock held by the current thread.
+    pub struct HeldLock {
+        address: usize,
+        kind: ResourceKind,
+        name: Option<&'static str>,
+        #[cfg(feature = "backtrace")]
+        backtrace: Option<Backtrace>,
+    }
+
+    impl HeldLock {
+        /// Address of the raw lock.
+        pub fn address(&self) -> usize {
+            self.address
+        }
+
+        /// The kind of the lock and how it is held. `ReentrantMutex` is
+        /// reported as `ResourceKind::Mutex`, once however many times it was
+        /// locked.
+        pub fn kind(&self) -> ResourceKind {
+            self.kind
+        }
+
+        /// The name given to the lock with `set_name`, if any.
+        pub fn name(&self) -> Option<&'static str> {
+            self.name
+        }
+
+        /// Backtrace of the acquisition of the lock, if backtraces were
+        /// enabled with `set_capture_backtraces` when it was acquired.
+        #[cfg(feature = "backtrace")]
+        pub fn backtrace(&self) -> Option<&Backtrace> {
+            self.backtrace.as_ref()
+        }
+    }
+
+    impl fmt::Debug for HeldLock {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let mut d = f.debug_struct("HeldLock");
+            d.field("address", &self.address)
+                .field("kind", &self.kind)
+                .field("name", &self.name);
+            #[cfg(feature = "backtrace")]
+            d.field("backtrace", &self.backtrace);
+            d.finish()
+        }
+    }
+
+    impl fmt::Display for HeldLock {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:#x} ({})", self.address, self.kind)?;
+            if let Some(name) = self.name {
+                write!(f, " \"{}\"", name)?;
+            }
+            #[cfg(feature = "backtrace")]
+            {
+                if let Some(ref backtrace) = self.backtrace {
+                    write!(f, ", acquired at:\n{:?}", backtrace)?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct Held {
+        address: usize,
+        kind: ResourceKind,
+        #[cfg(feature = "backtrace")]
+        backtrace: Option<Backtrace>,
+    }
+
+    #[cfg(feature = "backtrace")]
+    static CAPTURE_BACKTRACES: AtomicBool = AtomicBool::new(false);
+
+    // This uses the standard library mutex since our own locks are the ones
+    // being instrumented.
+    static NAMES: Lazy<Mutex<HashMap<usize, &'static str>>> =
+        Lazy::new(|| Mutex::new(HashMap::new()));
+
+    thread_local!(static HELD: RefCell<Vec<Held>> = RefCell::default());
+
+    pub(super) fn acquire(address: usize, kind: ResourceKind) {
+        let held = Held {
+            address,
+            kind,
+            #[cfg(feature = "backtrace")]
+            backtrace: if CAPTURE_BACKTRACES.load(Ordering::Relaxed) {
+                Some(Backtrace::new_unresolved())
+            } else {
+                None
+            },
+        };
+        // Locks used while the thread-local list is being destroyed aren't
+        // tracked.
+        let _ = HELD.try_with(|list| list.borrow_mut().push(held));
+    }
+
+    pub(super) fn change(address: usize, kind: ResourceKind) {
+        let _ = HELD.try_with(|list| {
+            let mut list = list.borrow_mut();
+            if let Some(held) = list.iter_mut().rev().find(|held| held.address == address) {
+                held.kind = kind;
+            }
+        });
+    }
+
+    pub(super) fn release(address: usize) {
+        // A lock may be released by another thread than the one which
+        // acquired it, for example with `force_unlock`, in which case it
+        // isn't in the list of the current thread.
+        let _ = HELD.try_with(|list| {
+            let mut list = list.borrow_mut();
+            if let Some(p) = list.iter().rposition(|held| held.address == address) {
+                list.remove(p);
+            }
+        });
+    }
+
+    /// Returns the locks currently held by the calling thread, in the order
+    /// in which they were acquired.
+    ///
+    /// A lock held in shared mode several times is listed once per
+    /// acquisition.
+    pub fn held_locks() -> Vec<HeldLock> {
+        let mut locks: Vec<HeldLock> = HELD
+            .try_with(|list| {
+                list.borrow()
+                    .iter()
+                    .map(|held| HeldLock {
+                        address: held.address,
+                        kind: held.kind,
+                        name: None,
+                        #[cfg(feature = "backtrace")]
+                        backtrace: held.backtrace.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !locks.is_empty() {
+            let names = NAMES.lock().unwrap_or_else(PoisonError::into_inner);
+            for lock in &mut locks {
+                lock.name = names.get(&lock.address).cloned();
+            }
+        }
+        #[cfg(feature = "backtrace")]
+        for lock in &mut locks {
+            if let Some(ref mut backtrace) = lock.backtrace {
+                backtrace.resolve();
+            }
+        }
+        locks
+    }
+
+    /// Panics if the calling thread holds any lock, listing them in the
+    /// panic message.
+    pub fn assert_no_locks_held() {
+        let locks = held_locks();
+        if !locks.is_empty() {
+            let mut message = format!("the current thread still holds {} locks:", locks.len());
+            for lock in &locks {
+                message.push_str("\n  ");
+                message.push_str(&lock.to_string());
+            }
+            panic!("{}", message);
+        }
+    }
+
+    /// Sets the name reported for the lock at `address`, or removes it if
+    /// `name` is `None`.
+    ///
+    /// The name is associated with the address rather than the lock, so it
+    /// should be removed before the lock is dropped if another lock could
+    /// later be created at the same address.
+    pub fn set_name(address: usize, name: Option<&'static str>) {
+        let mut names = NAMES.lock().unwrap_or_else(PoisonError::into_inner);
+        match name {
+            Some(name) => names.insert(address, name),
+            None => names.remove(&address),
+        };
+    }
+
+    /// Sets whether a backtrace should be captured every time a lock is
+    /// acquired, so that it can be included in the held locks. This is
+    /// disabled by default since it makes locking much more expensive.
+    #[cfg(feature = "backtrace")]
+    pub fn set_capture_backtraces(capture: bool) {
+        CAPTURE_BACKTRACES.store(capture, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "held_locks")]
+mod tests {
+    use super::{assert_no_locks_held, held_locks, set_name, ResourceKind};
+    use crate::{
+        Condvar, Mutex, ReentrantMutex, RwLock, RwLockUpgradableReadGuard, RwLockWriteGuard,
+    };
+    use std::mem;
+    use std::time::Duration;
+
+    // The list is per-thread, so these tests don't need to be serialized.
+    fn held() -> Vec<(usize, ResourceKind)> {
+        held_locks()
+            .iter()
+            .map(|lock| (lock.address(), lock.kind()))
+            .collect()
+    }
+
+    fn key<T>(mutex: &Mutex<T>) -> usize {
+        unsafe { mutex.raw() as *const _ as usize }
+    }
+
+    #[test]
+    fn test_mutex() {
+        let a = Mutex::new(());
+        let b = Mutex::new(());
+        set_name(key(&b), Some("b"));
+        assert!(held().is_empty());
+        {
+            let _a = a.lock();
+            let _b = b.try_lock().unwrap();
+            assert_eq!(
+                held(),
+                vec![
+                    (key(&a), ResourceKind::Mutex),
+                    (key(&b), ResourceKind::Mutex)
+                ]
+            );
+            assert_eq!(held_locks()[1].name(), Some("b"));
+        }
+        set_name(key(&b), None);
+        assert!(held().is_empty());
+    }
+
+    #[test]
+    fn test_remutex() {
+        let m = ReentrantMutex::new(());
+        let _a = m.lock();
+        let _b = m.lock();
+        assert_eq!(held().len(), 1);
+    }
+
+    #[test]
+    fn test_rwlock() {
+        let lock = RwLock::new(());
+        let addr = unsafe { lock.raw() as *const _ as usize };
+        {
+            let _a = lock.read();
+            let _b = lock.read_recursive();
+            assert_eq!(
+                held(),
+                vec![
+                    (addr, ResourceKind::RwLockShared),
+                    (addr, ResourceKind::RwLockShared)
+                ]
+            );
+        }
+        let guard = lock.upgradable_read();
+        assert_eq!(held(), vec![(addr, ResourceKind::RwLockUpgradable)]);
+        let guard = RwLockUpgradableReadGuard::upgrade(guard);
+        assert_eq!(held(), vec![(addr, ResourceKind::RwLockExclusive)]);
+        drop(guard);
+        assert!(held().is_empty());
+    }
+
+    #[test]
+    fn test_rwlock_change_keeps_order() {
+        let lock = RwLock::new(());
+        let mutex = Mutex::new(());
+        let addr = unsafe { lock.raw() as *const _ as usize };
+        let guard = lock.write();
+        let _m = mutex.lock();
+        let _guard = RwLockWriteGuard::downgrade(guard);
+        assert_eq!(
+            held(),
+            vec![
+                (addr, ResourceKind::RwLockShared),
+                (key(&mutex), ResourceKind::Mutex)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_condvar_wait_releases() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+        let mut guard = mutex.lock();
+        condvar.wait_for(&mut guard, Duration::from_millis(10));
+        assert_eq!(held(), vec![(key(&mutex), ResourceKind::Mutex)]);
+    }
+
+    #[test]
+    fn test_force_unlock() {
+        let mutex = Mutex::new(());
+        mem::forget(mutex.lock());
+        assert_eq!(held().len(), 1);
+        unsafe { mutex.force_unlock() };
+        assert_no_locks_held();
+    }
+
+    #[test]
+    #[should_panic(expected = "the current thread still holds 1 locks")]
+    fn test_assert_no_locks_held() {
+        let mutex = Mutex::new(());
+        let _guard = mutex.lock();
+        assert_no_locks_held();
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Lock hold-time warnings
+//!
+//! This feature is optional and can be enabled via the `hold_time_warnings`
+//! feature flag. When it is disabled, none of the instrumentation is compiled
+//! in.
+//!
+//! Every exclusive acquisition of a `Mutex`, `ReentrantMutex` or `RwLock`,
+//! and every upgradable acquisition of a `RwLock`, records when it happened.
+//! When the lock is released, through a guard or with `force_unlock`, a hook
+//! is called if it was held for longer than a threshold. This helps finding
+//! code which holds a lock across a blocking call. Upgrading or downgrading a
+//! `RwLock` between upgradable and exclusive access continues the same hold,
+//! and shared acquisitions are not tracked.
+//!
+//! The hook is called on the releasing thread, after the lock has been
+//! released. The default hook prints the warning to standard error.
+//!
+//! Acquisitions are recorded by the acquiring thread without any global
+//! synchronization, so a lock released by another thread than the one which
+//! acquired it is never reported.
+//!
+//! # Example
+//!
+//! ```
+//! #[cfg(feature = "hold_time_warnings")]
+//! { // only for #[cfg]
+//! use std::time::Duration;
+//! use parking_lot::hold_time;
+//!
+//! hold_time::set_threshold(Duration::from_millis(10));
+//! hold_time::set_capture_backtraces(true);
+//! hold_time::set_hook(Box::new(|hold| eprintln!("{}", hold)));
+//! } // only for #[cfg]
+//! ```
+
+#[cfg(feature = "hold_time_warnings")]
+pub use self::hold_time_impl::{
+    set_capture_backtraces, set_hook, set_threshold, threshold, LongHold,
+};
+
+/// Reports a hold exceeding the threshold when dropped. This must be dropped
+/// after the lock is released so that the hook can use the lock.
+#[derive(Default)]
+pub(crate) struct Release {
+    #[cfg(feature = "hold_time_warnings")]
+    hold: Option<LongHold>,
+}
+
+#[cfg(feature = "hold_time_warnings")]
+impl Drop for Release {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(hold) = self.hold.take() {
+            hold_time_impl::report(hold);
+        }
+    }
+}
+
+/// Records an exclusive or upgradable acquisition of the lock at `_key`.
+#[inline]
+pub(crate) fn acquire(_key: usize) {
+    #[cfg(feature = "hold_time_warnings")]
+    hold_time_impl::acquire(_key);
+}
+
+/// Records the release of an exclusive or upgradable lock at `_key`. Must be
+/// called before the lock is released.
+#[inline]
+pub(crate) fn release(_key: usize) -> Release {
+    Release {
+        #[cfg(feature = "hold_time_warnings")]
+        hold: hold_time_impl::release(_key),
+    }
+}
+
+#[cfg(feature = "hold_time_warnings")]
+mod hold_time_impl {
+    use crate::Lazy;
+    use backtrace::Backtrace;
+    use std::cell::RefCell;
+    use std::fmt;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::{Arc, PoisonError, RwLock};
+    use std::time::{Duration, Instant};
+
+    /// Maximum number of acquisitions being timed by a thread. This
+    /// only matters for locks which are released by another thread, since
+    /// they are never removed otherwise.
+    const MAX_HELD: usize = 64;
+
+    /// A lock which was held for longer than the threshold.
+    pub struct LongHold {
+        key: usize,
+        held_for: Duration,
+        backtrace: Option<Backtrace>,
+    }
+
+    impl LongHold {
+        /// Address of the raw lock which was held.
+        pub fn key(&self) -> usize {
+            self.key
+        }
+
+        /// How long the lock was held.
+        pub fn held_for(&self) -> Duration {
+            self.held_for
+        }
+
+        /// Backtrace of the acquisition of the lock, if backtraces were
+        /// enabled with `set_capture_backtraces` when it was acquired.
+        pub fn backtrace(&self) -> Option<&Backtrace> {
+            self.backtrace.as_ref()
+        }
+    }
+
+    impl fmt::Debug for LongHold {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("LongHold")
+                .field("key", &self.key)
+                .field("held_for", &self.held_for)
+                .field("backtrace", &self.backtrace)
+                .finish()
+        }
+    }
+
+    impl fmt::Display for LongHold {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "lock {:#x} held for {:?}", self.key, self.held_for)?;
+            if let Some(ref backtrace) = self.backtrace {
+                write!(f, ", acquired at:\n{:?}", backtrace)?;
+            }
+            Ok(())
+        }
+    }
+
+    type Hook = Arc<dyn Fn(&LongHold) + Send + Sync>;
+
+    struct Hold {
+        key: usize,
+        since: Instant,
+        backtrace: Option<Backtrace>,
+    }
+
+    // Default threshold of 100ms, in nanoseconds
+    static THRESHOLD: AtomicU64 = AtomicU64::new(100_000_000);
+    static CAPTURE_BACKTRACES: AtomicBool = AtomicBool::new(false);
+
+    // This uses the standard library lock since our own locks are the ones
+    // being instrumented.
+    static HOOK: Lazy<RwLock<Hook>> =
+        Lazy::new(|| RwLock::new(Arc::new(|hold: &LongHold| eprintln!("{}", hold))));
+
+    thread_local!(static HELD: RefCell<Vec<Hold>> = RefCell::default());
+
+    fn with_held<T>(f: impl FnOnce(&mut Vec<Hold>) -> Option<T>) -> Option<T> {
+        // Locks used while the thread-local data is being destroyed, or by
+        // the allocator while it is being updated, aren't timed.
+        HELD.try_with(|held| match held.try_borrow_mut() {
+            Ok(mut held) => f(&mut held),
+            Err(_) => None,
+        })
+        .unwrap_or(None)
+    }
+
+    pub(super) fn acquire(key: usize) {
+        let backtrace = if CAPTURE_BACKTRACES.load(Ordering::Relaxed) {
+            Some(Backtrace::new_unresolved())
+        } else {
+            None
+        };
+        let hold = Hold {
+            key,
+            since: Instant::now(),
+            backtrace,
+        };
+        with_held(|held| {
+            // An entry left by a release on another thread is stale.
+            match held.iter().position(|h| h.key == key) {
+                Some(p) => held[p] = hold,
+                None => {
+                    if held.len() >= MAX_HELD {
+                        held.remove(0);
+                    }
+                    held.push(hold);
+                }
+            }
+            Some(())
+        });
+    }
+
+    pub(super) fn release(key: usize) -> Option<LongHold> {
+        let now = Instant::now();
+        let hold = with_held(|held| {
+            let p = held.iter().rposition(|h| h.key == key)?;
+            Some(held.swap_remove(p))
+        })?;
+        let held_for = now - hold.since;
+        if held_for < threshold() {
+            return None;
+        }
+        Some(LongHold {
+            key,
+            held_for,
+            backtrace: hold.backtrace,
+        })
+    }
+
+    pub(super) fn report(mut hold: LongHold) {
+        if let Some(ref mut backtrace) = hold.backtrace {
+            backtrace.resolve();
+        }
+        let hook = HOOK.read().unwrap_or_else(PoisonError::into_inner).clone();
+        hook(&hold);
+    }
+
+    /// Returns the current threshold above which holds are reported.
+    pub fn threshold() -> Duration {
+        let nanos = THRESHOLD.load(Ordering::Relaxed);
+        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+    }
+
+    /// Sets the threshold above which holds are reported. This is 100ms by
+    /// default, and thresholds above about 584 years are clamped.
+    pub fn set_threshold(threshold: Duration) {
+        let nanos = threshold
+            .as_secs()
+            .saturating_mul(1_000_000_000)
+            .saturating_add(u64::from(threshold.subsec_nanos()));
+        THRESHOLD.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Sets whether a backtrace should be captured every time a lock is
+    /// acquired, so that it can be included in the reported holds. This is
+    /// disabled by default since it makes locking much more expensive.
+    pub fn set_capture_backtraces(capture: bool) {
+        CAPTURE_BACKTRACES.store(capture, Ordering::Relaxed);
+    }
+
+    /// Replaces the hook called for every hold exceeding the threshold.
+    ///
+    /// The hook is called on the thread which released the lock, after
+    /// releasing it. It may use the lock, but any lock it holds for longer
+    /// than the threshold is reported again.
+    pub fn set_hook(hook: Box<dyn Fn(&LongHold) + Send + Sync>) {
+        *HOOK.write().unwrap_or_else(PoisonError::into_inner) = Arc::from(hook);
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "hold_time_warnings")]
+mod tests {
+    use super::{set_hook, set_threshold, threshold};
+    use crate::{Condvar, Mutex, MutexGuard, RwLock, RwLockUpgradableReadGuard, RwLockWriteGuard};
+    use std::mem;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    // We need to serialize these tests since the hook and threshold are
+    // global.
+    lazy_static::lazy_static! {
+        static ref HOLD_TIME_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    }
+
+    // Runs `f` with a low threshold and returns how long each of the given
+    // keys was reported to be held for.
+    fn reported(keys: &[usize], f: impl FnOnce()) -> Vec<(usize, Duration)> {
+        let _guard = HOLD_TIME_LOCK.lock();
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reports2 = reports.clone();
+        let keys = keys.to_vec();
+        let old_threshold = threshold();
+        set_threshold(Duration::from_millis(20));
+        set_hook(Box::new(move |hold| {
+            if keys.contains(&hold.key()) {
+                reports2.lock().unwrap().push((hold.key(), hold.held_for()));
+            }
+        }));
+        f();
+        set_hook(Box::new(|hold| eprintln!("{}", hold)));
+        set_threshold(old_threshold);
+        let reports = reports.lock().unwrap();
+        reports.clone()
+    }
+
+    fn key<T>(mutex: &Mutex<T>) -> usize {
+        unsafe { mutex.raw() as *const _ as usize }
+    }
+
+    #[test]
+    fn test_mutex_hold() {
+        let mutex = Mutex::new(());
+        let reports = reported(&[key(&mutex)], || {
+            drop(mutex.lock());
+            let _guard = mutex.lock();
+            thread::sleep(Duration::from_millis(30));
+        });
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].1 >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_force_unlock_hold() {
+        let mutex = Mutex::new(());
+        let reports = reported(&[key(&mutex)], || {
+            mem::forget(mutex.lock());
+            thread::sleep(Duration::from_millis(30));
+            unsafe { mutex.force_unlock() };
+            // The hook runs after the lock was released.
+            assert!(mutex.try_lock().is_some());
+        });
+        assert_eq!(reports.len(), 1);
+    }
+
+    #[test]
+    fn test_rwlock_hold() {
+        let lock = RwLock::new(());
+        let rw_key = unsafe { lock.raw() as *const _ as usize };
+        let reports = reported(&[rw_key], || {
+            {
+                let _guard = lock.read();
+                thread::sleep(Duration::from_millis(30));
+            }
+            let _guard = lock.write();
+            thread::sleep(Duration::from_millis(30));
+        });
+        assert_eq!(reports.len(), 1);
+    }
+
+    #[test]
+    fn test_rwlock_upgradable_hold() {
+        let lock = RwLock::new(());
+        let rw_key = unsafe { lock.raw() as *const _ as usize };
+        let reports = reported(&[rw_key], || {
+            {
+                let _guard = lock.upgradable_read();
+                thread::sleep(Duration::from_millis(30));
+            }
+
+            // Upgrading and downgrading doesn't start a new hold
+            let guard = lock.upgradable_read();
+            thread::sleep(Duration::from_millis(15));
+            let guard = RwLockUpgradableReadGuard::upgrade(guard);
+            thread::sleep(Duration::from_millis(15));
+            let guard = RwLockWriteGuard::downgrade_to_upgradable(guard);
+            drop(RwLockUpgradableReadGuard::downgrade(guard));
+        });
+        assert_eq!(reports.len(), 2);
+        assert!(reports[1].1 >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_huge_threshold() {
+        let _guard = HOLD_TIME_LOCK.lock();
+        let old_threshold = threshold();
+        set_threshold(Duration::from_secs(!0));
+        assert!(threshold() >= Duration::from_secs(500 * 365 * 24 * 3600));
+        set_threshold(old_threshold);
+    }
+
+    #[test]
+    fn test_condvar_wait_ends_hold() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+        let reports = reported(&[key(&mutex)], || {
+            let mut guard = mutex.lock();
+            condvar.wait_for(&mut guard, Duration::from_millis(30));
+            MutexGuard::unlocked(&mut guard, || {});
+        });
+        assert!(reports.is_empty());
+    }
+}
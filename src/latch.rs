@@ -0,0 +1,358 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::condvar::WaitTimeoutResult;
+use crate::util;
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use parking_lot_core::{self, ParkResult, SpinWait, UnparkToken, DEFAULT_PARK_TOKEN};
+use std::time::{Duration, Instant};
+
+/// This bit is set in the `state` of a `Latch` just before parking a thread.
+const PARKED_BIT: usize = 0b1;
+/// The count is stored in the remaining bits of the `state`.
+const COUNT_SHIFT: u32 = 1;
+/// Base unit for the count.
+const ONE: usize = 1 << COUNT_SHIFT;
+
+// UnparkToken used to indicate that the count reached zero.
+const TOKEN_RELEASED: UnparkToken = UnparkToken(1);
+
+/// A count-down latch.
+///
+/// A latch is initialized with a count which is decremented by `count_down`.
+/// Threads calling `wait` are blocked until the count reaches zero, after
+/// which all waits return immediately. Unlike a `Barrier`, the threads
+/// decrementing the count don't wait themselves.
+///
+/// # Differences from a `Mutex<usize>` and `Condvar` based latch
+///
+/// - Only requires 1 word of space.
+/// - Can be statically constructed.
+/// - Does not require any drop glue when dropped.
+/// - Efficient handling of micro-contention using adaptive spinning.
+/// - Threads are only woken up once the count reaches zero.
+///
+/// # Examples
+///
+/// ```
+/// use parking_lot::Latch;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let latch = Arc::new(Latch::new(10));
+/// for _ in 0..10 {
+///     let latch = latch.clone();
+///     thread::spawn(move || {
+///         // Do some work...
+///         latch.count_down();
+///     });
+/// }
+///
+/// // Wait for all the threads to finish their work.
+/// latch.wait();
+/// assert_eq!(latch.count(), 0);
+/// ```
+pub struct Latch {
+    state: AtomicUsize,
+}
+
+impl Latch {
+    /// Creates a new latch with the given count.
+    ///
+    /// The count is clamped to `usize::max_value() >> 1`, which is the
+    /// maximum supported by the latch.
+    #[inline]
+    pub const fn new(count: usize) -> Latch {
+        // Clamp the count without using `if`, which isn't allowed in a
+        // `const fn`.
+        let max = !0usize >> COUNT_SHIFT;
+        let excess = (count > max) as usize * count.wrapping_sub(max);
+        Latch {
+            state: AtomicUsize::new((count - excess) << COUNT_SHIFT),
+        }
+    }
+
+    /// Returns the current count.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) >> COUNT_SHIFT
+    }
+
+    /// Decrements the count, waking up all waiting threads if it reaches
+    /// zero.
+    ///
+    /// Does nothing if the count is already zero.
+    #[inline]
+    pub fn count_down(&self) {
+        self.try_count_down();
+    }
+
+    /// Decrements the count like `count_down`, and returns false if the count
+    /// was already zero.
+    #[inline]
+    pub(crate) fn try_count_down(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state < ONE {
+                return false;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state - ONE,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(x) => state = x,
+            }
+        }
+        if state >> COUNT_SHIFT == 1 && state & PARKED_BIT != 0 {
+            self.release_slow();
+        }
+        true
+    }
+
+    /// Increments the count by `n`. Threads calling `wait` afterwards block
+    /// again until the count reaches zero.
+    ///
+    /// Returns false without changing the count if it would overflow.
+    #[inline]
+    pub(crate) fn try_count_up(&self, n: usize) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            let new_state = match (state >> COUNT_SHIFT).checked_add(n) {
+                Some(count) if count <= !0 >> COUNT_SHIFT => {
+                    (count << COUNT_SHIFT) | (state & PARKED_BIT)
+                }
+                _ => return false,
+            };
+            match self.state.compare_exchange_weak(
+                state,
+                new_state,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(x) => state = x,
+            }
+        }
+    }
+
+    /// Returns true if the count is zero, without blocking.
+    #[inline]
+    pub fn try_wait(&self) -> bool {
+        self.state.load(Ordering::Acquire) < ONE
+    }
+
+    /// Blocks the current thread until the count reaches zero.
+    #[inline]
+    pub fn wait(&self) {
+        if !self.try_wait() {
+            let result = self.wait_slow(None);
+            debug_assert!(result);
+        }
+    }
+
+    /// Blocks the current thread until the count reaches zero, timing out
+    /// after the specified time instant.
+    ///
+    /// The returned `WaitTimeoutResult` value indicates if the timeout is
+    /// known to have elapsed.
+    #[inline]
+    pub fn wait_until(&self, timeout: Instant) -> WaitTimeoutResult {
+        if self.try_wait() {
+            return WaitTimeoutResult(false);
+        }
+        WaitTimeoutResult(!self.wait_slow(Some(timeout)))
+    }
+
+    /// Blocks the current thread until the count reaches zero, timing out
+    /// after a specified duration.
+    ///
+    /// The returned `WaitTimeoutResult` value indicates if the timeout is
+    /// known to have elapsed.
+    #[inline]
+    pub fn wait_for(&self, timeout: Duration) -> WaitTimeoutResult {
+        if self.try_wait() {
+            return WaitTimeoutResult(false);
+        }
+        WaitTimeoutResult(!self.wait_slow(util::to_deadline(timeout)))
+    }
+
+    /// Waits until the count reaches zero. Returns false if the timeout
+    /// expired first.
+    #[cold]
+    fn wait_slow(&self, timeout: Option<Instant>) -> bool {
+        let mut spinwait = SpinWait::new();
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            if state < ONE {
+                return true;
+            }
+
+            // Spin a few times in case the count reaches zero shortly, if
+            // nobody is parked yet
+            if state & PARKED_BIT == 0 && spinwait.spin() {
+                state = self.state.load(Ordering::Acquire);
+                continue;
+            }
+
+            // Set the parked bit
+            if state & PARKED_BIT == 0 {
+                if let Err(x) = self.state.compare_exchange_weak(
+                    state,
+                    state | PARKED_BIT,
+                    Ordering::Acquire,
+                    Ordering::Acquire,
+                ) {
+                    state = x;
+                    continue;
+                }
+            }
+
+            // Park our thread until the count reaches zero
+            let addr = self as *const _ as usize;
+            let validate = || {
+                let state = self.state.load(Ordering::Relaxed);
+                state >= ONE && state & PARKED_BIT != 0
+            };
+            let before_sleep = || {};
+            let timed_out = |_, _| {};
+            // SAFETY:
+            //   * `addr` is an address we control.
+            //   * `validate`/`timed_out` does not panic or call into any function of `parking_lot`.
+            //   * `before_sleep` does not call `park`, nor does it panic.
+            let park_result = unsafe {
+                parking_lot_core::park(
+                    addr,
+                    validate,
+                    before_sleep,
+                    timed_out,
+                    DEFAULT_PARK_TOKEN,
+                    timeout,
+                )
+            };
+            match park_result {
+                // The count reached zero while we were parked. It may already
+                // have been incremented again by `try_count_up`, so don't
+                // look at the state.
+                ParkResult::Unparked(TOKEN_RELEASED) => return true,
+
+                // Loop back and check whether the count reached zero
+                ParkResult::Unparked(_) | ParkResult::Invalid => (),
+
+                // Timeout expired, unless the count reached zero in the
+                // meantime. The parked bit is left set since other threads
+                // may still be parked.
+                ParkResult::TimedOut => return self.try_wait(),
+            }
+            spinwait.reset();
+            state = self.state.load(Ordering::Acquire);
+        }
+    }
+
+    #[cold]
+    fn release_slow(&self) {
+        // Clear the parked bit. Nothing else can change the state while the
+        // count is zero, except `try_count_up`. This synchronizes with all
+        // the decrements of the count, so that the woken threads can return
+        // without reading the state again.
+        self.state.fetch_and(!PARKED_BIT, Ordering::Acquire);
+
+        // SAFETY: `addr` is an address we control.
+        unsafe {
+            let addr = self as *const _ as usize;
+            parking_lot_core::unpark_all(addr, TOKEN_RELEASED);
+        }
+    }
+}
+
+impl Default for Latch {
+    #[inline]
+    fn default() -> Latch {
+        Latch::new(0)
+    }
+}
+
+impl fmt::Debug for Latch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Latch")
+            .field("count", &self.count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Latch;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn smoke() {
+        let latch = Latch::new(2);
+        assert!(!latch.try_wait());
+        latch.count_down();
+        assert_eq!(latch.count(), 1);
+        latch.count_down();
+        assert!(latch.try_wait());
+        latch.wait();
+
+        // Counting down past zero does nothing
+        latch.count_down();
+        assert_eq!(latch.count(), 0);
+    }
+
+    #[test]
+    fn new_clamps_count() {
+        let latch = Latch::new(!0);
+        assert_eq!(latch.count(), !0 >> 1);
+        assert!(!latch.try_wait());
+    }
+
+    #[test]
+    fn wait_for_timeout() {
+        let latch = Latch::new(1);
+        assert!(latch.wait_for(Duration::from_millis(10)).timed_out());
+        latch.count_down();
+        assert!(!latch.wait_for(Duration::from_millis(10)).timed_out());
+    }
+
+    #[test]
+    fn wakes_all_waiters() {
+        const N: usize = 8;
+        let latch = Arc::new(Latch::new(N));
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let waiters: Vec<_> = (0..4)
+            .map(|_| {
+                let (latch, counter) = (latch.clone(), counter.clone());
+                thread::spawn(move || {
+                    latch.wait();
+                    assert_eq!(counter.load(Ordering::Relaxed), N);
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(10));
+
+        for _ in 0..N {
+            let (latch, counter) = (latch.clone(), counter.clone());
+            thread::spawn(move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+                latch.count_down();
+            });
+        }
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+    }
+}
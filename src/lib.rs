@@ -7,37 +7,115 @@
 
 //! This library provides implementations of `Mutex`, `RwLock`, `Condvar` and
 //! `Once` that are smaller, faster and more flexible than those in the Rust
-//! standard library. It also provides a `ReentrantMutex` type.
+//! standard library. It also provides `ReentrantMutex`, `Semaphore`,
+//! `Barrier`, `Latch`, `WaitGroup`, `ManualResetEvent`, `AutoResetEvent`,
+//! `Parker`, `SeqLock`, `OnceCell` and `Lazy` types.
 
 #![warn(missing_docs)]
 #![warn(rust_2018_idioms)]
 #![cfg_attr(feature = "nightly", feature(asm))]
 
+// Guards which can be sent to another thread may be released by a thread
+// which didn't acquire them, which the per-thread debugging state can't track.
+#[cfg(all(
+    feature = "send_guard",
+    any(
+        feature = "deadlock_detection",
+        feature = "lock_order_validation",
+        feature = "hold_time_warnings",
+        feature = "held_locks"
+    )
+))]
+compile_error!(
+    "the `send_guard` feature is incompatible with `deadlock_detection`, \
+     `lock_order_validation`, `hold_time_warnings` and `held_locks`"
+);
+
+mod async_waiter;
+pub mod atomic;
+mod barrier;
+mod checked_mutex;
+mod checked_rwlock;
 mod condvar;
 mod elision;
+mod event;
+mod latch;
 mod mutex;
 mod once;
+mod once_cell;
 mod raw_mutex;
 mod raw_rwlock;
 mod remutex;
 mod rwlock;
+mod semaphore;
+mod seq_lock;
 mod util;
+mod wait_group;
+
+#[cfg(feature = "lock_stats")]
+pub mod stats;
+#[cfg(not(feature = "lock_stats"))]
+mod stats;
+
+#[cfg(feature = "hold_time_warnings")]
+pub mod hold_time;
+#[cfg(not(feature = "hold_time_warnings"))]
+mod hold_time;
+
+#[cfg(feature = "held_locks")]
+pub mod held_locks;
+#[cfg(not(feature = "held_locks"))]
+mod held_locks;
 
 #[cfg(feature = "deadlock_detection")]
 pub mod deadlock;
 #[cfg(not(feature = "deadlock_detection"))]
 mod deadlock;
 
+#[cfg(feature = "lock_order_validation")]
+pub mod lock_order;
+
+#[cfg(feature = "stall_detection")]
+pub mod stall;
+
+pub use self::barrier::{Barrier, BarrierWaitResult};
+pub use self::checked_mutex::{
+    CheckedMutex, CheckedMutexGuard, MappedCheckedMutexGuard, RawCheckedMutex,
+};
+pub use self::checked_rwlock::{
+    CheckedRwLock, CheckedRwLockReadGuard, CheckedRwLockWriteGuard, RawCheckedRwLock,
+};
 pub use self::condvar::{Condvar, WaitTimeoutResult};
-pub use self::mutex::{MappedMutexGuard, Mutex, MutexGuard};
+pub use self::deadlock::DeadlockError;
+pub use self::event::{AutoResetEvent, ManualResetEvent};
+#[cfg(feature = "held_locks")]
+pub use self::held_locks::{assert_no_locks_held, held_locks};
+pub use self::latch::Latch;
+#[cfg(feature = "arc_lock")]
+pub use self::mutex::{ArcMutexGuard, MappedArcMutexGuard};
+pub use self::mutex::{MappedMutexGuard, Mutex, MutexGuard, MutexLockFuture};
+#[cfg(feature = "poison")]
+pub use self::mutex::{PoisonMutex, PoisonMutexGuard};
 pub use self::once::{Once, OnceState};
+pub use self::once_cell::{Lazy, OnceCell};
 pub use self::raw_mutex::RawMutex;
 pub use self::raw_rwlock::RawRwLock;
 pub use self::remutex::{
     MappedReentrantMutexGuard, RawThreadId, ReentrantMutex, ReentrantMutexGuard,
 };
+#[cfg(feature = "arc_lock")]
+pub use self::rwlock::{
+    ArcRwLockReadGuard, ArcRwLockUpgradableReadGuard, ArcRwLockWriteGuard,
+    MappedArcRwLockReadGuard, MappedArcRwLockWriteGuard,
+};
 pub use self::rwlock::{
-    MappedRwLockReadGuard, MappedRwLockWriteGuard, RwLock, RwLockReadGuard,
-    RwLockUpgradableReadGuard, RwLockWriteGuard,
+    MappedRwLockReadGuard, MappedRwLockWriteGuard, RwLock, RwLockReadFuture, RwLockReadGuard,
+    RwLockUpgradableReadFuture, RwLockUpgradableReadGuard, RwLockWriteFuture, RwLockWriteGuard,
 };
+#[cfg(feature = "poison")]
+pub use self::rwlock::{PoisonRwLock, PoisonRwLockWriteGuard};
+pub use self::semaphore::{Semaphore, SemaphoreGuard};
+pub use self::seq_lock::{SeqLock, SeqLockWriteGuard};
+pub use self::wait_group::WaitGroup;
 pub use ::lock_api;
+pub use parking_lot_core::{ParkTimeoutResult, Parker, Unparker};
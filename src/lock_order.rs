@@ -0,0 +1,207 @@
+//! \[Experimental\] Lock order validation
+//!
+//! This feature is optional and can be enabled via the
+//! `lock_order_validation` feature flag.
+//!
+//! Unlike the deadlock detector, which only finds cycles once threads are
+//! actually stuck, this records the order in which each thread acquires
+//! `Mutex`, `RwLock` and `ReentrantMutex` instances and reports the first time
+//! two locks are acquired in inconsistent orders, even if no deadlock occurred
+//! in that run.
+//!
+//! # Example
+//!
+//! ```
+//! #[cfg(feature = "lock_order_validation")]
+//! { // only for #[cfg]
+//! use parking_lot::{lock_order, Mutex};
+//!
+//! let a = Mutex::new(());
+//! let b = Mutex::new(());
+//! {
+//!     let _a = a.lock();
+//!     let _b = b.lock();
+//! }
+//! {
+//!     let _b = b.lock();
+//!     let _a = a.lock();
+//! }
+//!
+//! for violation in lock_order::check_lock_order() {
+//!     println!("{}", violation);
+//! }
+//! } // only for #[cfg]
+//! ```
+
+pub use parking_lot_core::lock_order::{
+    check_lock_order, set_panic_on_violation, LockOrderViolation,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::LockOrderViolation;
+    use crate::{Mutex, RwLock, RwLockUpgradableReadGuard, RwLockWriteGuard};
+    use std::sync::Arc;
+    use std::thread;
+
+    // We need to serialize these tests since lock order validation uses global
+    // state.
+    lazy_static::lazy_static! {
+        static ref LOCK_ORDER_VALIDATION_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    }
+
+    // Other tests may report violations concurrently, only keep those
+    // involving the given keys.
+    fn check_lock_order(keys: &[usize]) -> Vec<LockOrderViolation> {
+        super::check_lock_order()
+            .into_iter()
+            .filter(|v| keys.contains(&v.first()) && keys.contains(&v.second()))
+            .collect()
+    }
+
+    fn key<T>(lock: &Mutex<T>) -> usize {
+        unsafe { lock.raw() as *const _ as usize }
+    }
+
+    #[test]
+    fn test_consistent_order() {
+        let _guard = LOCK_ORDER_VALIDATION_LOCK.lock();
+        let a = Mutex::new(());
+        let b = Mutex::new(());
+        let c = Mutex::new(());
+        for _ in 0..2 {
+            let _a = a.lock();
+            let _b = b.lock();
+            let _c = c.lock();
+        }
+        // Releasing in a different order doesn't matter.
+        let a_guard = a.lock();
+        let c_guard = c.lock();
+        drop(a_guard);
+        drop(c_guard);
+        assert!(check_lock_order(&[key(&a), key(&b), key(&c)]).is_empty());
+    }
+
+    #[test]
+    fn test_inversion() {
+        let _guard = LOCK_ORDER_VALIDATION_LOCK.lock();
+        let a = Arc::new(Mutex::new(()));
+        let b = Arc::new(Mutex::new(()));
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+
+        // The inversion happens on another thread, without deadlocking.
+        let (a2, b2) = (a.clone(), b.clone());
+        thread::Builder::new()
+            .name("inverter".to_owned())
+            .spawn(move || {
+                let _b = b2.lock();
+                let _a = a2.lock();
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        let violations = check_lock_order(&[key(&a), key(&b)]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].first(), key(&a));
+        assert_eq!(violations[0].second(), key(&b));
+        assert_eq!(violations[0].thread_name(), Some("inverter"));
+        assert!(violations[0]
+            .to_string()
+            .starts_with("lock order violation: lock"));
+
+        // The same inversion is only reported once.
+        {
+            let _b = b.lock();
+            let _a = a.lock();
+        }
+        assert!(check_lock_order(&[key(&a), key(&b)]).is_empty());
+    }
+
+    #[test]
+    fn test_rwlock_reported_by_address() {
+        let _guard = LOCK_ORDER_VALIDATION_LOCK.lock();
+        let rw = RwLock::new(());
+        let m = Mutex::new(());
+        let rw_key = unsafe { rw.raw() as *const _ as usize };
+        {
+            let _rw = rw.read();
+            let _m = m.lock();
+        }
+        {
+            let _m = m.lock();
+            let _rw = rw.read();
+        }
+        let violations: Vec<_> = super::check_lock_order()
+            .into_iter()
+            .filter(|v| v.first() == key(&m) || v.second() == key(&m))
+            .collect();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].first(), rw_key);
+        assert_eq!(violations[0].second(), key(&m));
+    }
+
+    #[test]
+    fn test_rwlock_mode_change() {
+        let _guard = LOCK_ORDER_VALIDATION_LOCK.lock();
+        let rw = RwLock::new(());
+        let m = Mutex::new(());
+        let rw_key = unsafe { rw.raw() as *const _ as usize };
+
+        // The lock stays held across each change of mode, so none of them is
+        // an acquisition after `m`.
+        let upgradable = rw.upgradable_read();
+        let m_guard = m.lock();
+        let write = RwLockUpgradableReadGuard::upgrade(upgradable);
+        let upgradable = RwLockWriteGuard::downgrade_to_upgradable(write);
+        let read = RwLockUpgradableReadGuard::downgrade(upgradable);
+        drop(m_guard);
+        drop(read);
+
+        let write = rw.write();
+        let m_guard = m.lock();
+        let read = RwLockWriteGuard::downgrade(write);
+        drop(m_guard);
+        drop(read);
+
+        assert!(check_lock_order(&[rw_key, key(&m)]).is_empty());
+    }
+
+    #[test]
+    fn test_transitive_inversion() {
+        let _guard = LOCK_ORDER_VALIDATION_LOCK.lock();
+        let a = Mutex::new(());
+        let b = RwLock::new(());
+        let c = Mutex::new(());
+        let b_key = unsafe { b.raw() as *const _ as usize };
+        {
+            let _a = a.lock();
+            let _b = b.read();
+        }
+        {
+            let _b = b.write();
+            let _c = c.lock();
+        }
+        {
+            let _c = c.lock();
+            let _a = a.lock();
+        }
+        let violations = check_lock_order(&[key(&a), b_key, key(&c)]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].first(), key(&a));
+        assert_eq!(violations[0].second(), key(&c));
+
+        // The rwlock is reported by its address as well.
+        {
+            let _b = b.read();
+            let _a = a.lock();
+        }
+        let violations = check_lock_order(&[key(&a), b_key, key(&c)]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].first(), key(&a));
+        assert_eq!(violations[0].second(), b_key);
+    }
+}
@@ -103,13 +103,46 @@ pub type MutexGuard<'a, T> = lock_api::MutexGuard<'a, RawMutex, T>;
 /// thread.
 pub type MappedMutexGuard<'a, T> = lock_api::MappedMutexGuard<'a, RawMutex, T>;
 
+/// A future returned by `Mutex::lock_async` which resolves to a `MutexGuard`
+/// once the mutex has been acquired.
+pub type MutexLockFuture<'a, T> = lock_api::MutexLockFuture<'a, RawMutex, T>;
+
+/// An RAII mutex guard returned by `Mutex::lock_arc`, which owns an `Arc` of
+/// the mutex instead of borrowing it and therefore has a `'static` lifetime.
+///
+/// The guard can only be moved to another thread and unlocked there if the
+/// `send_guard` feature is enabled.
+#[cfg(feature = "arc_lock")]
+pub type ArcMutexGuard<T> = lock_api::ArcMutexGuard<RawMutex, T>;
+
+/// An RAII mutex guard returned by `ArcMutexGuard::map`, which can point to a
+/// subfield of the protected data while keeping the mutex's `Arc` alive.
+#[cfg(feature = "arc_lock")]
+pub type MappedArcMutexGuard<T, U> = lock_api::MappedArcMutexGuard<RawMutex, T, U>;
+
+/// A mutex which is poisoned if a thread panics while holding it, like the
+/// standard library `Mutex`. Its methods return `LockResult` and
+/// `TryLockResult` from `std::sync`.
+#[cfg(feature = "poison")]
+pub type PoisonMutex<T> = lock_api::PoisonMutex<RawMutex, T>;
+
+/// An RAII guard returned by `PoisonMutex::lock`, which poisons the mutex if
+/// it is dropped while the thread is panicking.
+#[cfg(feature = "poison")]
+pub type PoisonMutexGuard<'a, T> = lock_api::PoisonMutexGuard<'a, RawMutex, T>;
+
 #[cfg(test)]
 mod tests {
+    use crate::util::{block_on, thread_waker};
     use crate::{Condvar, Mutex};
+    use std::future::Future;
+    use std::pin::Pin;
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::mpsc::channel;
     use std::sync::Arc;
+    use std::task::{Context, Poll};
     use std::thread;
+    use std::time::Duration;
 
     #[cfg(feature = "serde")]
     use bincode::{deserialize, serialize};
@@ -291,6 +324,218 @@ mod tests {
         assert_eq!(format!("{:?}", mutex), "Mutex { data: <locked> }");
     }
 
+    #[cfg(feature = "arc_lock")]
+    #[test]
+    fn test_arc_lock() {
+        use crate::ArcMutexGuard;
+
+        let mutex = Arc::new(Mutex::new(vec![1, 2, 3]));
+        let guard = mutex.lock_arc();
+        assert!(mutex.try_lock_arc().is_none());
+        let mut mapped = ArcMutexGuard::map(guard, |v| &mut v[1]);
+        *mapped = 20;
+        drop(mapped);
+
+        // The guard keeps the mutex alive after the original `Arc` is gone.
+        let mut guard = mutex.lock_arc();
+        drop(mutex);
+        assert_eq!(*guard, vec![1, 20, 3]);
+        ArcMutexGuard::unlocked(&mut guard, || {});
+        ArcMutexGuard::bump(&mut guard);
+        let mutex = ArcMutexGuard::into_arc(guard);
+        assert_eq!(Arc::strong_count(&mutex), 1);
+        ArcMutexGuard::unlock_fair(mutex.try_lock_arc().unwrap());
+    }
+
+    #[cfg(all(feature = "arc_lock", feature = "send_guard"))]
+    #[test]
+    fn test_arc_lock_send() {
+        let mutex = Arc::new(Mutex::new(0));
+        let mut guard = mutex.lock_arc();
+        *guard += 1;
+
+        // The guard is moved to another thread and the mutex unlocked there
+        thread::spawn(move || *guard += 1).join().unwrap();
+        assert_eq!(*mutex.try_lock().unwrap(), 2);
+    }
+
+    #[cfg(feature = "poison")]
+    #[test]
+    fn test_poison() {
+        use crate::PoisonMutex;
+        use std::panic;
+        use std::sync::TryLockError;
+
+        let mutex = Arc::new(PoisonMutex::new(1));
+        assert!(!mutex.is_poisoned());
+        *mutex.lock().unwrap() += 1;
+
+        let mutex2 = mutex.clone();
+        let _ = thread::spawn(move || {
+            let mut guard = mutex2.lock().unwrap();
+            *guard = 10;
+            panic!();
+        })
+        .join();
+        assert!(mutex.is_poisoned());
+        assert_eq!(
+            format!("{:?}", mutex),
+            "PoisonMutex { data: 10, poisoned: true }"
+        );
+
+        // The lock is still acquired and the data can be repaired.
+        let mut guard = mutex.lock().unwrap_err().into_inner();
+        *guard = 2;
+        match mutex.try_lock() {
+            Err(TryLockError::WouldBlock) => {}
+            _ => panic!("expected WouldBlock"),
+        }
+        drop(guard);
+        match mutex.try_lock() {
+            Err(TryLockError::Poisoned(_)) => {}
+            _ => panic!("expected Poisoned"),
+        }
+        mutex.clear_poison();
+        assert_eq!(*mutex.try_lock().unwrap(), 2);
+
+        // A guard taken while already panicking doesn't poison the mutex.
+        let mutex2 = mutex.clone();
+        let _ = thread::spawn(move || {
+            struct LockOnDrop(Arc<PoisonMutex<i32>>);
+            impl Drop for LockOnDrop {
+                fn drop(&mut self) {
+                    *self.0.lock().unwrap() += 1;
+                }
+            }
+            let _lock_on_drop = LockOnDrop(mutex2);
+            panic!();
+        })
+        .join();
+        assert!(!mutex.is_poisoned());
+
+        let mut mutex = Arc::try_unwrap(mutex).unwrap();
+        assert_eq!(*mutex.get_mut().unwrap(), 3);
+        assert_eq!(mutex.into_inner().unwrap(), 3);
+
+        let mut mutex = PoisonMutex::new(vec![1]);
+        let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _guard = mutex.lock();
+            panic!();
+        }));
+        assert!(mutex.get_mut().is_err());
+        assert_eq!(mutex.into_inner().unwrap_err().into_inner(), vec![1]);
+    }
+
+    #[test]
+    fn test_lock_async() {
+        let m = Arc::new(Mutex::new(0));
+        *block_on(m.lock_async()) += 1;
+
+        // Contend between threads using blocking and asynchronous locking
+        const J: u32 = 1000;
+        const K: u32 = 3;
+        let (tx, rx) = channel();
+        for i in 0..K * 2 {
+            let (tx, m) = (tx.clone(), m.clone());
+            thread::spawn(move || {
+                for _ in 0..J {
+                    if i % 2 == 0 {
+                        *m.lock() += 1;
+                    } else {
+                        *block_on(m.lock_async()) += 1;
+                    }
+                }
+                tx.send(()).unwrap();
+            });
+        }
+        drop(tx);
+        for _ in 0..K * 2 {
+            rx.recv().unwrap();
+        }
+        assert_eq!(*m.lock(), J * K * 2 + 1);
+    }
+
+    #[test]
+    fn test_lock_async_wakeup() {
+        let m = Arc::new(Mutex::new(()));
+        let guard = m.lock();
+        let m2 = m.clone();
+        let t = thread::spawn(move || drop(block_on(m2.lock_async())));
+        thread::sleep(Duration::from_millis(10));
+        drop(guard);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_lock_async_cancel() {
+        let m = Arc::new(Mutex::new(()));
+        let guard = m.lock();
+
+        // Queue a task and then give up on it
+        let waker = thread_waker();
+        let mut future = Box::pin(m.lock_async());
+        assert!(future
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+
+        // A thread queued behind the cancelled task must still get the lock
+        let m2 = m.clone();
+        let t = thread::spawn(move || drop(m2.lock()));
+        drop(future);
+        drop(guard);
+        t.join().unwrap();
+        assert!(m.try_lock().is_some());
+
+        // Cancel after being woken up but before polling again
+        let guard = m.lock();
+        let mut future: Pin<Box<_>> = Box::pin(m.lock_async());
+        assert!(future
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        drop(guard);
+        drop(future);
+        assert!(m.try_lock().is_some());
+    }
+
+    #[test]
+    fn test_lock_async_interleaved_tasks() {
+        let m = Mutex::new(0);
+        let waker = thread_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        // Two tasks polled alternately by this thread, as on a single-threaded
+        // executor. Task A holds the guard across a suspension point.
+        let mut a = Box::pin(m.lock_async());
+        let mut guard = match a.as_mut().poll(&mut cx) {
+            Poll::Ready(guard) => guard,
+            Poll::Pending => panic!("uncontended lock_async returned Pending"),
+        };
+        *guard += 1;
+        let mut b = Box::pin(m.lock_async());
+        assert!(b.as_mut().poll(&mut cx).is_pending());
+
+        // The guard is attributed to the polling thread, not to task A
+        #[cfg(feature = "held_locks")]
+        {
+            let held = crate::held_locks();
+            assert_eq!(held.len(), 1);
+            assert_eq!(held[0].address(), &m as *const _ as usize);
+        }
+
+        // Task A resumes and releases the lock, which hands it to task B
+        *guard += 1;
+        drop(guard);
+        match b.as_mut().poll(&mut cx) {
+            Poll::Ready(mut guard) => *guard *= 10,
+            Poll::Pending => panic!("lock_async not woken after unlock"),
+        }
+        #[cfg(feature = "held_locks")]
+        crate::assert_no_locks_held();
+        assert_eq!(*m.lock(), 20);
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn test_serde() {
@@ -5,12 +5,15 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use crate::deadlock;
 use crate::util::UncheckedOptionExt;
 use core::{
     fmt, mem,
     sync::atomic::{fence, AtomicU8, Ordering},
 };
-use parking_lot_core::{self, SpinWait, DEFAULT_PARK_TOKEN, DEFAULT_UNPARK_TOKEN};
+use parking_lot_core::{
+    self, deadlock::ResourceKind, SpinWait, DEFAULT_PARK_TOKEN, DEFAULT_UNPARK_TOKEN,
+};
 
 const DONE_BIT: u8 = 1;
 const POISON_BIT: u8 = 2;
@@ -163,7 +166,10 @@ impl Once {
         }
 
         let mut f = Some(f);
-        self.call_once_slow(false, &mut |_| unsafe { f.take().unchecked_unwrap()() });
+        self.call_once_slow(false, &mut |_| {
+            unsafe { f.take().unchecked_unwrap()() };
+            true
+        });
     }
 
     /// Performs the same function as `call_once` except ignores poisoning.
@@ -185,8 +191,9 @@ impl Once {
         }
 
         let mut f = Some(f);
-        self.call_once_slow(true, &mut |state| unsafe {
-            f.take().unchecked_unwrap()(state)
+        self.call_once_slow(true, &mut |state| {
+            unsafe { f.take().unchecked_unwrap()(state) };
+            true
         });
     }
 
@@ -201,8 +208,12 @@ impl Once {
     // Finally, this takes an `FnMut` instead of a `FnOnce` because there's
     // currently no way to take an `FnOnce` and call it via virtual dispatch
     // without some allocation overhead.
+    //
+    // The closure returns whether the initialization completed. If it returns
+    // false then the `Once` is reset to its initial state so that another call
+    // can retry the initialization.
     #[cold]
-    fn call_once_slow(&self, ignore_poison: bool, f: &mut dyn FnMut(OnceState)) {
+    pub(crate) fn call_once_slow(&self, ignore_poison: bool, f: &mut dyn FnMut(OnceState) -> bool) {
         let mut spinwait = SpinWait::new();
         let mut state = self.0.load(Ordering::Relaxed);
         loop {
@@ -263,6 +274,7 @@ impl Once {
                 let validate = || self.0.load(Ordering::Relaxed) == LOCKED_BIT | PARKED_BIT;
                 let before_sleep = || {};
                 let timed_out = |_, _| unreachable!();
+                deadlock::set_wait_kind(ResourceKind::Once);
                 parking_lot_core::park(
                     addr,
                     validate,
@@ -283,6 +295,7 @@ impl Once {
             fn drop(&mut self) {
                 // Mark the state as poisoned, unlock it and unpark all threads.
                 let once = self.0;
+                unsafe { deadlock::release_resource(once as *const _ as usize) };
                 let state = once.0.swap(POISON_BIT, Ordering::Release);
                 if state & PARKED_BIT != 0 {
                     unsafe {
@@ -294,18 +307,26 @@ impl Once {
         }
 
         // At this point we have the lock, so run the closure. Make sure we
-        // properly clean up if the closure panicks.
+        // properly clean up if the closure panicks. The thread running the
+        // closure owns the `Once` as far as deadlock detection is concerned.
+        unsafe {
+            deadlock::acquire_resource_with_kind(self as *const _ as usize, ResourceKind::Once)
+        };
         let guard = PanicGuard(self);
         let once_state = if state & POISON_BIT != 0 {
             OnceState::Poisoned
         } else {
             OnceState::New
         };
-        f(once_state);
+        let done = f(once_state);
         mem::forget(guard);
+        unsafe { deadlock::release_resource(self as *const _ as usize) };
 
-        // Now unlock the state, set the done bit and unpark all threads
-        let state = self.0.swap(DONE_BIT, Ordering::Release);
+        // Now unlock the state, set the done bit (unless the closure asked us
+        // to leave the `Once` uninitialized) and unpark all threads
+        let state = self
+            .0
+            .swap(if done { DONE_BIT } else { 0 }, Ordering::Release);
         if state & PARKED_BIT != 0 {
             unsafe {
                 let addr = self as *const _ as usize;
@@ -0,0 +1,467 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::once::Once;
+use crate::util::UncheckedOptionExt;
+use core::{
+    cell::{Cell, UnsafeCell},
+    fmt,
+    mem::MaybeUninit,
+    ops::Deref,
+    ptr,
+};
+
+/// A thread-safe cell which can be written to only once.
+///
+/// This is built on top of `Once`: concurrent initializations are serialized
+/// and the threads which lose the race are parked until the value is ready.
+///
+/// Unlike `Once`, a panicking initializer does not poison the cell: the cell
+/// is left uninitialized and the next call to `get_or_init` will run its own
+/// initializer.
+///
+/// # Examples
+///
+/// ```
+/// use parking_lot::OnceCell;
+///
+/// static CELL: OnceCell<String> = OnceCell::new();
+/// assert!(CELL.get().is_none());
+///
+/// let value: &String = CELL.get_or_init(|| "Hello, World!".to_string());
+/// assert_eq!(value, "Hello, World!");
+/// assert!(CELL.get().is_some());
+/// ```
+pub struct OnceCell<T> {
+    once: Once,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Sharing a `OnceCell` between threads allows a value to be sent through it
+// (`set`) and shared through it (`get`).
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+unsafe impl<T: Send> Send for OnceCell<T> {}
+
+impl<T> OnceCell<T> {
+    /// Creates a new empty cell.
+    #[inline]
+    pub const fn new() -> OnceCell<T> {
+        OnceCell {
+            once: Once::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Gets a reference to the underlying value.
+    ///
+    /// Returns `None` if the cell is empty or is being initialized. This
+    /// method never blocks.
+    #[inline]
+    pub fn get(&self) -> Option<&T> {
+        if self.is_initialized() {
+            Some(unsafe { self.get_unchecked() })
+        } else {
+            None
+        }
+    }
+
+    /// Gets a mutable reference to the underlying value.
+    ///
+    /// Since this call borrows the `OnceCell` mutably, there is no need for
+    /// any synchronization.
+    #[inline]
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if self.is_initialized() {
+            Some(unsafe { &mut *(*self.value.get()).as_mut_ptr() })
+        } else {
+            None
+        }
+    }
+
+    /// Sets the contents of this cell to `value`.
+    ///
+    /// If the cell was already initialized then `value` is returned back as
+    /// an error. This method blocks if another thread is currently
+    /// initializing the cell.
+    #[inline]
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| unsafe { value.take().unchecked_unwrap() });
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty.
+    ///
+    /// Many threads may call `get_or_init` concurrently with different
+    /// initializing functions, but it is guaranteed that only one function
+    /// will be executed. The other threads block until the value is ready.
+    ///
+    /// If `f` panics, the panic is propagated to the caller and the cell
+    /// remains uninitialized.
+    ///
+    /// It is an error to reentrantly initialize the cell from `f`. Doing so
+    /// results in a deadlock.
+    #[inline]
+    pub fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        enum Void {}
+        match self.get_or_try_init(|| Ok::<T, Void>(f())) {
+            Ok(value) => value,
+            Err(void) => match void {},
+        }
+    }
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty. If the cell was empty and `f` failed, an error is returned.
+    ///
+    /// If `f` returns an error or panics, the cell remains uninitialized and
+    /// one of the threads blocked on the initialization (or a later caller)
+    /// will run its own initializer instead.
+    ///
+    /// It is an error to reentrantly initialize the cell from `f`. Doing so
+    /// results in a deadlock.
+    #[inline]
+    pub fn get_or_try_init<F, E>(&self, f: F) -> Result<&T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+
+        let mut f = Some(f);
+        let mut result = Ok(());
+        let value = &self.value;
+        self.once.call_once_slow(
+            true,
+            &mut |_| match unsafe { f.take().unchecked_unwrap()() } {
+                Ok(v) => {
+                    unsafe { (*value.get()).as_mut_ptr().write(v) };
+                    true
+                }
+                Err(e) => {
+                    result = Err(e);
+                    false
+                }
+            },
+        );
+        result?;
+
+        debug_assert!(self.is_initialized());
+        Ok(unsafe { self.get_unchecked() })
+    }
+
+    /// Takes the value out of this cell, moving it back to an uninitialized
+    /// state.
+    ///
+    /// Since this call borrows the `OnceCell` mutably, there is no need for
+    /// any synchronization.
+    #[inline]
+    pub fn take(&mut self) -> Option<T> {
+        if self.is_initialized() {
+            self.once = Once::new();
+            Some(unsafe { ptr::read((*self.value.get()).as_ptr()) })
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the cell, returning the wrapped value.
+    ///
+    /// Returns `None` if the cell was empty.
+    #[inline]
+    pub fn into_inner(mut self) -> Option<T> {
+        self.take()
+    }
+
+    #[inline]
+    fn is_initialized(&self) -> bool {
+        self.once.state().done()
+    }
+
+    #[inline]
+    unsafe fn get_unchecked(&self) -> &T {
+        &*(*self.value.get()).as_ptr()
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.is_initialized() {
+            unsafe { ptr::drop_in_place((*self.value.get()).as_mut_ptr()) };
+        }
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    #[inline]
+    fn default() -> OnceCell<T> {
+        OnceCell::new()
+    }
+}
+
+impl<T> From<T> for OnceCell<T> {
+    #[inline]
+    fn from(value: T) -> OnceCell<T> {
+        let cell = OnceCell::new();
+        let _ = cell.set(value);
+        cell
+    }
+}
+
+impl<T: Clone> Clone for OnceCell<T> {
+    #[inline]
+    fn clone(&self) -> OnceCell<T> {
+        match self.get() {
+            Some(value) => OnceCell::from(value.clone()),
+            None => OnceCell::new(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_struct("OnceCell").field("value", value).finish(),
+            None => {
+                struct UninitPlaceholder;
+                impl fmt::Debug for UninitPlaceholder {
+                    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                        f.write_str("<uninit>")
+                    }
+                }
+
+                f.debug_struct("OnceCell")
+                    .field("value", &UninitPlaceholder)
+                    .finish()
+            }
+        }
+    }
+}
+
+/// A value which is initialized on the first access.
+///
+/// The initialization function is run by the first thread to dereference the
+/// `Lazy`, while other threads accessing it concurrently block until the
+/// value is ready. If the initialization function panics then the `Lazy` is
+/// poisoned and all further accesses will panic as well.
+///
+/// # Examples
+///
+/// ```
+/// use parking_lot::Lazy;
+/// use std::collections::HashMap;
+///
+/// static MAP: Lazy<HashMap<u32, &'static str>> = Lazy::new(|| {
+///     let mut map = HashMap::new();
+///     map.insert(13, "Spica");
+///     map.insert(74, "Hoyten");
+///     map
+/// });
+///
+/// assert_eq!(MAP.get(&13), Some(&"Spica"));
+/// ```
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: Cell<Option<F>>,
+}
+
+// The initialization function is only ever called by one thread, and the
+// `OnceCell` guarantees that access to `init` is serialized.
+unsafe impl<T, F: Send> Sync for Lazy<T, F> where OnceCell<T>: Sync {}
+
+impl<T, F> Lazy<T, F> {
+    /// Creates a new lazy value with the given initializing function.
+    #[inline]
+    pub const fn new(f: F) -> Lazy<T, F> {
+        Lazy {
+            cell: OnceCell::new(),
+            init: Cell::new(Some(f)),
+        }
+    }
+
+    /// Consumes this `Lazy` returning the stored value.
+    ///
+    /// Returns `Err(f)` with the initializing function if the value has not
+    /// been initialized yet.
+    #[inline]
+    pub fn into_value(this: Lazy<T, F>) -> Result<T, F> {
+        let Lazy { cell, init } = this;
+        cell.into_inner().ok_or_else(|| {
+            init.into_inner()
+                .unwrap_or_else(|| panic!("Lazy instance has previously been poisoned"))
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    /// Forces the evaluation of this lazy value and returns a reference to
+    /// the result.
+    ///
+    /// This is equivalent to the `Deref` impl, but is explicit.
+    #[inline]
+    pub fn force(this: &Lazy<T, F>) -> &T {
+        this.cell.get_or_init(|| match this.init.take() {
+            Some(f) => f(),
+            None => panic!("Lazy instance has previously been poisoned"),
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+impl<T: Default> Default for Lazy<T> {
+    #[inline]
+    fn default() -> Lazy<T> {
+        Lazy::new(T::default)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lazy").field("cell", &self.cell).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Lazy, OnceCell};
+    use std::panic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    #[test]
+    fn smoke_once_cell() {
+        let mut cell = OnceCell::new();
+        assert_eq!(cell.get(), None);
+        assert_eq!(*cell.get_or_init(|| 1), 1);
+        assert_eq!(*cell.get_or_init(|| 2), 1);
+        assert_eq!(cell.set(3), Err(3));
+        *cell.get_mut().unwrap() = 4;
+        assert_eq!(cell.take(), Some(4));
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.set(5), Ok(()));
+        assert_eq!(cell.into_inner(), Some(5));
+    }
+
+    #[test]
+    fn stampede_once_cell() {
+        static CELL: OnceCell<usize> = OnceCell::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let (tx, rx) = channel();
+        for i in 0..10 {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for _ in 0..4 {
+                    thread::yield_now()
+                }
+                let value = *CELL.get_or_init(|| {
+                    CALLS.fetch_add(1, Ordering::Relaxed);
+                    i
+                });
+                tx.send(value).unwrap();
+            });
+        }
+
+        let first = rx.recv().unwrap();
+        for _ in 0..9 {
+            assert_eq!(rx.recv().unwrap(), first);
+        }
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn get_or_try_init_retries() {
+        let cell = OnceCell::new();
+        assert_eq!(cell.get_or_try_init(|| Err(())), Err(()));
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.get_or_try_init(|| Ok::<_, ()>(1)), Ok(&1));
+        assert_eq!(cell.get_or_try_init(|| Err(())), Ok(&1));
+    }
+
+    #[test]
+    fn panicking_init_is_retried() {
+        static CELL: OnceCell<usize> = OnceCell::new();
+
+        let t = panic::catch_unwind(|| {
+            CELL.get_or_init(|| panic!());
+        });
+        assert!(t.is_err());
+        assert_eq!(CELL.get(), None);
+        assert_eq!(*CELL.get_or_init(|| 1), 1);
+    }
+
+    #[test]
+    fn drop_once_cell() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Dropper;
+        impl Drop for Dropper {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let cell = OnceCell::new();
+        cell.get_or_init(|| Dropper);
+        drop(OnceCell::<Dropper>::new());
+        assert_eq!(DROPS.load(Ordering::Relaxed), 0);
+        drop(cell);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn smoke_lazy() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static LAZY: Lazy<usize> = Lazy::new(|| {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            92
+        });
+
+        assert_eq!(*LAZY, 92);
+        assert_eq!(*Lazy::force(&LAZY), 92);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+
+        let lazy: Lazy<i32, _> = Lazy::new(|| 1);
+        let lazy = match Lazy::into_value(lazy) {
+            Ok(_) => panic!(),
+            Err(f) => Lazy::new(f),
+        };
+        assert_eq!(*lazy, 1);
+        assert_eq!(Lazy::into_value(lazy).ok(), Some(1));
+    }
+
+    #[test]
+    fn poison_lazy() {
+        let lazy: Lazy<usize> = Lazy::new(|| panic!());
+        assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| *lazy)).is_err());
+        assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| *lazy)).is_err());
+    }
+
+    #[test]
+    fn test_once_cell_debug() {
+        let cell = OnceCell::new();
+        assert_eq!(format!("{:?}", cell), "OnceCell { value: <uninit> }");
+        cell.set(1).unwrap();
+        assert_eq!(format!("{:?}", cell), "OnceCell { value: 1 }");
+    }
+}
@@ -5,13 +5,22 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
-use crate::{deadlock, util};
+use crate::deadlock::DeadlockError;
+use crate::{async_waiter::AsyncWaiter, deadlock, held_locks, hold_time, stats, util};
 use core::{
     sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
     time::Duration,
 };
-use lock_api::{GuardNoSend, RawMutex as RawMutex_};
-use parking_lot_core::{self, ParkResult, SpinWait, UnparkResult, UnparkToken, DEFAULT_PARK_TOKEN};
+#[cfg(not(feature = "send_guard"))]
+use lock_api::GuardNoSend;
+#[cfg(feature = "send_guard")]
+use lock_api::GuardSend;
+use lock_api::RawMutex as RawMutex_;
+use parking_lot_core::{
+    self, deadlock::ResourceKind, ParkResult, SpinWait, UnparkResult, UnparkToken,
+    DEFAULT_PARK_TOKEN,
+};
 use std::time::Instant;
 
 // UnparkToken used to indicate that that the target thread should attempt to
@@ -60,7 +69,10 @@ unsafe impl lock_api::RawMutex for RawMutex {
         state: AtomicU8::new(0),
     };
 
+    #[cfg(not(feature = "send_guard"))]
     type GuardMarker = GuardNoSend;
+    #[cfg(feature = "send_guard")]
+    type GuardMarker = GuardSend;
 
     #[inline]
     fn lock(&self) {
@@ -71,7 +83,7 @@ unsafe impl lock_api::RawMutex for RawMutex {
         {
             self.lock_slow(None);
         }
-        unsafe { deadlock::acquire_resource(self as *const _ as usize) };
+        self.on_acquire();
     }
 
     #[inline]
@@ -88,7 +100,7 @@ unsafe impl lock_api::RawMutex for RawMutex {
                 Ordering::Relaxed,
             ) {
                 Ok(_) => {
-                    unsafe { deadlock::acquire_resource(self as *const _ as usize) };
+                    self.on_acquire();
                     return true;
                 }
                 Err(x) => state = x,
@@ -98,7 +110,7 @@ unsafe impl lock_api::RawMutex for RawMutex {
 
     #[inline]
     fn unlock(&self) {
-        unsafe { deadlock::release_resource(self as *const _ as usize) };
+        let _hold = self.on_release();
         if self
             .state
             .compare_exchange(LOCKED_BIT, 0, Ordering::Release, Ordering::Relaxed)
@@ -113,7 +125,7 @@ unsafe impl lock_api::RawMutex for RawMutex {
 unsafe impl lock_api::RawMutexFair for RawMutex {
     #[inline]
     fn unlock_fair(&self) {
-        unsafe { deadlock::release_resource(self as *const _ as usize) };
+        let _hold = self.on_release();
         if self
             .state
             .compare_exchange(LOCKED_BIT, 0, Ordering::Release, Ordering::Relaxed)
@@ -148,7 +160,7 @@ unsafe impl lock_api::RawMutexTimed for RawMutex {
             self.lock_slow(Some(timeout))
         };
         if result {
-            unsafe { deadlock::acquire_resource(self as *const _ as usize) };
+            self.on_acquire();
         }
         result
     }
@@ -165,13 +177,81 @@ unsafe impl lock_api::RawMutexTimed for RawMutex {
             self.lock_slow(util::to_deadline(timeout))
         };
         if result {
-            unsafe { deadlock::acquire_resource(self as *const _ as usize) };
+            self.on_acquire();
         }
         result
     }
 }
 
+unsafe impl lock_api::RawMutexDeadlock for RawMutex {
+    type DeadlockError = DeadlockError;
+
+    #[inline]
+    fn lock_or_deadlock(&self) -> Result<(), DeadlockError> {
+        if self
+            .state
+            .compare_exchange_weak(0, LOCKED_BIT, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            deadlock::lock_or_deadlock(|| self.lock_slow(None))?;
+        }
+        self.on_acquire();
+        Ok(())
+    }
+}
+
+unsafe impl lock_api::RawMutexAsync for RawMutex {
+    type Waiter = AsyncWaiter;
+
+    #[inline]
+    unsafe fn poll_lock(&self, waiter: &mut AsyncWaiter, cx: &mut Context<'_>) -> Poll<()> {
+        let result = if !waiter.is_parked()
+            && self
+                .state
+                .compare_exchange_weak(0, LOCKED_BIT, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        {
+            Poll::Ready(())
+        } else {
+            self.poll_lock_slow(waiter, cx)
+        };
+        if result.is_ready() {
+            self.on_acquire();
+        }
+        result
+    }
+
+    #[inline]
+    unsafe fn cancel_lock(&self, waiter: &mut AsyncWaiter) {
+        if waiter.is_parked() {
+            self.cancel_lock_slow(waiter);
+        }
+    }
+}
+
 impl RawMutex {
+    // Records an acquisition of the mutex with all the instrumentation which
+    // is enabled.
+    #[inline]
+    pub(crate) fn on_acquire(&self) {
+        let addr = self as *const _ as usize;
+        unsafe { deadlock::acquire_resource_with_kind(addr, ResourceKind::Mutex) };
+        stats::acquire(addr, true);
+        hold_time::acquire(addr);
+        held_locks::acquire(addr, ResourceKind::Mutex);
+    }
+
+    // Records the release of the mutex. Must be called before the mutex is
+    // released, and the result dropped after it.
+    #[inline]
+    pub(crate) fn on_release(&self) -> hold_time::Release {
+        let addr = self as *const _ as usize;
+        unsafe { deadlock::release_resource(addr) };
+        held_locks::release(addr);
+        stats::release(addr);
+        hold_time::release(addr)
+    }
+
     // Used by Condvar when requeuing threads to us, must be called while
     // holding the queue lock.
     #[inline]
@@ -202,6 +282,8 @@ impl RawMutex {
 
     #[cold]
     fn lock_slow(&self, timeout: Option<Instant>) -> bool {
+        let addr = self as *const _ as usize;
+        let mut contention = stats::Contention::new();
         let mut spinwait = SpinWait::new();
         let mut state = self.state.load(Ordering::Relaxed);
         loop {
@@ -213,7 +295,10 @@ impl RawMutex {
                     Ordering::Acquire,
                     Ordering::Relaxed,
                 ) {
-                    Ok(_) => return true,
+                    Ok(_) => {
+                        contention.finish(addr, true);
+                        return true;
+                    }
                     Err(x) => state = x,
                 }
                 continue;
@@ -221,6 +306,7 @@ impl RawMutex {
 
             // If there is no queue, try spinning a few times
             if state & PARKED_BIT == 0 && spinwait.spin() {
+                contention.spin();
                 state = self.state.load(Ordering::Relaxed);
                 continue;
             }
@@ -239,7 +325,6 @@ impl RawMutex {
             }
 
             // Park our thread until we are woken up by an unlock
-            let addr = self as *const _ as usize;
             let validate = || self.state.load(Ordering::Relaxed) == LOCKED_BIT | PARKED_BIT;
             let before_sleep = || {};
             let timed_out = |_, was_last_thread| {
@@ -252,6 +337,8 @@ impl RawMutex {
             //   * `addr` is an address we control.
             //   * `validate`/`timed_out` does not panic or call into any function of `parking_lot`.
             //   * `before_sleep` does not call `park`, nor does it panic.
+            contention.park();
+            deadlock::set_wait_kind(ResourceKind::Mutex);
             match unsafe {
                 parking_lot_core::park(
                     addr,
@@ -264,7 +351,10 @@ impl RawMutex {
             } {
                 // The thread that unparked us passed the lock on to us
                 // directly without unlocking it.
-                ParkResult::Unparked(TOKEN_HANDOFF) => return true,
+                ParkResult::Unparked(TOKEN_HANDOFF) => {
+                    contention.finish(addr, true);
+                    return true;
+                }
 
                 // We were unparked normally, try acquiring the lock again
                 ParkResult::Unparked(_) => (),
@@ -273,7 +363,10 @@ impl RawMutex {
                 ParkResult::Invalid => (),
 
                 // Timeout expired
-                ParkResult::TimedOut => return false,
+                ParkResult::TimedOut => {
+                    contention.finish(addr, false);
+                    return false;
+                }
             }
 
             // Loop back and try locking again
@@ -282,6 +375,98 @@ impl RawMutex {
         }
     }
 
+    // Asynchronous version of lock_slow: the task is queued with its waker
+    // instead of parking the thread, and there is no spinning.
+    #[cold]
+    fn poll_lock_slow(&self, waiter: &mut AsyncWaiter, cx: &mut Context<'_>) -> Poll<()> {
+        let addr = self as *const _ as usize;
+
+        // If we were already queued, check whether we have been unparked
+        if waiter.is_parked() {
+            // SAFETY: `addr` is an address we control.
+            match unsafe { waiter.park(addr, || true, cx, DEFAULT_PARK_TOKEN) } {
+                Poll::Pending => return Poll::Pending,
+
+                // The thread that unparked us passed the lock on to us
+                // directly without unlocking it.
+                Poll::Ready(ParkResult::Unparked(TOKEN_HANDOFF)) => return Poll::Ready(()),
+
+                // We were unparked normally, try acquiring the lock again
+                Poll::Ready(_) => (),
+            }
+        }
+
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            // Grab the lock if it isn't locked, even if there is a queue on it
+            if state & LOCKED_BIT == 0 {
+                match self.state.compare_exchange_weak(
+                    state,
+                    state | LOCKED_BIT,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return Poll::Ready(()),
+                    Err(x) => state = x,
+                }
+                continue;
+            }
+
+            // Set the parked bit
+            if state & PARKED_BIT == 0 {
+                if let Err(x) = self.state.compare_exchange_weak(
+                    state,
+                    state | PARKED_BIT,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    state = x;
+                    continue;
+                }
+            }
+
+            // Queue our task until we are woken up by an unlock
+            let validate = || self.state.load(Ordering::Relaxed) == LOCKED_BIT | PARKED_BIT;
+            // SAFETY:
+            //   * `addr` is an address we control.
+            //   * `validate` does not panic or call into any function of `parking_lot`.
+            match unsafe { waiter.park(addr, validate, cx, DEFAULT_PARK_TOKEN) } {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(ParkResult::Unparked(TOKEN_HANDOFF)) => return Poll::Ready(()),
+
+                // The validation function failed, try locking again
+                Poll::Ready(_) => (),
+            }
+            state = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    #[cold]
+    fn cancel_lock_slow(&self, waiter: &mut AsyncWaiter) {
+        let cancelled = |_, was_last_thread| {
+            // Clear the parked bit if we were the last parked thread
+            if was_last_thread {
+                self.state.fetch_and(!PARKED_BIT, Ordering::Relaxed);
+            }
+        };
+        // SAFETY: `cancelled` does not panic or call into any function of `parking_lot`.
+        match unsafe { waiter.cancel(cancelled) } {
+            // We were still queued and have been removed
+            None => (),
+
+            // The lock was handed off to us, release it again
+            Some(TOKEN_HANDOFF) => {
+                self.on_acquire();
+                self.unlock();
+            }
+
+            // We were unparked normally to retry acquiring the lock. Pass that
+            // on to the next waiter so that it doesn't remain parked.
+            Some(_) if self.try_lock() => self.unlock(),
+            Some(_) => (),
+        }
+    }
+
     #[cold]
     fn unlock_slow(&self, force_fair: bool) {
         // Unpark one thread and leave the parked bit set if there might
@@ -311,15 +496,19 @@ impl RawMutex {
         // SAFETY:
         //   * `addr` is an address we control.
         //   * `callback` does not panic or call into any function of `parking_lot`.
-        unsafe {
-            parking_lot_core::unpark_one(addr, callback);
+        let result = unsafe { parking_lot_core::unpark_one(addr, callback) };
+        if result.unparked_threads != 0 && (force_fair || result.be_fair) {
+            stats::fair_handoff(addr);
         }
     }
 
     #[cold]
     fn bump_slow(&self) {
-        unsafe { deadlock::release_resource(self as *const _ as usize) };
-        self.unlock_slow(true);
+        {
+            // Report the hold before locking again
+            let _hold = self.on_release();
+            self.unlock_slow(true);
+        }
         self.lock();
     }
 }
@@ -5,16 +5,25 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use crate::async_waiter::AsyncWaiter;
+use crate::deadlock::{lock_or_deadlock, DeadlockError};
 use crate::elision::{have_elision, AtomicElisionExt};
 use crate::raw_mutex::{TOKEN_HANDOFF, TOKEN_NORMAL};
-use crate::util;
+use crate::{held_locks, hold_time, stats, util};
 use core::{
     cell::Cell,
     sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
 };
-use lock_api::{GuardNoSend, RawRwLock as RawRwLock_, RawRwLockUpgrade};
+#[cfg(not(feature = "send_guard"))]
+use lock_api::GuardNoSend;
+#[cfg(feature = "send_guard")]
+use lock_api::GuardSend;
+use lock_api::{RawRwLock as RawRwLock_, RawRwLockUpgrade};
 use parking_lot_core::{
-    self, deadlock, FilterOp, ParkResult, ParkToken, SpinWait, UnparkResult, UnparkToken,
+    self,
+    deadlock::{self, Resource, ResourceKind},
+    FilterOp, ParkResult, ParkToken, SpinWait, UnparkResult, UnparkToken,
 };
 use std::time::{Duration, Instant};
 
@@ -61,7 +70,10 @@ unsafe impl lock_api::RawRwLock for RawRwLock {
         state: AtomicUsize::new(0),
     };
 
+    #[cfg(not(feature = "send_guard"))]
     type GuardMarker = GuardNoSend;
+    #[cfg(feature = "send_guard")]
+    type GuardMarker = GuardSend;
 
     #[inline]
     fn lock_exclusive(&self) {
@@ -73,7 +85,7 @@ unsafe impl lock_api::RawRwLock for RawRwLock {
             let result = self.lock_exclusive_slow(None);
             debug_assert!(result);
         }
-        self.deadlock_acquire();
+        self.on_acquire(ResourceKind::RwLockExclusive);
     }
 
     #[inline]
@@ -83,7 +95,7 @@ unsafe impl lock_api::RawRwLock for RawRwLock {
             .compare_exchange(0, WRITER_BIT, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockExclusive);
             true
         } else {
             false
@@ -92,7 +104,7 @@ unsafe impl lock_api::RawRwLock for RawRwLock {
 
     #[inline]
     fn unlock_exclusive(&self) {
-        self.deadlock_release();
+        let _hold = self.on_release(ResourceKind::RwLockExclusive);
         if self
             .state
             .compare_exchange(WRITER_BIT, 0, Ordering::Release, Ordering::Relaxed)
@@ -109,7 +121,7 @@ unsafe impl lock_api::RawRwLock for RawRwLock {
             let result = self.lock_shared_slow(false, None);
             debug_assert!(result);
         }
-        self.deadlock_acquire();
+        self.on_acquire(ResourceKind::RwLockShared);
     }
 
     #[inline]
@@ -120,14 +132,14 @@ unsafe impl lock_api::RawRwLock for RawRwLock {
             self.try_lock_shared_slow(false)
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockShared);
         }
         result
     }
 
     #[inline]
     fn unlock_shared(&self) {
-        self.deadlock_release();
+        self.on_release(ResourceKind::RwLockShared);
         let state = if have_elision() {
             self.state.elision_fetch_sub_release(ONE_READER)
         } else {
@@ -148,7 +160,7 @@ unsafe impl lock_api::RawRwLockFair for RawRwLock {
 
     #[inline]
     fn unlock_exclusive_fair(&self) {
-        self.deadlock_release();
+        let _hold = self.on_release(ResourceKind::RwLockExclusive);
         if self
             .state
             .compare_exchange(WRITER_BIT, 0, Ordering::Release, Ordering::Relaxed)
@@ -179,6 +191,7 @@ unsafe impl lock_api::RawRwLockFair for RawRwLock {
 unsafe impl lock_api::RawRwLockDowngrade for RawRwLock {
     #[inline]
     fn downgrade(&self) {
+        let _hold = self.on_change(ResourceKind::RwLockExclusive, ResourceKind::RwLockShared);
         let state = self
             .state
             .fetch_add(ONE_READER - WRITER_BIT, Ordering::Release);
@@ -202,7 +215,7 @@ unsafe impl lock_api::RawRwLockTimed for RawRwLock {
             self.lock_shared_slow(false, util::to_deadline(timeout))
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockShared);
         }
         result
     }
@@ -215,7 +228,7 @@ unsafe impl lock_api::RawRwLockTimed for RawRwLock {
             self.lock_shared_slow(false, Some(timeout))
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockShared);
         }
         result
     }
@@ -232,7 +245,7 @@ unsafe impl lock_api::RawRwLockTimed for RawRwLock {
             self.lock_exclusive_slow(util::to_deadline(timeout))
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockExclusive);
         }
         result
     }
@@ -249,12 +262,38 @@ unsafe impl lock_api::RawRwLockTimed for RawRwLock {
             self.lock_exclusive_slow(Some(timeout))
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockExclusive);
         }
         result
     }
 }
 
+unsafe impl lock_api::RawRwLockDeadlock for RawRwLock {
+    type DeadlockError = DeadlockError;
+
+    #[inline]
+    fn lock_shared_or_deadlock(&self) -> Result<(), DeadlockError> {
+        if !self.try_lock_shared_fast(false) {
+            lock_or_deadlock(|| self.lock_shared_slow(false, None))?;
+        }
+        self.on_acquire(ResourceKind::RwLockShared);
+        Ok(())
+    }
+
+    #[inline]
+    fn lock_exclusive_or_deadlock(&self) -> Result<(), DeadlockError> {
+        if self
+            .state
+            .compare_exchange_weak(0, WRITER_BIT, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            lock_or_deadlock(|| self.lock_exclusive_slow(None))?;
+        }
+        self.on_acquire(ResourceKind::RwLockExclusive);
+        Ok(())
+    }
+}
+
 unsafe impl lock_api::RawRwLockRecursive for RawRwLock {
     #[inline]
     fn lock_shared_recursive(&self) {
@@ -262,7 +301,7 @@ unsafe impl lock_api::RawRwLockRecursive for RawRwLock {
             let result = self.lock_shared_slow(true, None);
             debug_assert!(result);
         }
-        self.deadlock_acquire();
+        self.on_acquire(ResourceKind::RwLockShared);
     }
 
     #[inline]
@@ -273,7 +312,7 @@ unsafe impl lock_api::RawRwLockRecursive for RawRwLock {
             self.try_lock_shared_slow(true)
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockShared);
         }
         result
     }
@@ -288,7 +327,7 @@ unsafe impl lock_api::RawRwLockRecursiveTimed for RawRwLock {
             self.lock_shared_slow(true, util::to_deadline(timeout))
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockShared);
         }
         result
     }
@@ -301,7 +340,7 @@ unsafe impl lock_api::RawRwLockRecursiveTimed for RawRwLock {
             self.lock_shared_slow(true, Some(timeout))
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockShared);
         }
         result
     }
@@ -314,7 +353,7 @@ unsafe impl lock_api::RawRwLockUpgrade for RawRwLock {
             let result = self.lock_upgradable_slow(None);
             debug_assert!(result);
         }
-        self.deadlock_acquire();
+        self.on_acquire(ResourceKind::RwLockUpgradable);
     }
 
     #[inline]
@@ -325,14 +364,14 @@ unsafe impl lock_api::RawRwLockUpgrade for RawRwLock {
             self.try_lock_upgradable_slow()
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockUpgradable);
         }
         result
     }
 
     #[inline]
     fn unlock_upgradable(&self) {
-        self.deadlock_release();
+        let _hold = self.on_release(ResourceKind::RwLockUpgradable);
         let state = self.state.load(Ordering::Relaxed);
         if state & PARKED_BIT == 0 {
             if self
@@ -361,11 +400,15 @@ unsafe impl lock_api::RawRwLockUpgrade for RawRwLock {
             let result = self.upgrade_slow(None);
             debug_assert!(result);
         }
+        self.on_change(
+            ResourceKind::RwLockUpgradable,
+            ResourceKind::RwLockExclusive,
+        );
     }
 
     #[inline]
     fn try_upgrade(&self) -> bool {
-        if self
+        let result = self
             .state
             .compare_exchange_weak(
                 ONE_READER | UPGRADABLE_BIT,
@@ -374,18 +417,21 @@ unsafe impl lock_api::RawRwLockUpgrade for RawRwLock {
                 Ordering::Relaxed,
             )
             .is_ok()
-        {
-            true
-        } else {
-            self.try_upgrade_slow()
+            || self.try_upgrade_slow();
+        if result {
+            self.on_change(
+                ResourceKind::RwLockUpgradable,
+                ResourceKind::RwLockExclusive,
+            );
         }
+        result
     }
 }
 
 unsafe impl lock_api::RawRwLockUpgradeFair for RawRwLock {
     #[inline]
     fn unlock_upgradable_fair(&self) {
-        self.deadlock_release();
+        let _hold = self.on_release(ResourceKind::RwLockUpgradable);
         let state = self.state.load(Ordering::Relaxed);
         if state & PARKED_BIT == 0 {
             if self
@@ -415,6 +461,7 @@ unsafe impl lock_api::RawRwLockUpgradeFair for RawRwLock {
 unsafe impl lock_api::RawRwLockUpgradeDowngrade for RawRwLock {
     #[inline]
     fn downgrade_upgradable(&self) {
+        let _hold = self.on_change(ResourceKind::RwLockUpgradable, ResourceKind::RwLockShared);
         let state = self.state.fetch_sub(UPGRADABLE_BIT, Ordering::Relaxed);
 
         // Wake up parked upgradable threads if there are any
@@ -425,6 +472,10 @@ unsafe impl lock_api::RawRwLockUpgradeDowngrade for RawRwLock {
 
     #[inline]
     fn downgrade_to_upgradable(&self) {
+        let _hold = self.on_change(
+            ResourceKind::RwLockExclusive,
+            ResourceKind::RwLockUpgradable,
+        );
         let state = self.state.fetch_add(
             (ONE_READER | UPGRADABLE_BIT) - WRITER_BIT,
             Ordering::Release,
@@ -446,7 +497,7 @@ unsafe impl lock_api::RawRwLockUpgradeTimed for RawRwLock {
             self.lock_upgradable_slow(Some(timeout))
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockUpgradable);
         }
         result
     }
@@ -459,7 +510,7 @@ unsafe impl lock_api::RawRwLockUpgradeTimed for RawRwLock {
             self.lock_upgradable_slow(util::to_deadline(timeout))
         };
         if result {
-            self.deadlock_acquire();
+            self.on_acquire(ResourceKind::RwLockUpgradable);
         }
         result
     }
@@ -470,11 +521,14 @@ unsafe impl lock_api::RawRwLockUpgradeTimed for RawRwLock {
             (ONE_READER | UPGRADABLE_BIT) - WRITER_BIT,
             Ordering::Relaxed,
         );
-        if state & READERS_MASK == ONE_READER {
-            true
-        } else {
-            self.upgrade_slow(Some(timeout))
+        let result = state & READERS_MASK == ONE_READER || self.upgrade_slow(Some(timeout));
+        if result {
+            self.on_change(
+                ResourceKind::RwLockUpgradable,
+                ResourceKind::RwLockExclusive,
+            );
         }
+        result
     }
 
     #[inline]
@@ -483,10 +537,136 @@ unsafe impl lock_api::RawRwLockUpgradeTimed for RawRwLock {
             (ONE_READER | UPGRADABLE_BIT) - WRITER_BIT,
             Ordering::Relaxed,
         );
-        if state & READERS_MASK == ONE_READER {
-            true
+        let result =
+            state & READERS_MASK == ONE_READER || self.upgrade_slow(util::to_deadline(timeout));
+        if result {
+            self.on_change(
+                ResourceKind::RwLockUpgradable,
+                ResourceKind::RwLockExclusive,
+            );
+        }
+        result
+    }
+}
+
+unsafe impl lock_api::RawRwLockAsync for RawRwLock {
+    type Waiter = AsyncWaiter;
+
+    #[inline]
+    unsafe fn poll_lock_shared(&self, waiter: &mut AsyncWaiter, cx: &mut Context<'_>) -> Poll<()> {
+        let result = if !waiter.is_parked() && self.try_lock_shared_fast(false) {
+            Poll::Ready(())
         } else {
-            self.upgrade_slow(util::to_deadline(timeout))
+            let try_lock = |state: &mut usize| {
+                if self.try_lock_shared_slow(false) {
+                    return true;
+                }
+                *state = self.state.load(Ordering::Relaxed);
+                false
+            };
+            self.poll_lock_common(waiter, cx, TOKEN_SHARED, try_lock, WRITER_BIT)
+        };
+        if result.is_ready() {
+            self.on_acquire(ResourceKind::RwLockShared);
+        }
+        result
+    }
+
+    #[inline]
+    unsafe fn cancel_lock_shared(&self, waiter: &mut AsyncWaiter) {
+        if waiter.is_parked() {
+            self.cancel_lock_common(waiter, TOKEN_SHARED);
+        }
+    }
+
+    #[inline]
+    unsafe fn poll_lock_exclusive(
+        &self,
+        waiter: &mut AsyncWaiter,
+        cx: &mut Context<'_>,
+    ) -> Poll<()> {
+        if !waiter.waiting_for_readers {
+            if !waiter.is_parked()
+                && self
+                    .state
+                    .compare_exchange_weak(0, WRITER_BIT, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                self.on_acquire(ResourceKind::RwLockExclusive);
+                return Poll::Ready(());
+            }
+
+            // Step 1: grab exclusive ownership of WRITER_BIT
+            let try_lock = |state: &mut usize| self.try_lock_writer_bit(state);
+            if self
+                .poll_lock_common(
+                    waiter,
+                    cx,
+                    TOKEN_EXCLUSIVE,
+                    try_lock,
+                    WRITER_BIT | UPGRADABLE_BIT,
+                )
+                .is_pending()
+            {
+                return Poll::Pending;
+            }
+            waiter.waiting_for_readers = true;
+        }
+
+        // Step 2: wait for all remaining readers to exit the lock.
+        if self.poll_wait_for_readers(waiter, cx).is_pending() {
+            return Poll::Pending;
+        }
+        waiter.waiting_for_readers = false;
+        self.on_acquire(ResourceKind::RwLockExclusive);
+        Poll::Ready(())
+    }
+
+    #[inline]
+    unsafe fn cancel_lock_exclusive(&self, waiter: &mut AsyncWaiter) {
+        if waiter.waiting_for_readers {
+            self.cancel_wait_for_readers(waiter);
+        } else if waiter.is_parked() {
+            self.cancel_lock_common(waiter, TOKEN_EXCLUSIVE);
+        }
+    }
+}
+
+unsafe impl lock_api::RawRwLockUpgradeAsync for RawRwLock {
+    #[inline]
+    unsafe fn poll_lock_upgradable(
+        &self,
+        waiter: &mut AsyncWaiter,
+        cx: &mut Context<'_>,
+    ) -> Poll<()> {
+        let result = if !waiter.is_parked() && self.try_lock_upgradable_fast() {
+            Poll::Ready(())
+        } else {
+            let try_lock = |state: &mut usize| {
+                if self.try_lock_upgradable_slow() {
+                    return true;
+                }
+                *state = self.state.load(Ordering::Relaxed);
+                false
+            };
+            self.poll_lock_common(
+                waiter,
+                cx,
+                TOKEN_UPGRADABLE,
+                try_lock,
+                WRITER_BIT | UPGRADABLE_BIT,
+            )
+        };
+        if result.is_ready() {
+            self.on_acquire(ResourceKind::RwLockUpgradable);
+        }
+        result
+    }
+
+    #[inline]
+    unsafe fn cancel_lock_upgradable(&self, waiter: &mut AsyncWaiter) {
+        if waiter.is_parked() {
+            self.cancel_lock_common(waiter, TOKEN_UPGRADABLE);
         }
     }
 }
@@ -596,45 +776,52 @@ impl RawRwLock {
         }
     }
 
-    #[cold]
-    fn lock_exclusive_slow(&self, timeout: Option<Instant>) -> bool {
-        let try_lock = |state: &mut usize| {
-            loop {
-                if *state & (WRITER_BIT | UPGRADABLE_BIT) != 0 {
-                    return false;
-                }
+    #[inline]
+    fn try_lock_writer_bit(&self, state: &mut usize) -> bool {
+        loop {
+            if *state & (WRITER_BIT | UPGRADABLE_BIT) != 0 {
+                return false;
+            }
 
-                // Grab WRITER_BIT if it isn't set, even if there are parked threads.
-                match self.state.compare_exchange_weak(
-                    *state,
-                    *state | WRITER_BIT,
-                    Ordering::Acquire,
-                    Ordering::Relaxed,
-                ) {
-                    Ok(_) => return true,
-                    Err(x) => *state = x,
-                }
+            // Grab WRITER_BIT if it isn't set, even if there are parked threads.
+            match self.state.compare_exchange_weak(
+                *state,
+                *state | WRITER_BIT,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(x) => *state = x,
             }
-        };
+        }
+    }
+
+    #[cold]
+    fn lock_exclusive_slow(&self, timeout: Option<Instant>) -> bool {
+        let try_lock = |state: &mut usize| self.try_lock_writer_bit(state);
+        let mut contention = stats::Contention::new();
 
         // Step 1: grab exclusive ownership of WRITER_BIT
-        let timed_out = !self.lock_common(
+        let mut acquired = self.lock_common(
             timeout,
             TOKEN_EXCLUSIVE,
             try_lock,
             WRITER_BIT | UPGRADABLE_BIT,
+            &mut contention,
         );
-        if timed_out {
-            return false;
-        }
 
         // Step 2: wait for all remaining readers to exit the lock.
-        self.wait_for_readers(timeout, 0)
+        if acquired {
+            acquired = self.wait_for_readers(timeout, 0, &mut contention);
+        }
+        contention.finish(self as *const _ as usize, acquired);
+        acquired
     }
 
     #[cold]
     fn unlock_exclusive_slow(&self, force_fair: bool) {
         // There are threads to unpark. Try to unpark as many as we can.
+        let handed_off = Cell::new(false);
         let callback = |mut new_state, result: UnparkResult| {
             // If we are using a fair unlock then we should keep the
             // rwlock locked and hand it off to the unparked threads.
@@ -643,6 +830,7 @@ impl RawRwLock {
                     new_state |= PARKED_BIT;
                 }
                 self.state.store(new_state, Ordering::Release);
+                handed_off.set(true);
                 TOKEN_HANDOFF
             } else {
                 // Clear the parked bit if there are no more parked threads.
@@ -658,6 +846,9 @@ impl RawRwLock {
         unsafe {
             self.wake_parked_threads(0, callback);
         }
+        if handed_off.get() {
+            stats::fair_handoff(self as *const _ as usize);
+        }
     }
 
     #[cold]
@@ -704,7 +895,11 @@ impl RawRwLock {
                 *state = self.state.load(Ordering::Relaxed);
             }
         };
-        self.lock_common(timeout, TOKEN_SHARED, try_lock, WRITER_BIT)
+        let mut contention = stats::Contention::new();
+        let acquired =
+            self.lock_common(timeout, TOKEN_SHARED, try_lock, WRITER_BIT, &mut contention);
+        contention.finish(self as *const _ as usize, acquired);
+        acquired
     }
 
     #[cold]
@@ -758,12 +953,16 @@ impl RawRwLock {
                 *state = self.state.load(Ordering::Relaxed);
             }
         };
-        self.lock_common(
+        let mut contention = stats::Contention::new();
+        let acquired = self.lock_common(
             timeout,
             TOKEN_UPGRADABLE,
             try_lock,
             WRITER_BIT | UPGRADABLE_BIT,
-        )
+            &mut contention,
+        );
+        contention.finish(self as *const _ as usize, acquired);
+        acquired
     }
 
     #[cold]
@@ -856,7 +1055,10 @@ impl RawRwLock {
 
     #[cold]
     fn upgrade_slow(&self, timeout: Option<Instant>) -> bool {
-        self.wait_for_readers(timeout, ONE_READER | UPGRADABLE_BIT)
+        let mut contention = stats::Contention::new();
+        let acquired = self.wait_for_readers(timeout, ONE_READER | UPGRADABLE_BIT, &mut contention);
+        contention.finish(self as *const _ as usize, acquired);
+        acquired
     }
 
     #[cold]
@@ -899,15 +1101,21 @@ impl RawRwLock {
 
     #[cold]
     fn bump_exclusive_slow(&self) {
-        self.deadlock_release();
-        self.unlock_exclusive_slow(true);
+        {
+            // Report the hold before locking again
+            let _hold = self.on_release(ResourceKind::RwLockExclusive);
+            self.unlock_exclusive_slow(true);
+        }
         self.lock_exclusive();
     }
 
     #[cold]
     fn bump_upgradable_slow(&self) {
-        self.deadlock_release();
-        self.unlock_upgradable_slow(true);
+        {
+            // Report the hold before locking again
+            let _hold = self.on_release(ResourceKind::RwLockUpgradable);
+            self.unlock_upgradable_slow(true);
+        }
         self.lock_upgradable();
     }
 
@@ -959,7 +1167,12 @@ impl RawRwLock {
     // Common code for waiting for readers to exit the lock after acquiring
     // WRITER_BIT.
     #[inline]
-    fn wait_for_readers(&self, timeout: Option<Instant>, prev_value: usize) -> bool {
+    fn wait_for_readers(
+        &self,
+        timeout: Option<Instant>,
+        prev_value: usize,
+        contention: &mut stats::Contention,
+    ) -> bool {
         // At this point WRITER_BIT is already set, we just need to wait for the
         // remaining readers to exit the lock.
         let mut spinwait = SpinWait::new();
@@ -967,6 +1180,7 @@ impl RawRwLock {
         while state & READERS_MASK != 0 {
             // Spin a few times to wait for readers to exit
             if spinwait.spin() {
+                contention.spin();
                 state = self.state.load(Ordering::Relaxed);
                 continue;
             }
@@ -993,19 +1207,27 @@ impl RawRwLock {
             };
             let before_sleep = || {};
             let timed_out = |_, _| {};
+            contention.park();
+            deadlock::set_wait_resource(Resource {
+                key: addr - 1,
+                kind: ResourceKind::RwLockExclusive,
+            });
             // SAFETY:
             //   * `addr` is an address we control.
             //   * `validate`/`timed_out` does not panic or call into any function of `parking_lot`.
             //   * `before_sleep` does not call `park`, nor does it panic.
             let park_result = unsafe {
-                parking_lot_core::park(
+                deadlock::acquire_resource_with_kind(addr - 1, ResourceKind::RwLockExclusive);
+                let park_result = parking_lot_core::park(
                     addr,
                     validate,
                     before_sleep,
                     timed_out,
                     TOKEN_EXCLUSIVE,
                     timeout,
-                )
+                );
+                deadlock::release_resource(addr - 1);
+                park_result
             };
             match park_result {
                 // We still need to re-check the state if we are unparked
@@ -1053,6 +1275,7 @@ impl RawRwLock {
         token: ParkToken,
         mut try_lock: impl FnMut(&mut usize) -> bool,
         validate_flags: usize,
+        contention: &mut stats::Contention,
     ) -> bool {
         let mut spinwait = SpinWait::new();
         let mut state = self.state.load(Ordering::Relaxed);
@@ -1064,6 +1287,7 @@ impl RawRwLock {
 
             // If there are no parked threads, try spinning a few times.
             if state & (PARKED_BIT | WRITER_PARKED_BIT) == 0 && spinwait.spin() {
+                contention.spin();
                 state = self.state.load(Ordering::Relaxed);
                 continue;
             }
@@ -1099,6 +1323,12 @@ impl RawRwLock {
             // * `addr` is an address we control.
             // * `validate`/`timed_out` does not panic or call into any function of `parking_lot`.
             // * `before_sleep` does not call `park`, nor does it panic.
+            contention.park();
+            deadlock::set_wait_kind(match token {
+                TOKEN_SHARED => ResourceKind::RwLockShared,
+                TOKEN_UPGRADABLE => ResourceKind::RwLockUpgradable,
+                _ => ResourceKind::RwLockExclusive,
+            });
             let park_result = unsafe {
                 parking_lot_core::park(addr, validate, before_sleep, timed_out, token, timeout)
             };
@@ -1123,15 +1353,327 @@ impl RawRwLock {
         }
     }
 
+    /// Asynchronous version of `lock_common`: the task is queued with its
+    /// waker instead of parking the thread, and there is no spinning.
+    #[cold]
+    fn poll_lock_common(
+        &self,
+        waiter: &mut AsyncWaiter,
+        cx: &mut Context<'_>,
+        token: ParkToken,
+        mut try_lock: impl FnMut(&mut usize) -> bool,
+        validate_flags: usize,
+    ) -> Poll<()> {
+        let addr = self as *const _ as usize;
+
+        // If we were already queued, check whether we have been unparked
+        if waiter.is_parked() {
+            // SAFETY: `addr` is an address we control.
+            match unsafe { waiter.park(addr, || true, cx, token) } {
+                Poll::Pending => return Poll::Pending,
+
+                // The thread that unparked us passed the lock on to us
+                // directly without unlocking it.
+                Poll::Ready(ParkResult::Unparked(TOKEN_HANDOFF)) => return Poll::Ready(()),
+
+                // We were unparked normally, try acquiring the lock again
+                Poll::Ready(_) => (),
+            }
+        }
+
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            // Attempt to grab the lock
+            if try_lock(&mut state) {
+                return Poll::Ready(());
+            }
+
+            // Set the parked bit
+            if state & PARKED_BIT == 0 {
+                if let Err(x) = self.state.compare_exchange_weak(
+                    state,
+                    state | PARKED_BIT,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    state = x;
+                    continue;
+                }
+            }
+
+            // Queue our task until we are woken up by an unlock
+            let validate = || {
+                let state = self.state.load(Ordering::Relaxed);
+                state & PARKED_BIT != 0 && (state & validate_flags != 0)
+            };
+            // SAFETY:
+            // * `addr` is an address we control.
+            // * `validate` does not panic or call into any function of `parking_lot`.
+            match unsafe { waiter.park(addr, validate, cx, token) } {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(ParkResult::Unparked(TOKEN_HANDOFF)) => return Poll::Ready(()),
+
+                // The validation function failed, try locking again
+                Poll::Ready(_) => (),
+            }
+            state = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    /// Gives up on a lock which a task was queued for in `poll_lock_common`.
+    #[cold]
+    fn cancel_lock_common(&self, waiter: &mut AsyncWaiter, token: ParkToken) {
+        let cancelled = |_, was_last_thread| {
+            // Clear the parked bit if we were the last parked thread
+            if was_last_thread {
+                self.state.fetch_and(!PARKED_BIT, Ordering::Relaxed);
+            }
+        };
+        // SAFETY: `cancelled` does not panic or call into any function of `parking_lot`.
+        match unsafe { waiter.cancel(cancelled) } {
+            // We were still queued and have been removed
+            None => (),
+
+            // The lock was handed off to us, release it again
+            Some(TOKEN_HANDOFF) => match token {
+                TOKEN_SHARED => {
+                    self.on_acquire(ResourceKind::RwLockShared);
+                    self.unlock_shared();
+                }
+                TOKEN_UPGRADABLE => {
+                    self.on_acquire(ResourceKind::RwLockUpgradable);
+                    self.unlock_upgradable();
+                }
+                _ => self.release_writer_bit(0),
+            },
+
+            // We were unparked normally to retry acquiring the lock. Pass that
+            // on to the remaining waiters so that they don't remain parked.
+            Some(_) => match token {
+                TOKEN_SHARED => {
+                    if self.try_lock_shared() {
+                        self.unlock_shared();
+                    }
+                }
+                TOKEN_UPGRADABLE => {
+                    if self.try_lock_upgradable() {
+                        self.unlock_upgradable();
+                    }
+                }
+                _ => {
+                    let mut state = self.state.load(Ordering::Relaxed);
+                    if self.try_lock_writer_bit(&mut state) {
+                        self.release_writer_bit(0);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Asynchronous version of `wait_for_readers` for a task which has
+    /// acquired WRITER_BIT.
+    #[cold]
+    fn poll_wait_for_readers(&self, waiter: &mut AsyncWaiter, cx: &mut Context<'_>) -> Poll<()> {
+        // Using the 2nd key at addr + 1
+        let addr = self as *const _ as usize + 1;
+
+        // If we were already queued, check whether we have been unparked.
+        // We still need to re-check the state in that case, see
+        // wait_for_readers.
+        if waiter.is_parked() {
+            // SAFETY: `addr` is an address we control.
+            if unsafe { waiter.park(addr, || true, cx, TOKEN_EXCLUSIVE) }.is_pending() {
+                return Poll::Pending;
+            }
+        }
+
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state & READERS_MASK != 0 {
+            // Set the parked bit
+            if state & WRITER_PARKED_BIT == 0 {
+                if let Err(x) = self.state.compare_exchange_weak(
+                    state,
+                    state | WRITER_PARKED_BIT,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    state = x;
+                    continue;
+                }
+            }
+
+            // Queue our task until we are woken up by the last reader
+            let validate = || {
+                let state = self.state.load(Ordering::Relaxed);
+                state & READERS_MASK != 0 && state & WRITER_PARKED_BIT != 0
+            };
+            // SAFETY:
+            //   * `addr` is an address we control.
+            //   * `validate` does not panic or call into any function of `parking_lot`.
+            if unsafe { waiter.park(addr, validate, cx, TOKEN_EXCLUSIVE) }.is_pending() {
+                return Poll::Pending;
+            }
+            state = self.state.load(Ordering::Relaxed);
+        }
+        Poll::Ready(())
+    }
+
+    /// Gives up on waiting for readers after `poll_wait_for_readers`.
+    #[cold]
+    fn cancel_wait_for_readers(&self, waiter: &mut AsyncWaiter) {
+        waiter.waiting_for_readers = false;
+
+        // If we are removed from the queue then WRITER_PARKED_BIT is still set,
+        // otherwise it was cleared by the reader that unparked us.
+        // SAFETY: the callback does nothing.
+        let removed = waiter.is_parked() && unsafe { waiter.cancel(|_, _| {}) }.is_none();
+        self.release_writer_bit(if removed { WRITER_PARKED_BIT } else { 0 });
+    }
+
+    /// Releases WRITER_BIT for a writer which gave up on acquiring the lock,
+    /// and wakes up any threads that might be waiting on WRITER_BIT.
+    #[inline]
+    fn release_writer_bit(&self, parked_bits: usize) {
+        let state = self
+            .state
+            .fetch_sub(WRITER_BIT | parked_bits, Ordering::Release);
+        if state & PARKED_BIT != 0 {
+            let callback = |_, result: UnparkResult| {
+                // Clear the parked bit if there no more parked threads
+                if !result.have_more_threads {
+                    self.state.fetch_and(!PARKED_BIT, Ordering::Relaxed);
+                }
+                TOKEN_NORMAL
+            };
+            // SAFETY: `callback` does not panic or call any function of `parking_lot`.
+            unsafe {
+                self.wake_parked_threads(0, callback);
+            }
+        }
+    }
+
+    // Records an acquisition of the lock in `kind` mode with all the
+    // instrumentation which is enabled. Exclusive and upgradable holds are
+    // timed, since they are the ones blocking writers.
+    #[inline]
+    fn on_acquire(&self, kind: ResourceKind) {
+        let addr = self as *const _ as usize;
+        self.acquire_resources(kind);
+        stats::acquire(addr, kind == ResourceKind::RwLockExclusive);
+        if kind != ResourceKind::RwLockShared {
+            hold_time::acquire(addr);
+        }
+    }
+
+    // Records the release of the lock held in `kind` mode. Must be called
+    // before the lock is released, and the result dropped after it.
+    #[inline]
+    fn on_release(&self, kind: ResourceKind) -> hold_time::Release {
+        self.release_resources(kind);
+        self.end_exclusive_stats(kind);
+        if kind != ResourceKind::RwLockShared {
+            hold_time::release(self as *const _ as usize)
+        } else {
+            hold_time::Release::default()
+        }
+    }
+
+    // Records a change of the mode the lock is held in, which doesn't count
+    // as a new acquisition. Upgrading and downgrading between upgradable and
+    // exclusive access continues the same timed hold.
+    #[inline]
+    fn on_change(&self, from: ResourceKind, to: ResourceKind) -> hold_time::Release {
+        let addr = self as *const _ as usize;
+        self.change_resources(from, to);
+        self.end_exclusive_stats(from);
+        match (from, to) {
+            (ResourceKind::RwLockShared, ResourceKind::RwLockShared) => {
+                hold_time::Release::default()
+            }
+            (ResourceKind::RwLockShared, _) => {
+                hold_time::acquire(addr);
+                hold_time::Release::default()
+            }
+            (_, ResourceKind::RwLockShared) => hold_time::release(addr),
+            _ => hold_time::Release::default(),
+        }
+    }
+
     #[inline]
-    fn deadlock_acquire(&self) {
-        unsafe { deadlock::acquire_resource(self as *const _ as usize) };
-        unsafe { deadlock::acquire_resource(self as *const _ as usize + 1) };
+    fn end_exclusive_stats(&self, kind: ResourceKind) {
+        if kind == ResourceKind::RwLockExclusive {
+            stats::release(self as *const _ as usize);
+        }
     }
 
+    // The lock is modeled as two resources in the deadlock detector. `addr`
+    // is held by writers and upgradable readers, which are the ones blocking
+    // threads parked on `addr`. `addr + 1` is held by readers and writers,
+    // since readers are the ones blocking a writer waiting for them to exit on
+    // `addr + 1`. A writer waiting for readers also holds `addr` while it is
+    // parked, since it blocks any new reader. Both are reported as `addr`,
+    // the kind telling how the lock is held. The lock is also recorded once
+    // in the locks held by the thread.
     #[inline]
-    fn deadlock_release(&self) {
-        unsafe { deadlock::release_resource(self as *const _ as usize) };
-        unsafe { deadlock::release_resource(self as *const _ as usize + 1) };
+    fn acquire_resources(&self, kind: ResourceKind) {
+        let addr = self as *const _ as usize;
+        held_locks::acquire(addr, kind);
+        if kind != ResourceKind::RwLockShared {
+            unsafe { deadlock::acquire_resource_with_kind(addr, kind) };
+        }
+        if kind != ResourceKind::RwLockUpgradable {
+            unsafe { deadlock::acquire_resource_as(addr + 1, Resource { key: addr, kind }) };
+        }
+    }
+
+    // The lock stays held during the change, so the keys needed by `to` are
+    // acquired before the others are released. The lock order validator then
+    // never sees the lock being acquired again.
+    #[inline]
+    fn change_resources(&self, from: ResourceKind, to: ResourceKind) {
+        let addr = self as *const _ as usize;
+        held_locks::change(addr, to);
+        let primary = |kind| kind != ResourceKind::RwLockShared;
+        let secondary = |kind| kind != ResourceKind::RwLockUpgradable;
+        unsafe {
+            if primary(to) {
+                if primary(from) {
+                    deadlock::change_resource_kind(addr, to);
+                } else {
+                    deadlock::acquire_resource_with_kind(addr, to);
+                }
+            }
+            if secondary(to) {
+                if secondary(from) {
+                    deadlock::change_resource_kind(addr + 1, to);
+                } else {
+                    deadlock::acquire_resource_as(
+                        addr + 1,
+                        Resource {
+                            key: addr,
+                            kind: to,
+                        },
+                    );
+                }
+            }
+            if primary(from) && !primary(to) {
+                deadlock::release_resource(addr);
+            }
+            if secondary(from) && !secondary(to) {
+                deadlock::release_resource(addr + 1);
+            }
+        }
+    }
+
+    #[inline]
+    fn release_resources(&self, kind: ResourceKind) {
+        let addr = self as *const _ as usize;
+        held_locks::release(addr);
+        if kind != ResourceKind::RwLockShared {
+            unsafe { deadlock::release_resource(addr) };
+        }
+        if kind != ResourceKind::RwLockUpgradable {
+            unsafe { deadlock::release_resource(addr + 1) };
+        }
     }
 }
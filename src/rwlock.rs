@@ -118,13 +118,75 @@ pub type MappedRwLockWriteGuard<'a, T> = lock_api::MappedRwLockWriteGuard<'a, Ra
 /// dropped.
 pub type RwLockUpgradableReadGuard<'a, T> = lock_api::RwLockUpgradableReadGuard<'a, RawRwLock, T>;
 
+/// A future returned by `RwLock::read_async` which resolves to a
+/// `RwLockReadGuard` once shared access has been acquired.
+pub type RwLockReadFuture<'a, T> = lock_api::RwLockReadFuture<'a, RawRwLock, T>;
+
+/// A future returned by `RwLock::write_async` which resolves to a
+/// `RwLockWriteGuard` once exclusive access has been acquired.
+pub type RwLockWriteFuture<'a, T> = lock_api::RwLockWriteFuture<'a, RawRwLock, T>;
+
+/// A future returned by `RwLock::upgradable_read_async` which resolves to a
+/// `RwLockUpgradableReadGuard` once upgradable access has been acquired.
+pub type RwLockUpgradableReadFuture<'a, T> = lock_api::RwLockUpgradableReadFuture<'a, RawRwLock, T>;
+
+/// RAII structure returned by `RwLock::read_arc`, which owns an `Arc` of the
+/// lock and releases its shared read access when dropped.
+///
+/// The guard can only be moved to another thread and unlocked there if the
+/// `send_guard` feature is enabled.
+#[cfg(feature = "arc_lock")]
+pub type ArcRwLockReadGuard<T> = lock_api::ArcRwLockReadGuard<RawRwLock, T>;
+
+/// RAII structure returned by `RwLock::write_arc`, which owns an `Arc` of the
+/// lock and releases its exclusive write access when dropped.
+///
+/// The guard can only be moved to another thread and unlocked there if the
+/// `send_guard` feature is enabled.
+#[cfg(feature = "arc_lock")]
+pub type ArcRwLockWriteGuard<T> = lock_api::ArcRwLockWriteGuard<RawRwLock, T>;
+
+/// RAII structure returned by `RwLock::upgradable_read_arc`, which owns an
+/// `Arc` of the lock and releases its upgradable read access when dropped.
+///
+/// The guard can only be moved to another thread and unlocked there if the
+/// `send_guard` feature is enabled.
+#[cfg(feature = "arc_lock")]
+pub type ArcRwLockUpgradableReadGuard<T> = lock_api::ArcRwLockUpgradableReadGuard<RawRwLock, T>;
+
+/// An RAII read lock guard returned by `ArcRwLockReadGuard::map`, which can
+/// point to a subfield of the protected data while keeping the lock's `Arc`
+/// alive.
+#[cfg(feature = "arc_lock")]
+pub type MappedArcRwLockReadGuard<T, U> = lock_api::MappedArcRwLockReadGuard<RawRwLock, T, U>;
+
+/// An RAII write lock guard returned by `ArcRwLockWriteGuard::map`, which can
+/// point to a subfield of the protected data while keeping the lock's `Arc`
+/// alive.
+#[cfg(feature = "arc_lock")]
+pub type MappedArcRwLockWriteGuard<T, U> = lock_api::MappedArcRwLockWriteGuard<RawRwLock, T, U>;
+
+/// A reader-writer lock which is poisoned if a thread panics while holding a
+/// write lock, like the standard library `RwLock`. Its methods return
+/// `LockResult` and `TryLockResult` from `std::sync`.
+#[cfg(feature = "poison")]
+pub type PoisonRwLock<T> = lock_api::PoisonRwLock<RawRwLock, T>;
+
+/// An RAII guard returned by `PoisonRwLock::write`, which poisons the lock if
+/// it is dropped while the thread is panicking.
+#[cfg(feature = "poison")]
+pub type PoisonRwLockWriteGuard<'a, T> = lock_api::PoisonRwLockWriteGuard<'a, RawRwLock, T>;
+
 #[cfg(test)]
 mod tests {
+    use crate::util::{block_on, thread_waker};
     use crate::{RwLock, RwLockUpgradableReadGuard, RwLockWriteGuard};
     use rand::Rng;
+    use std::future::Future;
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::mpsc::channel;
     use std::sync::Arc;
+    use std::task::Context;
     use std::thread;
     use std::time::Duration;
 
@@ -567,6 +629,216 @@ mod tests {
         assert_eq!(Arc::strong_count(&b), 2);
     }
 
+    #[cfg(feature = "arc_lock")]
+    #[test]
+    fn test_arc_lock() {
+        use crate::{ArcRwLockReadGuard, ArcRwLockUpgradableReadGuard, ArcRwLockWriteGuard};
+
+        let lock = Arc::new(RwLock::new((1, 2)));
+        let read = lock.read_arc();
+        let read2 = lock.try_read_arc().unwrap();
+        assert!(lock.try_write_arc().is_none());
+        let mapped = ArcRwLockReadGuard::map(read, |v| &v.1);
+        assert_eq!(*mapped, 2);
+        drop((mapped, read2));
+
+        let write = lock.write_arc();
+        let mut write = ArcRwLockWriteGuard::map(write, |v| &mut v.0);
+        *write = 10;
+        drop(write);
+
+        let upgradable = lock.upgradable_read_arc();
+        assert!(lock.try_upgradable_read_arc().is_none());
+        let _read = lock.read_arc();
+        let upgradable = match ArcRwLockUpgradableReadGuard::try_upgrade(upgradable) {
+            Ok(_) => panic!("upgrade should fail while a reader exists"),
+            Err(upgradable) => upgradable,
+        };
+        drop(_read);
+        let mut write = ArcRwLockUpgradableReadGuard::upgrade(upgradable);
+        write.1 = 20;
+        let read = ArcRwLockWriteGuard::downgrade(write);
+        assert_eq!(*read, (10, 20));
+        assert!(lock.try_read_arc().is_some());
+        let lock = ArcRwLockReadGuard::into_arc(read);
+        assert!(lock.try_write_arc().is_some());
+    }
+
+    #[cfg(all(feature = "arc_lock", feature = "send_guard"))]
+    #[test]
+    fn test_arc_lock_send() {
+        use crate::ArcRwLockUpgradableReadGuard;
+
+        let lock = Arc::new(RwLock::new(0));
+        let read = lock.read_arc();
+        let upgradable = lock.upgradable_read_arc();
+
+        // Guards are moved to another thread and released there
+        thread::spawn(move || {
+            drop(read);
+            *ArcRwLockUpgradableReadGuard::upgrade(upgradable) += 1;
+        })
+        .join()
+        .unwrap();
+        assert_eq!(*lock.try_write().unwrap(), 1);
+    }
+
+    #[cfg(feature = "poison")]
+    #[test]
+    fn test_poison() {
+        use crate::PoisonRwLock;
+        use std::sync::TryLockError;
+
+        let lock = Arc::new(PoisonRwLock::new(1));
+
+        // Panicking while holding a read lock doesn't poison the lock.
+        let lock2 = lock.clone();
+        let _ = thread::spawn(move || {
+            let _guard = lock2.read().unwrap();
+            panic!();
+        })
+        .join();
+        assert!(!lock.is_poisoned());
+
+        let lock2 = lock.clone();
+        let _ = thread::spawn(move || {
+            let mut guard = lock2.write().unwrap();
+            *guard = 10;
+            panic!();
+        })
+        .join();
+        assert!(lock.is_poisoned());
+        assert_eq!(
+            format!("{:?}", lock),
+            "PoisonRwLock { data: 10, poisoned: true }"
+        );
+
+        let read = lock.read().unwrap_err().into_inner();
+        assert_eq!(*read, 10);
+        match lock.try_write() {
+            Err(TryLockError::WouldBlock) => {}
+            _ => panic!("expected WouldBlock"),
+        }
+        assert!(lock.try_read().is_err());
+        drop(read);
+
+        *lock.write().unwrap_err().into_inner() = 2;
+        lock.clear_poison();
+        assert_eq!(*lock.try_read().unwrap(), 2);
+        *lock.try_write().unwrap() += 1;
+
+        let mut lock = Arc::try_unwrap(lock).unwrap();
+        assert_eq!(*lock.get_mut().unwrap(), 3);
+        assert_eq!(lock.into_inner().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_rwlock_async() {
+        let lock = Arc::new(RwLock::new(0));
+        *block_on(lock.write_async()) += 1;
+        assert_eq!(*block_on(lock.read_async()), 1);
+        assert_eq!(*block_on(lock.upgradable_read_async()), 1);
+
+        // Contend between threads using blocking and asynchronous locking
+        const N: u32 = 6;
+        const M: u32 = 500;
+        let (tx, rx) = channel::<()>();
+        for i in 0..N {
+            let (tx, lock) = (tx.clone(), lock.clone());
+            thread::spawn(move || {
+                let mut rng = rand::thread_rng();
+                for _ in 0..M {
+                    let write = rng.gen_bool(0.5);
+                    match (i % 2 == 0, write) {
+                        (true, true) => *lock.write() += 1,
+                        (true, false) => drop(lock.read()),
+                        (false, true) => *block_on(lock.write_async()) += 1,
+                        (false, false) => {
+                            if rng.gen_bool(0.5) {
+                                drop(block_on(lock.read_async()));
+                            } else {
+                                let guard = block_on(lock.upgradable_read_async());
+                                *RwLockUpgradableReadGuard::upgrade(guard) += 0;
+                            }
+                        }
+                    }
+                }
+                drop(tx);
+            });
+        }
+        drop(tx);
+        let _ = rx.recv();
+        let value = *lock.read();
+        assert!((1..=1 + N * M).contains(&value));
+    }
+
+    #[test]
+    fn test_rwlock_async_wait_for_readers() {
+        let lock = Arc::new(RwLock::new(()));
+        let read_guard = lock.read();
+        let waker = thread_waker();
+
+        // The writer grabs the lock but has to wait for the reader to exit
+        let mut future = Box::pin(lock.write_async());
+        assert!(future
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        assert!(lock.try_read().is_none());
+
+        // Giving up releases the lock to readers again
+        drop(future);
+        assert!(lock.try_read().is_some());
+
+        // Otherwise the writer acquires the lock once the reader exits
+        let lock2 = lock.clone();
+        let t = thread::spawn(move || drop(block_on(lock2.write_async())));
+        thread::sleep(Duration::from_millis(10));
+        drop(read_guard);
+        t.join().unwrap();
+        assert!(lock.try_write().is_some());
+    }
+
+    #[test]
+    fn test_rwlock_async_cancel() {
+        let lock = Arc::new(RwLock::new(()));
+        let write_guard = lock.write();
+        let waker = thread_waker();
+
+        let mut read = Box::pin(lock.read_async());
+        let mut upgradable = Box::pin(lock.upgradable_read_async());
+        let mut write = Box::pin(lock.write_async());
+        for future in [
+            &mut read as &mut dyn CancelTest,
+            &mut upgradable,
+            &mut write,
+        ]
+        .iter_mut()
+        {
+            assert!(future.poll_pending(&mut Context::from_waker(&waker)));
+        }
+
+        // Threads queued behind the cancelled tasks must still get the lock
+        let lock2 = lock.clone();
+        let t = thread::spawn(move || drop(lock2.write()));
+        drop(write);
+        drop(write_guard);
+        drop(read);
+        drop(upgradable);
+        t.join().unwrap();
+        assert!(lock.try_write().is_some());
+    }
+
+    trait CancelTest {
+        fn poll_pending(&mut self, cx: &mut Context<'_>) -> bool;
+    }
+
+    impl<F: Future> CancelTest for std::pin::Pin<Box<F>> {
+        fn poll_pending(&mut self, cx: &mut Context<'_>) -> bool {
+            self.as_mut().poll(cx).is_pending()
+        }
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn test_serde() {
@@ -0,0 +1,605 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::raw_mutex::{TOKEN_HANDOFF, TOKEN_NORMAL};
+use crate::util;
+use core::{
+    cell::Cell,
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use parking_lot_core::{self, FilterOp, ParkResult, ParkToken, SpinWait, UnparkResult};
+use std::time::{Duration, Instant};
+
+/// This bit is set in the `state` of a `Semaphore` just before parking a thread. A thread is
+/// parked if it wants to acquire more permits than are currently available.
+const PARKED_BIT: usize = 0b1;
+/// The number of available permits is stored in the remaining bits of the `state`.
+const PERMITS_SHIFT: u32 = 1;
+/// Base unit for counting permits.
+const ONE_PERMIT: usize = 1 << PERMITS_SHIFT;
+
+/// A counting semaphore.
+///
+/// A semaphore maintains a number of permits. Permits are acquired with
+/// `acquire`, which blocks the current thread until enough permits are
+/// available, and are returned when the resulting `SemaphoreGuard` is dropped.
+/// Permits can also be added directly with `release`.
+///
+/// Threads waiting for permits are queued in the order in which they started
+/// waiting and a release will only wake up the threads at the front of the
+/// queue whose requests can be satisfied. A waiting thread is therefore never
+/// overtaken by the threads queued behind it.
+///
+/// However, a thread which isn't waiting yet doesn't join the queue if enough
+/// permits are available: it takes them immediately, even if other threads are
+/// waiting. A waiting thread requesting many permits can therefore be starved
+/// by a stream of smaller requests from other threads, in the same way that a
+/// thread waiting for a `Mutex` can be overtaken by threads which keep locking
+/// it.
+///
+/// # Fairness
+///
+/// Like `Mutex`, this semaphore uses [eventual fairness](https://trac.webkit.org/changeset/203350)
+/// to ensure that it will be fair on average without sacrificing performance.
+/// Normally a thread which is woken up by a release has to race with other
+/// threads for the released permits. On average every 0.5ms a release will
+/// instead hand the permits directly to the woken threads. This only applies
+/// to the threads whose requests can be satisfied by the release, so it
+/// doesn't prevent the starvation of large requests described above.
+///
+/// You can also force a fair release by calling `SemaphoreGuard::release_fair`
+/// instead of simply dropping the `SemaphoreGuard`.
+///
+/// # Differences from a `Mutex<usize>` and `Condvar` based semaphore
+///
+/// - Only requires 1 word of space.
+/// - Can be statically constructed.
+/// - Does not require any drop glue when dropped.
+/// - Inline fast path for the uncontended case.
+/// - Efficient handling of micro-contention using adaptive spinning.
+/// - Only wakes up as many threads as there are available permits.
+///
+/// # Examples
+///
+/// ```
+/// use parking_lot::Semaphore;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// // Allow at most 2 threads to work at the same time.
+/// let semaphore = Arc::new(Semaphore::new(2));
+///
+/// let handles: Vec<_> = (0..10)
+///     .map(|_| {
+///         let semaphore = semaphore.clone();
+///         thread::spawn(move || {
+///             let _permit = semaphore.acquire(1);
+///             // At most 2 threads can be running this code at once. The
+///             // permit is returned to the semaphore when `_permit` is dropped.
+///         })
+///     })
+///     .collect();
+///
+/// for handle in handles {
+///     handle.join().unwrap();
+/// }
+/// assert_eq!(semaphore.available_permits(), 2);
+/// ```
+pub struct Semaphore {
+    state: AtomicUsize,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with the given number of permits.
+    ///
+    /// The number of permits is clamped to `usize::max_value() >> 1`, which is
+    /// the maximum supported by the semaphore.
+    #[inline]
+    pub const fn new(permits: usize) -> Semaphore {
+        // Clamp the number of permits without using `if`, which isn't allowed
+        // in a `const fn`.
+        let max = !0usize >> PERMITS_SHIFT;
+        let excess = (permits > max) as usize * permits.wrapping_sub(max);
+        Semaphore {
+            state: AtomicUsize::new((permits - excess) << PERMITS_SHIFT),
+        }
+    }
+
+    /// Returns the number of permits which are currently available.
+    #[inline]
+    pub fn available_permits(&self) -> usize {
+        self.state.load(Ordering::Relaxed) >> PERMITS_SHIFT
+    }
+
+    /// Acquires `n` permits, blocking the current thread until they are
+    /// available.
+    ///
+    /// Returns an RAII guard which will release the permits once it is
+    /// dropped.
+    ///
+    /// Attempting to acquire more permits than will ever be released to the
+    /// semaphore will block forever.
+    #[inline]
+    pub fn acquire(&self, n: usize) -> SemaphoreGuard<'_> {
+        if !self.try_acquire_fast(n) {
+            let result = self.acquire_slow(n, None);
+            debug_assert!(result);
+        }
+        SemaphoreGuard {
+            semaphore: self,
+            permits: n,
+        }
+    }
+
+    /// Attempts to acquire `n` permits without blocking.
+    ///
+    /// If the permits could not be acquired at this time, then `None` is
+    /// returned. Otherwise, an RAII guard is returned which will release the
+    /// permits when it is dropped.
+    #[inline]
+    pub fn try_acquire(&self, n: usize) -> Option<SemaphoreGuard<'_>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state >> PERMITS_SHIFT < n {
+                return None;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state - n * ONE_PERMIT,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Some(SemaphoreGuard {
+                        semaphore: self,
+                        permits: n,
+                    })
+                }
+                Err(x) => state = x,
+            }
+        }
+    }
+
+    /// Attempts to acquire `n` permits until a timeout is reached.
+    ///
+    /// If the permits could not be acquired before the timeout expired, then
+    /// `None` is returned. Otherwise, an RAII guard is returned which will
+    /// release the permits when it is dropped.
+    #[inline]
+    pub fn try_acquire_for(&self, n: usize, timeout: Duration) -> Option<SemaphoreGuard<'_>> {
+        if self.try_acquire_fast(n) || self.acquire_slow(n, util::to_deadline(timeout)) {
+            Some(SemaphoreGuard {
+                semaphore: self,
+                permits: n,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Attempts to acquire `n` permits until a timeout is reached.
+    ///
+    /// If the permits could not be acquired before the timeout expired, then
+    /// `None` is returned. Otherwise, an RAII guard is returned which will
+    /// release the permits when it is dropped.
+    #[inline]
+    pub fn try_acquire_until(&self, n: usize, timeout: Instant) -> Option<SemaphoreGuard<'_>> {
+        if self.try_acquire_fast(n) || self.acquire_slow(n, Some(timeout)) {
+            Some(SemaphoreGuard {
+                semaphore: self,
+                permits: n,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Adds `n` permits to the semaphore, waking up any waiting threads whose
+    /// requests can now be satisfied.
+    ///
+    /// This is useful when combined with `SemaphoreGuard::forget` to hand
+    /// permits over without keeping a guard alive, or to increase the number of
+    /// permits of a semaphore.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the number of permits would overflow.
+    #[inline]
+    pub fn release(&self, n: usize) {
+        self.release_internal(n, false);
+    }
+
+    #[inline]
+    fn try_acquire_fast(&self, n: usize) -> bool {
+        let state = self.state.load(Ordering::Relaxed);
+        state >> PERMITS_SHIFT >= n
+            && self
+                .state
+                .compare_exchange_weak(
+                    state,
+                    state - n * ONE_PERMIT,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+    }
+
+    #[inline]
+    fn release_internal(&self, n: usize, force_fair: bool) {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            let new_state = n
+                .checked_mul(ONE_PERMIT)
+                .and_then(|x| state.checked_add(x))
+                .expect("Semaphore permit count overflow");
+            match self.state.compare_exchange_weak(
+                state,
+                new_state,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(x) => state = x,
+            }
+        }
+        if state & PARKED_BIT != 0 {
+            self.release_slow(force_fair);
+        }
+    }
+
+    #[cold]
+    fn acquire_slow(&self, n: usize, timeout: Option<Instant>) -> bool {
+        let mut spinwait = SpinWait::new();
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            // Grab the permits if enough are available, even if there is a queue
+            if state >> PERMITS_SHIFT >= n {
+                match self.state.compare_exchange_weak(
+                    state,
+                    state - n * ONE_PERMIT,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return true,
+                    Err(x) => state = x,
+                }
+                continue;
+            }
+
+            // If there is no queue, try spinning a few times
+            if state & PARKED_BIT == 0 && spinwait.spin() {
+                state = self.state.load(Ordering::Relaxed);
+                continue;
+            }
+
+            // Set the parked bit
+            if state & PARKED_BIT == 0 {
+                if let Err(x) = self.state.compare_exchange_weak(
+                    state,
+                    state | PARKED_BIT,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    state = x;
+                    continue;
+                }
+            }
+
+            // Park our thread until we are woken up by a release
+            let addr = self as *const _ as usize;
+            let validate = || {
+                let state = self.state.load(Ordering::Relaxed);
+                state & PARKED_BIT != 0 && state >> PERMITS_SHIFT < n
+            };
+            let before_sleep = || {};
+            let timed_out = |_, was_last_thread| {
+                // Clear the parked bit if we were the last parked thread
+                if was_last_thread {
+                    self.state.fetch_and(!PARKED_BIT, Ordering::Relaxed);
+                }
+            };
+            // SAFETY:
+            //   * `addr` is an address we control.
+            //   * `validate`/`timed_out` does not panic or call into any function of `parking_lot`.
+            //   * `before_sleep` does not call `park`, nor does it panic.
+            match unsafe {
+                parking_lot_core::park(
+                    addr,
+                    validate,
+                    before_sleep,
+                    timed_out,
+                    ParkToken(n),
+                    timeout,
+                )
+            } {
+                // The thread that unparked us passed the permits on to us
+                // directly without adding them to the semaphore.
+                ParkResult::Unparked(TOKEN_HANDOFF) => return true,
+
+                // We were unparked normally, try acquiring the permits again
+                ParkResult::Unparked(_) => (),
+
+                // The validation function failed, try acquiring again
+                ParkResult::Invalid => (),
+
+                // Timeout expired
+                ParkResult::TimedOut => {
+                    // We may have been blocking threads queued behind us which
+                    // can be satisfied with the permits that are available now.
+                    let state = self.state.load(Ordering::Relaxed);
+                    if state & PARKED_BIT != 0 && state >> PERMITS_SHIFT != 0 {
+                        self.release_slow(false);
+                    }
+                    return false;
+                }
+            }
+
+            // Loop back and try acquiring again
+            spinwait.reset();
+            state = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    #[cold]
+    fn release_slow(&self, force_fair: bool) {
+        // Wake up threads from the front of the queue for as long as their
+        // requests can be satisfied with the available permits. We stop at the
+        // first thread which can't be satisfied so that it isn't starved by
+        // the threads queued behind it.
+        let addr = self as *const _ as usize;
+        let requested = Cell::new(0usize);
+        let filter = |ParkToken(n)| {
+            let available = self.state.load(Ordering::Relaxed) >> PERMITS_SHIFT;
+            match requested.get().checked_add(n) {
+                Some(total) if total <= available => {
+                    requested.set(total);
+                    FilterOp::Unpark
+                }
+                _ => FilterOp::Stop,
+            }
+        };
+        let callback = |result: UnparkResult| {
+            let mut token = TOKEN_NORMAL;
+
+            // If we are using a fair release then we should hand the permits
+            // directly to the unparked threads, provided that they haven't
+            // been taken by another thread in the meantime.
+            if result.unparked_threads != 0 && (force_fair || result.be_fair) {
+                let requested = requested.get();
+                let mut state = self.state.load(Ordering::Relaxed);
+                while state >> PERMITS_SHIFT >= requested {
+                    match self.state.compare_exchange_weak(
+                        state,
+                        state - requested * ONE_PERMIT,
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    ) {
+                        Ok(_) => {
+                            token = TOKEN_HANDOFF;
+                            break;
+                        }
+                        Err(x) => state = x,
+                    }
+                }
+            }
+
+            // Clear the parked bit if there are no more parked threads.
+            if !result.have_more_threads {
+                self.state.fetch_and(!PARKED_BIT, Ordering::Relaxed);
+            }
+            token
+        };
+        // SAFETY:
+        //   * `addr` is an address we control.
+        //   * `filter`/`callback` does not panic or call into any function of `parking_lot`.
+        unsafe {
+            parking_lot_core::unpark_filter(addr, filter, callback);
+        }
+    }
+}
+
+impl Default for Semaphore {
+    #[inline]
+    fn default() -> Semaphore {
+        Semaphore::new(0)
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("available_permits", &self.available_permits())
+            .finish()
+    }
+}
+
+/// An RAII guard holding permits acquired from a `Semaphore`. When this
+/// structure is dropped (falls out of scope), the permits are released back to
+/// the semaphore.
+#[must_use = "if unused the permits will immediately be released"]
+pub struct SemaphoreGuard<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl<'a> SemaphoreGuard<'a> {
+    /// Returns a reference to the original `Semaphore` object.
+    #[inline]
+    pub fn semaphore(&self) -> &'a Semaphore {
+        self.semaphore
+    }
+
+    /// Returns the number of permits held by this guard.
+    #[inline]
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Consumes the guard without releasing its permits back to the semaphore.
+    ///
+    /// The permits can later be returned with `Semaphore::release`.
+    #[inline]
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+
+    /// Releases the permits using a fair release protocol.
+    ///
+    /// By default, a release only wakes up waiting threads and lets them race
+    /// with other threads for the permits. This method instead hands the
+    /// permits directly to the waiting threads which can be satisfied by them,
+    /// so that the current thread can't immediately re-acquire them.
+    #[inline]
+    pub fn release_fair(self) {
+        self.semaphore.release_internal(self.permits, true);
+        core::mem::forget(self);
+    }
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        self.semaphore.release_internal(self.permits, false);
+    }
+}
+
+impl<'a> fmt::Debug for SemaphoreGuard<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SemaphoreGuard")
+            .field("permits", &self.permits)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Semaphore;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn smoke() {
+        let s = Semaphore::new(2);
+        let a = s.acquire(1);
+        let b = s.acquire(1);
+        assert_eq!(s.available_permits(), 0);
+        assert!(s.try_acquire(1).is_none());
+        drop(a);
+        assert_eq!(s.available_permits(), 1);
+        drop(b);
+        assert_eq!(s.available_permits(), 2);
+        assert_eq!(s.acquire(2).permits(), 2);
+        assert_eq!(s.acquire(0).permits(), 0);
+    }
+
+    #[test]
+    fn new_clamps_permits() {
+        let s = Semaphore::new(!0);
+        assert_eq!(s.available_permits(), !0 >> 1);
+        assert_eq!(Semaphore::new(5).available_permits(), 5);
+    }
+
+    #[test]
+    fn release_and_forget() {
+        let s = Semaphore::new(0);
+        assert!(s.try_acquire(1).is_none());
+        s.release(3);
+        assert_eq!(s.available_permits(), 3);
+        s.acquire(2).forget();
+        assert_eq!(s.available_permits(), 1);
+    }
+
+    #[test]
+    fn try_acquire_for() {
+        let s = Semaphore::new(1);
+        let _g = s.acquire(1);
+        let start = Instant::now();
+        assert!(s.try_acquire_for(1, Duration::from_millis(50)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(s
+            .try_acquire_until(1, Instant::now() + Duration::from_millis(10))
+            .is_none());
+        assert_eq!(s.available_permits(), 0);
+    }
+
+    #[test]
+    fn acquire_blocks_until_release() {
+        let s = Arc::new(Semaphore::new(0));
+        let s2 = s.clone();
+        let (tx, rx) = channel();
+        let t = thread::spawn(move || {
+            let g = s2.acquire(3);
+            tx.send(()).unwrap();
+            g.forget();
+        });
+        s.release(2);
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        s.release(1);
+        rx.recv().unwrap();
+        t.join().unwrap();
+        assert_eq!(s.available_permits(), 0);
+    }
+
+    #[test]
+    fn timed_out_waiter_unblocks_queue() {
+        let s = Arc::new(Semaphore::new(1));
+        let big = {
+            let s = s.clone();
+            thread::spawn(move || s.try_acquire_for(2, Duration::from_millis(100)).is_some())
+        };
+        thread::sleep(Duration::from_millis(20));
+        let small = {
+            let s = s.clone();
+            thread::spawn(move || s.acquire(1).forget())
+        };
+        assert!(!big.join().unwrap());
+        small.join().unwrap();
+        assert_eq!(s.available_permits(), 0);
+    }
+
+    #[test]
+    fn limits_concurrency() {
+        const N: usize = 8;
+        const PERMITS: usize = 3;
+
+        let s = Arc::new(Semaphore::new(PERMITS));
+        let active = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..N)
+            .map(|_| {
+                let (s, active) = (s.clone(), active.clone());
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let g = s.acquire(1);
+                        let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        assert!(now <= PERMITS);
+                        thread::yield_now();
+                        active.fetch_sub(1, Ordering::SeqCst);
+                        if now % 2 == 0 {
+                            g.release_fair();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(s.available_permits(), PERMITS);
+    }
+
+    #[test]
+    fn test_semaphore_debug() {
+        let s = Semaphore::new(4);
+        assert_eq!(format!("{:?}", s), "Semaphore { available_permits: 4 }");
+        let g = s.acquire(3);
+        assert_eq!(format!("{:?}", g), "SemaphoreGuard { permits: 3 }");
+    }
+}
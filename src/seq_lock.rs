@@ -0,0 +1,346 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::raw_mutex::RawMutex;
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{self, AtomicUsize, Ordering},
+};
+use lock_api::{GuardNoSend, RawMutex as RawMutexT, RawMutexFair};
+use parking_lot_core::SpinWait;
+use std::thread;
+
+/// A sequence lock, for small data which is read much more often than it is
+/// written.
+///
+/// Writers are serialized by a `RawMutex`, so a contended writer is parked
+/// like with a `Mutex`. Readers don't take any lock and never write to the
+/// shared memory: they copy the data and retry if a writer modified it in the
+/// meantime, which is detected with a sequence number incremented before and
+/// after each write. Readers therefore never block writers, but they may have
+/// to retry many times if writes are very frequent.
+///
+/// The data has to be `Copy`, since readers may observe a torn value which is
+/// then discarded. For the same reason, it should be small: a reader copies it
+/// entirely on every attempt.
+///
+/// # Differences from a `RwLock`
+///
+/// - Reading doesn't modify the lock, so readers on different cores don't
+///   contend with each other on the same cache line.
+/// - Readers can't starve writers.
+/// - `read` returns a copy of the data instead of a guard.
+///
+/// # Examples
+///
+/// ```
+/// use parking_lot::SeqLock;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let lock = Arc::new(SeqLock::new((0u32, 0u32)));
+/// let lock2 = lock.clone();
+///
+/// thread::spawn(move || {
+///     let mut data = lock2.write();
+///     data.0 += 1;
+///     data.1 += 1;
+/// });
+///
+/// // Both fields are always observed together.
+/// let (a, b) = lock.read();
+/// assert_eq!(a, b);
+/// ```
+pub struct SeqLock<T> {
+    seq: AtomicUsize,
+    lock: RawMutex,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: Readers only ever get copies of the data, and writers are
+// serialized by `lock`.
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T> SeqLock<T> {
+    /// Creates a new sequence lock in an unlocked state ready for use.
+    #[inline]
+    pub const fn new(val: T) -> SeqLock<T> {
+        SeqLock {
+            seq: AtomicUsize::new(0),
+            lock: RawMutex::INIT,
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    /// Consumes this lock, returning the underlying data.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the `SeqLock` mutably, no actual locking needs
+    /// to take place---the mutable borrow statically guarantees no locks exist.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<T: Copy> SeqLock<T> {
+    /// Returns a copy of the data.
+    ///
+    /// This doesn't block, but retries until the data could be copied without
+    /// a writer modifying it concurrently. While a writer holds the lock, the
+    /// current thread spins and then yields until it is released.
+    #[inline]
+    pub fn read(&self) -> T {
+        let mut spinwait = SpinWait::new();
+        loop {
+            let seq1 = self.seq.load(Ordering::Acquire);
+
+            // A write is in progress, wait for it to finish
+            if seq1 & 1 != 0 {
+                if !spinwait.spin() {
+                    thread::yield_now();
+                }
+                continue;
+            }
+
+            // SAFETY: The copy may race with a writer, in which case the
+            // sequence number has changed and the value is discarded without
+            // being used. `T: Copy` guarantees that a torn value has no drop
+            // glue to run.
+            let value = unsafe { ptr::read_volatile(self.data.get()) };
+
+            // Make sure the data is read before checking the sequence number
+            // again.
+            atomic::fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq1 {
+                return value;
+            }
+        }
+    }
+
+    /// Acquires the write lock, blocking the current thread until it is able
+    /// to do so.
+    ///
+    /// Returns an RAII guard which allows modifying the data. Readers retry
+    /// until the guard is dropped.
+    #[inline]
+    pub fn write(&self) -> SeqLockWriteGuard<'_, T> {
+        self.lock.lock();
+        // SAFETY: The lock is held.
+        unsafe { self.write_guard() }
+    }
+
+    /// Attempts to acquire the write lock.
+    ///
+    /// If the lock could not be acquired at this time, then `None` is
+    /// returned. Otherwise, an RAII guard is returned. The lock will be
+    /// unlocked when the guard is dropped.
+    ///
+    /// This function does not block.
+    #[inline]
+    pub fn try_write(&self) -> Option<SeqLockWriteGuard<'_, T>> {
+        if self.lock.try_lock() {
+            // SAFETY: The lock is held.
+            Some(unsafe { self.write_guard() })
+        } else {
+            None
+        }
+    }
+
+    /// Starts a write by making the sequence number odd.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held.
+    #[inline]
+    unsafe fn write_guard(&self) -> SeqLockWriteGuard<'_, T> {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+
+        // Make sure readers observe the odd sequence number before any
+        // modification of the data.
+        atomic::fence(Ordering::Release);
+        SeqLockWriteGuard {
+            seq_lock: self,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Copy + Default> Default for SeqLock<T> {
+    #[inline]
+    fn default() -> SeqLock<T> {
+        SeqLock::new(Default::default())
+    }
+}
+
+impl<T: Copy> From<T> for SeqLock<T> {
+    #[inline]
+    fn from(t: T) -> SeqLock<T> {
+        SeqLock::new(t)
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for SeqLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeqLock")
+            .field("data", &self.read())
+            .finish()
+    }
+}
+
+/// An RAII guard giving write access to the data of a `SeqLock`. When this
+/// structure is dropped (falls out of scope), the lock will be unlocked and
+/// readers see the modified data.
+#[must_use = "if unused the SeqLock will immediately unlock"]
+pub struct SeqLockWriteGuard<'a, T: Copy> {
+    seq_lock: &'a SeqLock<T>,
+    marker: PhantomData<(&'a mut T, GuardNoSend)>,
+}
+
+unsafe impl<'a, T: Copy + Sync> Sync for SeqLockWriteGuard<'a, T> {}
+
+impl<'a, T: Copy> SeqLockWriteGuard<'a, T> {
+    /// Returns a reference to the original `SeqLock` object.
+    #[inline]
+    pub fn seq_lock(s: &Self) -> &'a SeqLock<T> {
+        s.seq_lock
+    }
+
+    /// Unlocks the `SeqLock` using a fair unlock protocol.
+    ///
+    /// By default, the write lock is unfair and allows the current thread to
+    /// re-lock it before another writer has the chance to acquire it. This
+    /// method hands the lock directly to the next waiting writer instead, like
+    /// `MutexGuard::unlock_fair`.
+    #[inline]
+    pub fn unlock_fair(s: Self) {
+        s.end_write();
+        s.seq_lock.lock.unlock_fair();
+        core::mem::forget(s);
+    }
+
+    /// Ends the write by making the sequence number even again.
+    #[inline]
+    fn end_write(&self) {
+        let seq = self.seq_lock.seq.load(Ordering::Relaxed);
+        self.seq_lock
+            .seq
+            .store(seq.wrapping_add(1), Ordering::Release);
+    }
+}
+
+impl<'a, T: Copy> Deref for SeqLockWriteGuard<'a, T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.seq_lock.data.get() }
+    }
+}
+
+impl<'a, T: Copy> DerefMut for SeqLockWriteGuard<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.seq_lock.data.get() }
+    }
+}
+
+impl<'a, T: Copy> Drop for SeqLockWriteGuard<'a, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.end_write();
+        self.seq_lock.lock.unlock();
+    }
+}
+
+impl<'a, T: Copy + fmt::Debug> fmt::Debug for SeqLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: Copy + fmt::Display> fmt::Display for SeqLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SeqLock;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn smoke() {
+        let lock = SeqLock::new(1);
+        assert_eq!(lock.read(), 1);
+        *lock.write() += 1;
+        assert_eq!(lock.read(), 2);
+
+        let guard = lock.write();
+        assert!(lock.try_write().is_none());
+        drop(guard);
+        *lock.try_write().unwrap() = 3;
+        assert_eq!(lock.into_inner(), 3);
+    }
+
+    #[test]
+    fn no_torn_reads() {
+        const N: u64 = 10000;
+        let lock = Arc::new(SeqLock::new([0u64; 4]));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (lock, done) = (lock.clone(), done.clone());
+                thread::spawn(move || {
+                    let mut last = 0;
+                    while !done.load(Ordering::Relaxed) {
+                        let data = lock.read();
+                        assert!(data.iter().all(|&x| x == data[0]));
+                        assert!(data[0] >= last);
+                        last = data[0];
+                    }
+                })
+            })
+            .collect();
+
+        let writers: Vec<_> = (0..2)
+            .map(|_| {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    for _ in 0..N {
+                        let mut data = lock.write();
+                        for x in data.iter_mut() {
+                            *x += 1;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(lock.read(), [2 * N; 4]);
+    }
+}
@@ -0,0 +1,88 @@
+//! \[Experimental\] Stall detection
+//!
+//! This feature is optional and can be enabled via the `stall_detection`
+//! feature flag.
+//!
+//! The deadlock detector only reports threads which wait for each other in a
+//! cycle, and ignores waits with a timeout. This instead reports every thread
+//! which has been blocked on the same `Mutex`, `RwLock`, `Condvar` or other
+//! primitive for longer than a threshold, which helps finding slow critical
+//! sections and lock convoys.
+//!
+//! Threads are reported with the key they are parked on, which is the address
+//! of the raw lock or `Condvar` they wait for. Exclusive `RwLock` waiters
+//! blocked by remaining readers are parked on that address plus one.
+//!
+//! # Example
+//!
+//! ```
+//! #[cfg(feature = "stall_detection")]
+//! { // only for #[cfg]
+//! use std::thread;
+//! use std::time::Duration;
+//! use parking_lot::stall;
+//!
+//! // Create a background thread which reports threads blocked for more than
+//! // a second, checking every 10s
+//! thread::spawn(move || loop {
+//!     thread::sleep(Duration::from_secs(10));
+//!     for thread in stall::check_stalls(Duration::from_secs(1)) {
+//!         eprintln!("{}", thread);
+//!     }
+//! });
+//! } // only for #[cfg]
+//! ```
+
+pub use parking_lot_core::stall::{check_stalls, set_capture_backtraces, StalledThread};
+
+#[cfg(test)]
+mod tests {
+    use super::{check_stalls, StalledThread};
+    use crate::Mutex;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    fn stalls_on(key: usize, threshold: Duration) -> Vec<StalledThread> {
+        check_stalls(threshold)
+            .into_iter()
+            .filter(|t| t.key() == key)
+            .collect()
+    }
+
+    #[test]
+    fn test_mutex_stall() {
+        let mutex = Arc::new(Mutex::new(()));
+        let key = unsafe { mutex.raw() } as *const _ as usize;
+        let barrier = Arc::new(Barrier::new(3));
+
+        let guard = mutex.lock();
+        let threads: Vec<_> = (0..2)
+            .map(|i| {
+                let (mutex, barrier) = (mutex.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    if i == 0 {
+                        drop(mutex.lock());
+                    } else {
+                        assert!(mutex.try_lock_for(Duration::from_secs(10)).is_some());
+                    }
+                })
+            })
+            .collect();
+        barrier.wait();
+        thread::sleep(Duration::from_millis(100));
+
+        let stalled = stalls_on(key, Duration::from_millis(50));
+        assert_eq!(stalled.len(), 2);
+        assert_eq!(stalled.iter().filter(|t| t.is_timed()).count(), 1);
+        assert!(stalled[0].to_string().contains("parked on"));
+        assert!(stalls_on(key, Duration::from_secs(10)).is_empty());
+
+        drop(guard);
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert!(stalls_on(key, Duration::from_secs(0)).is_empty());
+    }
+}
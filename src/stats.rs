@@ -0,0 +1,460 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Lock contention statistics
+//!
+//! This feature is optional and can be enabled via the `lock_stats` feature
+//! flag. When it is disabled, none of the instrumentation is compiled in.
+//!
+//! Statistics are collected for every `Mutex`, `RwLock` and `Condvar` and are
+//! keyed by the address of the underlying `RawMutex`, `RawRwLock` or
+//! `Condvar`. The address of a raw lock can be obtained with `Mutex::raw` or
+//! `RwLock::raw`. A lock which is dropped keeps its entry, which is shared with
+//! any lock that is later created at the same address. At most 4096 locks are
+//! tracked: beyond that, the statistics of the least acquired lock are
+//! discarded to make room for a new one.
+//!
+//! Uncontended acquisitions only update statistics local to the current
+//! thread, which are merged into the global statistics regularly, whenever the
+//! thread waits for a lock, and when it exits. Only the slow paths touch
+//! shared state, so the instrumentation doesn't add contention between
+//! threads which use unrelated locks.
+//!
+//! # Example
+//!
+//! ```
+//! #[cfg(feature = "lock_stats")]
+//! { // only for #[cfg]
+//! use parking_lot::{stats, Mutex};
+//!
+//! let mutex = Mutex::new(0);
+//! *mutex.lock() += 1;
+//!
+//! let address = unsafe { mutex.raw() } as *const _ as usize;
+//! for lock in stats::snapshot() {
+//!     if lock.address == address {
+//!         println!("{:#?}", lock);
+//!     }
+//! }
+//! } // only for #[cfg]
+//! ```
+
+#[cfg(feature = "lock_stats")]
+use std::time::Instant;
+
+#[cfg(feature = "lock_stats")]
+pub use self::stats_impl::{reset, snapshot, LockStats};
+
+/// Contention recorded by a thread while it waits for a lock in a slow path.
+pub(crate) struct Contention {
+    #[cfg(feature = "lock_stats")]
+    start: Instant,
+    #[cfg(feature = "lock_stats")]
+    spins: u64,
+    #[cfg(feature = "lock_stats")]
+    parks: u64,
+}
+
+impl Contention {
+    #[inline]
+    pub(crate) fn new() -> Contention {
+        Contention {
+            #[cfg(feature = "lock_stats")]
+            start: Instant::now(),
+            #[cfg(feature = "lock_stats")]
+            spins: 0,
+            #[cfg(feature = "lock_stats")]
+            parks: 0,
+        }
+    }
+
+    /// Records a spin iteration.
+    #[inline]
+    pub(crate) fn spin(&mut self) {
+        #[cfg(feature = "lock_stats")]
+        {
+            self.spins += 1;
+        }
+    }
+
+    /// Records that the thread was parked.
+    #[inline]
+    pub(crate) fn park(&mut self) {
+        #[cfg(feature = "lock_stats")]
+        {
+            self.parks += 1;
+        }
+    }
+
+    /// Adds the contention to the statistics of the lock at `_key`.
+    /// `_acquired` is false if the wait timed out.
+    #[inline]
+    pub(crate) fn finish(self, _key: usize, _acquired: bool) {
+        #[cfg(feature = "lock_stats")]
+        stats_impl::contended(_key, self, _acquired);
+    }
+}
+
+/// Records an acquisition of the lock at `_key`. Hold times are only measured
+/// for exclusive acquisitions.
+#[inline]
+pub(crate) fn acquire(_key: usize, _exclusive: bool) {
+    #[cfg(feature = "lock_stats")]
+    stats_impl::acquire(_key, _exclusive);
+}
+
+/// Records the release of an exclusive lock at `_key`. Must be called before
+/// the lock is released.
+#[inline]
+pub(crate) fn release(_key: usize) {
+    #[cfg(feature = "lock_stats")]
+    stats_impl::release(_key);
+}
+
+/// Records that the lock at `_key` was handed off directly to a parked thread.
+#[inline]
+pub(crate) fn fair_handoff(_key: usize) {
+    #[cfg(feature = "lock_stats")]
+    stats_impl::fair_handoff(_key);
+}
+
+#[cfg(feature = "lock_stats")]
+mod stats_impl {
+    use super::Contention;
+    use crate::Lazy;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+    use std::time::{Duration, Instant};
+
+    /// Maximum number of locks with statistics. Beyond this, the statistics of
+    /// the least acquired lock are discarded to make room for a new one.
+    const MAX_LOCKS: usize = 4096;
+
+    /// Number of uncontended acquisitions and releases after which a thread
+    /// merges its statistics into the global table.
+    const FLUSH_INTERVAL: usize = 256;
+
+    /// Maximum number of exclusive acquisitions whose hold time is being
+    /// measured by a thread. This only matters for locks which are released
+    /// by another thread, since they are never removed otherwise.
+    const MAX_HELD: usize = 64;
+
+    /// Statistics collected for a single lock.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub struct LockStats {
+        /// Address of the raw lock or `Condvar` these statistics belong to.
+        pub address: usize,
+
+        /// Number of times the lock was acquired, in any mode.
+        pub acquisitions: u64,
+
+        /// Number of acquisitions which had to wait in a slow path.
+        pub contended_acquisitions: u64,
+
+        /// Number of spin iterations performed while waiting for the lock.
+        pub spins: u64,
+
+        /// Number of times a thread was parked while waiting for the lock, or
+        /// while waiting on the `Condvar`.
+        pub parks: u64,
+
+        /// Total time spent in slow paths waiting for the lock, including
+        /// waits which timed out, or waiting on the `Condvar`.
+        pub wait_time: Duration,
+
+        /// Total time the lock was held in exclusive mode.
+        pub hold_time: Duration,
+
+        /// Number of times the lock was handed off directly to a parked
+        /// thread by a fair unlock.
+        pub fair_handoffs: u64,
+    }
+
+    // This uses the standard library mutex since our own locks are the ones
+    // being instrumented.
+    static TABLE: Lazy<Mutex<HashMap<usize, LockStats>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+    // Incremented by `reset` so that threads discard the statistics they
+    // haven't merged yet.
+    static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+    fn table() -> MutexGuard<'static, HashMap<usize, LockStats>> {
+        TABLE.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn entry(table: &mut HashMap<usize, LockStats>, key: usize) -> &mut LockStats {
+        if table.len() >= MAX_LOCKS && !table.contains_key(&key) {
+            let coldest = table
+                .values()
+                .min_by_key(|stats| stats.acquisitions)
+                .map(|stats| stats.address);
+            if let Some(coldest) = coldest {
+                table.remove(&coldest);
+            }
+        }
+        table.entry(key).or_insert_with(|| LockStats {
+            address: key,
+            ..LockStats::default()
+        })
+    }
+
+    #[derive(Default)]
+    struct Pending {
+        acquisitions: u64,
+        hold_time: Duration,
+    }
+
+    // Statistics of the fast paths, which are recorded by each thread without
+    // touching any shared state and merged into the global table from time to
+    // time.
+    struct Local {
+        epoch: usize,
+        pending: HashMap<usize, Pending>,
+        held: Vec<(usize, Instant)>,
+        events: usize,
+    }
+
+    impl Local {
+        fn new() -> Local {
+            Local {
+                epoch: EPOCH.load(Ordering::Relaxed),
+                pending: HashMap::new(),
+                held: Vec::new(),
+                events: 0,
+            }
+        }
+
+        fn tick(&mut self) {
+            self.events += 1;
+            if self.events >= FLUSH_INTERVAL {
+                self.flush(&mut table());
+            }
+        }
+
+        fn flush(&mut self, table: &mut HashMap<usize, LockStats>) {
+            let epoch = EPOCH.load(Ordering::Relaxed);
+            if self.epoch == epoch {
+                for (key, pending) in self.pending.drain() {
+                    let stats = entry(table, key);
+                    stats.acquisitions += pending.acquisitions;
+                    stats.hold_time += pending.hold_time;
+                }
+            } else {
+                self.pending.clear();
+                self.epoch = epoch;
+            }
+            self.events = 0;
+        }
+    }
+
+    impl Drop for Local {
+        fn drop(&mut self) {
+            self.flush(&mut table());
+        }
+    }
+
+    thread_local!(static LOCAL: RefCell<Local> = RefCell::new(Local::new()));
+
+    fn with_local(f: impl FnOnce(&mut Local)) {
+        // Locks used while the thread-local data is being destroyed, or by
+        // the allocator while it is being updated, aren't counted.
+        let _ = LOCAL.try_with(|local| {
+            if let Ok(mut local) = local.try_borrow_mut() {
+                f(&mut local);
+            }
+        });
+    }
+
+    pub(super) fn acquire(key: usize, exclusive: bool) {
+        with_local(|local| {
+            local.pending.entry(key).or_default().acquisitions += 1;
+            if exclusive {
+                if local.held.len() >= MAX_HELD {
+                    local.held.remove(0);
+                }
+                local.held.push((key, Instant::now()));
+            }
+            local.tick();
+        });
+    }
+
+    pub(super) fn release(key: usize) {
+        with_local(|local| {
+            // A lock may be released by another thread than the one which
+            // acquired it, in which case its hold time isn't measured.
+            if let Some(p) = local.held.iter().rposition(|&(k, _)| k == key) {
+                let (_, start) = local.held.swap_remove(p);
+                local.pending.entry(key).or_default().hold_time += start.elapsed();
+                local.tick();
+            }
+        });
+    }
+
+    pub(super) fn fair_handoff(key: usize) {
+        entry(&mut table(), key).fair_handoffs += 1;
+    }
+
+    pub(super) fn contended(key: usize, contention: Contention, acquired: bool) {
+        let wait_time = contention.start.elapsed();
+        let mut table = table();
+        with_local(|local| local.flush(&mut table));
+        let stats = entry(&mut table, key);
+        if acquired {
+            stats.contended_acquisitions += 1;
+        }
+        stats.spins += contention.spins;
+        stats.parks += contention.parks;
+        stats.wait_time += wait_time;
+    }
+
+    /// Returns the statistics collected so far for every lock which has been
+    /// used, sorted by address.
+    ///
+    /// This includes all the statistics recorded by the calling thread, but
+    /// the uncontended acquisitions and the hold times recorded by other
+    /// threads only appear once these threads have merged them, which they
+    /// do regularly and when they exit.
+    pub fn snapshot() -> Vec<LockStats> {
+        let mut table = table();
+        with_local(|local| local.flush(&mut table));
+        let mut stats: Vec<_> = table.values().cloned().collect();
+        stats.sort_by_key(|stats| stats.address);
+        stats
+    }
+
+    /// Discards all the statistics collected so far, including the ones
+    /// which other threads haven't merged yet.
+    pub fn reset() {
+        let mut table = table();
+        EPOCH.fetch_add(1, Ordering::Relaxed);
+        table.clear();
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "lock_stats")]
+mod tests {
+    use super::{snapshot, LockStats};
+    use crate::{Condvar, Mutex, MutexGuard, RwLock};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    // A previously dropped lock may have left statistics at the same address,
+    // so tests compare against the statistics at the start of the test.
+    struct Delta {
+        address: usize,
+        start: LockStats,
+    }
+
+    impl Delta {
+        fn new(address: usize) -> Delta {
+            Delta {
+                address,
+                start: Delta::current(address),
+            }
+        }
+
+        fn current(address: usize) -> LockStats {
+            snapshot()
+                .into_iter()
+                .find(|stats| stats.address == address)
+                .unwrap_or_default()
+        }
+
+        fn get(&self) -> LockStats {
+            let now = Delta::current(self.address);
+            LockStats {
+                address: self.address,
+                acquisitions: now.acquisitions - self.start.acquisitions,
+                contended_acquisitions: now.contended_acquisitions
+                    - self.start.contended_acquisitions,
+                spins: now.spins - self.start.spins,
+                parks: now.parks - self.start.parks,
+                wait_time: now.wait_time - self.start.wait_time,
+                hold_time: now.hold_time - self.start.hold_time,
+                fair_handoffs: now.fair_handoffs - self.start.fair_handoffs,
+            }
+        }
+    }
+
+    #[test]
+    fn test_mutex_stats() {
+        let mutex = Arc::new(Mutex::new(()));
+        let delta = Delta::new(unsafe { mutex.raw() } as *const _ as usize);
+
+        drop(mutex.lock());
+        let stats = delta.get();
+        assert_eq!(stats.acquisitions, 1);
+        assert_eq!(stats.contended_acquisitions, 0);
+
+        let guard = mutex.lock();
+        let barrier = Arc::new(Barrier::new(2));
+        let (mutex2, barrier2) = (mutex.clone(), barrier.clone());
+        let t = thread::spawn(move || {
+            barrier2.wait();
+            drop(mutex2.lock());
+        });
+        barrier.wait();
+        thread::sleep(Duration::from_millis(50));
+        MutexGuard::unlock_fair(guard);
+        t.join().unwrap();
+
+        let stats = delta.get();
+        assert_eq!(stats.acquisitions, 3);
+        assert_eq!(stats.contended_acquisitions, 1);
+        assert!(stats.parks >= 1);
+        assert_eq!(stats.fair_handoffs, 1);
+        assert!(stats.wait_time >= Duration::from_millis(40));
+        assert!(stats.hold_time >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_rwlock_stats() {
+        let lock = Arc::new(RwLock::new(()));
+        let delta = Delta::new(unsafe { lock.raw() } as *const _ as usize);
+
+        drop(lock.read());
+        drop(lock.upgradable_read());
+        let guard = lock.write();
+        let lock2 = lock.clone();
+        let t = thread::spawn(move || drop(lock2.read()));
+        thread::sleep(Duration::from_millis(50));
+        drop(guard);
+        t.join().unwrap();
+
+        let stats = delta.get();
+        assert_eq!(stats.acquisitions, 4);
+        assert_eq!(stats.contended_acquisitions, 1);
+        assert!(stats.hold_time >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_table_is_bounded() {
+        let mutexes: Vec<_> = (0..5000).map(|_| Mutex::new(())).collect();
+        for mutex in &mutexes {
+            drop(mutex.lock());
+        }
+        assert!(snapshot().len() <= 4096);
+    }
+
+    #[test]
+    fn test_condvar_stats() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+        let delta = Delta::new(&condvar as *const _ as usize);
+
+        let mut guard = mutex.lock();
+        condvar.wait_for(&mut guard, Duration::from_millis(10));
+        let stats = delta.get();
+        assert_eq!(stats.parks, 1);
+        assert_eq!(stats.contended_acquisitions, 0);
+        assert!(stats.wait_time >= Duration::from_millis(10));
+    }
+}
@@ -36,3 +36,50 @@ unsafe fn unreachable() -> ! {
 pub fn to_deadline(timeout: Duration) -> Option<Instant> {
     Instant::now().checked_add(timeout)
 }
+
+// Minimal executor used to test the asynchronous locking methods.
+#[cfg(test)]
+pub fn block_on<F: core::future::Future>(future: F) -> F::Output {
+    use core::task::{Context, Poll};
+    use std::thread;
+
+    let waker = thread_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+// Returns a waker which unparks the current thread.
+#[cfg(test)]
+pub fn thread_waker() -> core::task::Waker {
+    use core::task::{RawWaker, RawWakerVTable, Waker};
+    use std::sync::Arc;
+    use std::thread::{self, Thread};
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+    fn raw_waker(thread: Arc<Thread>) -> RawWaker {
+        RawWaker::new(Arc::into_raw(thread) as *const (), &VTABLE)
+    }
+    unsafe fn clone(ptr: *const ()) -> RawWaker {
+        let thread = Arc::from_raw(ptr as *const Thread);
+        let waker = raw_waker(thread.clone());
+        core::mem::forget(thread);
+        waker
+    }
+    unsafe fn wake(ptr: *const ()) {
+        Arc::from_raw(ptr as *const Thread).unpark();
+    }
+    unsafe fn wake_by_ref(ptr: *const ()) {
+        (*(ptr as *const Thread)).unpark();
+    }
+    unsafe fn drop(ptr: *const ()) {
+        core::mem::drop(Arc::from_raw(ptr as *const Thread));
+    }
+
+    unsafe { Waker::from_raw(raw_waker(Arc::new(thread::current()))) }
+}
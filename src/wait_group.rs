@@ -0,0 +1,205 @@
+// Copyright 2019 Amanieu d'Antras
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::condvar::WaitTimeoutResult;
+use crate::latch::Latch;
+use core::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A wait group, which waits for a set of tasks to finish.
+///
+/// The counter of the group is incremented with `add` for every task started
+/// and decremented with `done` when it finishes. `wait` blocks until the
+/// counter is zero. Unlike a `Latch`, the counter can go up again after it
+/// reached zero, so the same group can be reused.
+///
+/// Threads blocked in `wait` when the counter reaches zero are all woken up,
+/// even if `add` is called again before they get to run. A `wait` which starts
+/// concurrently with such an `add` may return either for the previous set of
+/// tasks or only once the new ones have finished.
+///
+/// A `WaitGroup` is a handle to a shared counter: cloning it returns another
+/// handle to the same counter, which can be moved to the thread running a
+/// task.
+///
+/// # Examples
+///
+/// ```
+/// use parking_lot::WaitGroup;
+/// use std::thread;
+///
+/// let wg = WaitGroup::new();
+/// for _ in 0..10 {
+///     wg.add(1);
+///     let wg = wg.clone();
+///     thread::spawn(move || {
+///         // Do some work...
+///         wg.done();
+///     });
+/// }
+///
+/// // Wait for all the tasks to finish.
+/// wg.wait();
+/// ```
+#[derive(Clone, Default)]
+pub struct WaitGroup {
+    latch: Arc<Latch>,
+}
+
+impl WaitGroup {
+    /// Creates a new wait group with a counter of zero.
+    #[inline]
+    pub fn new() -> WaitGroup {
+        WaitGroup::default()
+    }
+
+    /// Returns the current value of the counter.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.latch.count()
+    }
+
+    /// Adds `n` to the counter.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the counter would exceed
+    /// `usize::max_value() >> 1`.
+    #[inline]
+    pub fn add(&self, n: usize) {
+        if !self.latch.try_count_up(n) {
+            panic!("WaitGroup counter overflow");
+        }
+    }
+
+    /// Decrements the counter, waking up all waiting threads if it reaches
+    /// zero.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the counter is already zero.
+    #[inline]
+    pub fn done(&self) {
+        if !self.latch.try_count_down() {
+            panic!("WaitGroup::done called more times than WaitGroup::add");
+        }
+    }
+
+    /// Blocks the current thread until the counter is zero.
+    #[inline]
+    pub fn wait(&self) {
+        self.latch.wait();
+    }
+
+    /// Blocks the current thread until the counter is zero, timing out after
+    /// the specified time instant.
+    ///
+    /// The returned `WaitTimeoutResult` value indicates if the timeout is
+    /// known to have elapsed.
+    #[inline]
+    pub fn wait_until(&self, timeout: Instant) -> WaitTimeoutResult {
+        self.latch.wait_until(timeout)
+    }
+
+    /// Blocks the current thread until the counter is zero, timing out after
+    /// a specified duration.
+    ///
+    /// The returned `WaitTimeoutResult` value indicates if the timeout is
+    /// known to have elapsed.
+    #[inline]
+    pub fn wait_for(&self, timeout: Duration) -> WaitTimeoutResult {
+        self.latch.wait_for(timeout)
+    }
+}
+
+impl fmt::Debug for WaitGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WaitGroup")
+            .field("count", &self.count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::WaitGroup;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn reuse() {
+        let wg = WaitGroup::new();
+        let counter = Arc::new(AtomicUsize::new(0));
+        for round in 1..3 {
+            for _ in 0..4 {
+                wg.add(1);
+                let (wg, counter) = (wg.clone(), counter.clone());
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(5));
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    wg.done();
+                });
+            }
+            wg.wait();
+            assert_eq!(counter.load(Ordering::Relaxed), round * 4);
+            assert_eq!(wg.count(), 0);
+        }
+    }
+
+    #[test]
+    fn add_before_waiter_wakes() {
+        let wg = WaitGroup::new();
+        wg.add(1);
+        let (tx, rx) = channel();
+        let wg2 = wg.clone();
+        thread::spawn(move || {
+            wg2.wait();
+            tx.send(()).unwrap();
+        });
+        thread::sleep(Duration::from_millis(10));
+
+        // The parked waiter must return even though the counter is no longer
+        // zero by the time it runs.
+        wg.done();
+        wg.add(1);
+        rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(wg.count(), 1);
+    }
+
+    #[test]
+    fn wait_for_timeout() {
+        let wg = WaitGroup::new();
+        assert!(!wg.wait_for(Duration::from_millis(10)).timed_out());
+        wg.add(1);
+        assert!(wg.wait_for(Duration::from_millis(10)).timed_out());
+        wg.done();
+        assert!(!wg.wait_for(Duration::from_millis(10)).timed_out());
+    }
+
+    #[test]
+    #[should_panic(expected = "WaitGroup::done called more times than WaitGroup::add")]
+    fn done_without_add() {
+        WaitGroup::new().done();
+    }
+
+    #[test]
+    fn add_overflow() {
+        let wg = WaitGroup::new();
+        wg.add(1);
+        let wg2 = wg.clone();
+        let result = thread::spawn(move || wg2.add(!0 >> 1)).join();
+        assert!(result.is_err());
+        // The counter was left unchanged.
+        assert_eq!(wg.count(), 1);
+        wg.done();
+        wg.wait();
+    }
+}